@@ -0,0 +1,6 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // protoc is only needed when the gRPC frontend is enabled
+    #[cfg(feature = "grpc")]
+    tonic_build::compile_protos("proto/iris.proto")?;
+    Ok(())
+}
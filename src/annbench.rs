@@ -0,0 +1,124 @@
+use std::path::Path;
+
+use anyhow::{ensure, Context};
+use hdf5::{types::VarLenUnicode, File, H5Type};
+use hnsw_hamming::{IrisCode, IrisCodeArray};
+use ndarray::{Array1, Array2, ArrayView1};
+
+/// A binary dataset in the ann-benchmarks HDF5 layout: `train` and `test` as bool matrices of one
+/// point per row, and the ids of the true nearest neighbours of every test point in `neighbors`.
+pub struct AnnDataset {
+    pub name: String,
+    /// Bits per point, at most the code width.
+    pub dimension: usize,
+    pub train: Vec<IrisCode>,
+    pub test: Vec<IrisCode>,
+    /// Nearest first, one row per test point.
+    pub neighbors: Array2<i64>,
+}
+
+/// Results of one run, stored the way ann-benchmarks' `store_results` does.
+pub struct AnnRun {
+    pub algo: String,
+    /// Name of the run, telling the parameters apart.
+    pub name: String,
+    pub build_time_secs: f64,
+    pub index_size_kb: f64,
+    /// Seconds per test point.
+    pub times: Vec<f64>,
+    /// Ids found for each test point, padded with -1.
+    pub neighbors: Array2<i64>,
+    /// Hamming distances in bits, padded with infinity.
+    pub distances: Array2<f32>,
+}
+
+pub fn read_dataset(path: &Path) -> anyhow::Result<AnnDataset> {
+    let file = File::open(path).with_context(|| format!("opening {}", path.display()))?;
+    let distance: VarLenUnicode = file.attr("distance")?.read_scalar()?;
+    ensure!(
+        distance.as_str() == "hamming",
+        "{} is a {} dataset, only hamming is supported",
+        path.display(),
+        distance.as_str()
+    );
+    let train = file.dataset("train")?.read_2d::<bool>()?;
+    let test = file.dataset("test")?.read_2d::<bool>()?;
+    let neighbors = file.dataset("neighbors")?.read_2d::<i64>()?;
+    let dimension = train.ncols();
+    ensure!(
+        test.ncols() == dimension,
+        "train has {dimension} bits per point, but test {}",
+        test.ncols()
+    );
+    ensure!(
+        dimension <= <IrisCode>::IRIS_CODE_SIZE,
+        "{dimension}-bit points don't fit into {}-bit codes, see the `full-codes` feature",
+        <IrisCode>::IRIS_CODE_SIZE
+    );
+    ensure!(
+        neighbors.nrows() == test.nrows(),
+        "neighbors has {} rows for {} test points",
+        neighbors.nrows(),
+        test.nrows()
+    );
+    Ok(AnnDataset {
+        name: path
+            .file_stem()
+            .map_or_else(String::new, |stem| stem.to_string_lossy().into_owned()),
+        dimension,
+        train: train.rows().into_iter().map(to_code).collect(),
+        test: test.rows().into_iter().map(to_code).collect(),
+        neighbors,
+    })
+}
+
+// bits past the point are masked out, so the fractional distance is the hamming distance over
+// the dimension and orders neighbours the same way
+fn to_code(point: ArrayView1<bool>) -> IrisCode {
+    let mut code = IrisCode::new(IrisCodeArray::ZERO, IrisCodeArray::ZERO);
+    for (i, &bit) in point.iter().enumerate() {
+        code.code.set_bit(i, bit);
+    }
+    code.mask.set_range(0, point.len(), true);
+    code
+}
+
+fn write_attr<T: H5Type>(file: &File, name: &str, value: &T) -> anyhow::Result<()> {
+    file.new_attr::<T>()
+        .shape(())
+        .create(name)?
+        .write_scalar(value)?;
+    Ok(())
+}
+
+fn write_str_attr(file: &File, name: &str, value: &str) -> anyhow::Result<()> {
+    write_attr(file, name, &value.parse::<VarLenUnicode>()?)
+}
+
+pub fn write_results(path: &Path, dataset: &AnnDataset, run: &AnnRun) -> anyhow::Result<()> {
+    let file = File::create(path).with_context(|| format!("creating {}", path.display()))?;
+    let count = run.neighbors.ncols();
+    let best_search_time = run.times.iter().sum::<f64>() / run.times.len().max(1) as f64;
+    write_str_attr(&file, "algo", &run.algo)?;
+    write_str_attr(&file, "name", &run.name)?;
+    write_str_attr(&file, "dataset", &dataset.name)?;
+    write_str_attr(&file, "distance", "hamming")?;
+    write_attr(&file, "build_time", &run.build_time_secs)?;
+    write_attr(&file, "index_size", &run.index_size_kb)?;
+    write_attr(&file, "best_search_time", &best_search_time)?;
+    write_attr(&file, "candidates", &(count as f64))?;
+    write_attr(&file, "count", &(count as i64))?;
+    write_attr(&file, "run_count", &1i64)?;
+    write_attr(&file, "batch_mode", &false)?;
+    write_attr(&file, "expect_extra", &false)?;
+    file.new_dataset_builder()
+        .with_data(&Array1::from_vec(run.times.clone()))
+        .create("times")?;
+    file.new_dataset_builder()
+        .with_data(&run.neighbors)
+        .create("neighbors")?;
+    file.new_dataset_builder()
+        .with_data(&run.distances)
+        .create("distances")?;
+    Ok(())
+}
@@ -0,0 +1,103 @@
+use std::sync::Arc;
+
+use tokio::task::{spawn_blocking, JoinError};
+
+use crate::{
+    error::IrisError,
+    index::{Id, IrisHnsw, SearchHit, UniquenessResult},
+    iris::IrisCode,
+};
+
+/// Async facade over a shared [`IrisHnsw`]. Graph operations are CPU-bound, so they run on
+/// tokio's blocking pool instead of the async workers; an outer error means the operation panicked,
+/// an inner one that the index rejected it.
+#[derive(Clone)]
+pub struct AsyncIrisHnsw {
+    index: Arc<IrisHnsw>,
+}
+
+impl AsyncIrisHnsw {
+    pub fn new(index: IrisHnsw) -> Self {
+        Self {
+            index: Arc::new(index),
+        }
+    }
+
+    /// The wrapped index, for cheap calls that don't need to leave the async worker.
+    pub fn inner(&self) -> &Arc<IrisHnsw> {
+        &self.index
+    }
+
+    async fn run<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&IrisHnsw) -> T + Send + 'static,
+    ) -> Result<T, JoinError> {
+        let index = self.index.clone();
+        spawn_blocking(move || f(&index)).await
+    }
+
+    pub async fn insert(&self, code: IrisCode, id: Id) -> Result<Result<(), IrisError>, JoinError> {
+        self.run(move |index| index.insert(&code, id)).await
+    }
+
+    pub async fn insert_batch(
+        &self,
+        items: Vec<(IrisCode, Id)>,
+    ) -> Result<Result<(), IrisError>, JoinError> {
+        self.run(move |index| index.insert_batch(&items, |_| {}))
+            .await
+    }
+
+    pub async fn insert_unique(
+        &self,
+        code: IrisCode,
+        id: Id,
+    ) -> Result<Result<UniquenessResult, IrisError>, JoinError> {
+        self.run(move |index| index.insert_unique(&code, id)).await
+    }
+
+    pub async fn update(
+        &self,
+        id: Id,
+        code: IrisCode,
+    ) -> Result<Result<bool, IrisError>, JoinError> {
+        self.run(move |index| index.update(id, &code)).await
+    }
+
+    pub async fn search(&self, query: IrisCode, k: usize) -> Result<Vec<SearchHit>, JoinError> {
+        self.run(move |index| index.search(&query, k)).await
+    }
+
+    pub async fn search_with_ef(
+        &self,
+        query: IrisCode,
+        k: usize,
+        ef: usize,
+    ) -> Result<Vec<SearchHit>, JoinError> {
+        self.run(move |index| index.search_with_ef(&query, k, ef))
+            .await
+    }
+
+    pub async fn search_batch(
+        &self,
+        queries: Vec<IrisCode>,
+        k: usize,
+        ef: usize,
+    ) -> Result<Vec<Vec<SearchHit>>, JoinError> {
+        self.run(move |index| index.search_batch(&queries, k, ef))
+            .await
+    }
+
+    pub async fn search_within(
+        &self,
+        query: IrisCode,
+        threshold: f64,
+    ) -> Result<Vec<SearchHit>, JoinError> {
+        self.run(move |index| index.search_within(&query, threshold))
+            .await
+    }
+
+    pub async fn is_unique(&self, code: IrisCode) -> Result<UniquenessResult, JoinError> {
+        self.run(move |index| index.is_unique(&code)).await
+    }
+}
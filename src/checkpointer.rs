@@ -0,0 +1,75 @@
+use std::{
+    path::PathBuf,
+    sync::{
+        mpsc::{self, RecvTimeoutError, Sender},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use hnsw_hamming::IrisHnsw;
+use tracing::{info, warn};
+
+use crate::store;
+
+// how often the thread checks whether a checkpoint is due
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Thread writing checkpoints of an index under construction, each once `every` inserts were
+/// made or `interval` passed since the last one, whichever comes first. The build doesn't have
+/// to stop between checkpoints, but inserts stall for as long as each dump of the graph takes,
+/// see [`IrisHnsw::dump`], so checkpoints of large indexes are best spaced well apart. Stops
+/// when dropped.
+pub struct Checkpointer {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Checkpointer {
+    pub fn start(
+        index: Arc<IrisHnsw>,
+        index_dir: PathBuf,
+        seed: u64,
+        n_points: usize,
+        every: Option<usize>,
+        interval: Option<Duration>,
+    ) -> Self {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let thread = thread::spawn(move || {
+            let mut last_len = index.len();
+            let mut last_time = Instant::now();
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(POLL_INTERVAL) {
+                let inserted = index.len();
+                let new = inserted.saturating_sub(last_len);
+                let due = every.is_some_and(|every| new >= every)
+                    || interval.is_some_and(|interval| last_time.elapsed() >= interval);
+                if !due || new == 0 {
+                    continue;
+                }
+                match store::write_checkpoint(&index_dir, &index, seed, n_points, inserted) {
+                    Ok(()) => info!(inserted, "wrote checkpoint"),
+                    Err(err) => warn!("writing checkpoint failed: {err:#}"),
+                }
+                last_len = inserted;
+                last_time = Instant::now();
+            }
+        });
+        Self {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for Checkpointer {
+    fn drop(&mut self) {
+        // a checkpoint being written is finished first
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                warn!("checkpoint thread panicked");
+            }
+        }
+    }
+}
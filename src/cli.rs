@@ -0,0 +1,562 @@
+use std::{
+    net::SocketAddr,
+    num::{NonZeroU64, NonZeroUsize},
+    path::{Path, PathBuf},
+};
+
+use clap::{ArgAction, Args, Parser, Subcommand, ValueEnum};
+use tracing::Level;
+
+use hnsw_hamming::MATCH_THRESHOLD_RATIO;
+
+use crate::config::Config;
+
+#[derive(Parser, Debug)]
+#[command(version, about = "HNSW over masked Hamming distance on iris codes")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+
+    /// Increase log verbosity (-v debug, -vv trace)
+    #[arg(short, long, action = ArgAction::Count, global = true, conflicts_with = "quiet")]
+    pub verbose: u8,
+
+    /// Decrease log verbosity (-q warnings only, -qq errors only)
+    #[arg(short, long, action = ArgAction::Count, global = true)]
+    pub quiet: u8,
+}
+
+impl Cli {
+    pub fn log_level(&self) -> Level {
+        match self.verbose as i16 - self.quiet as i16 {
+            ..=-2 => Level::ERROR,
+            -1 => Level::WARN,
+            0 => Level::INFO,
+            1 => Level::DEBUG,
+            _ => Level::TRACE,
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Construct an index over random codes and persist it
+    Build {
+        #[command(flatten)]
+        experiment: ExperimentArgs,
+        /// Directory the index is written to, or an object store URL such as `s3://bucket/prefix`
+        #[arg(long)]
+        index_dir: PathBuf,
+        /// Dump the partially built index to the index directory after this many inserts, in the
+        /// background while inserting goes on
+        #[arg(long)]
+        checkpoint_every: Option<NonZeroUsize>,
+        /// Like `--checkpoint-every`, after this many minutes
+        #[arg(long)]
+        checkpoint_minutes: Option<NonZeroU64>,
+        /// Continue from the last checkpoint in the index directory
+        #[arg(long)]
+        resume: bool,
+        /// Dataset file written by `generate` to build from instead of generating codes. It is
+        /// memory-mapped, and the index reads exact templates from it instead of storing them a
+        /// second time; the graph still keeps a copy of every template
+        #[arg(long, requires = "queries")]
+        dataset: Option<PathBuf>,
+        /// Record file with the query set for `--dataset`
+        #[arg(long, requires = "dataset")]
+        queries: Option<PathBuf>,
+    },
+    /// Write a generated dataset to a file, for reuse across runs and machines
+    Generate {
+        #[command(flatten)]
+        experiment: ExperimentArgs,
+        /// Dataset file the codes are written to
+        #[arg(long)]
+        output: PathBuf,
+        /// Record file the query set is written to
+        #[arg(long)]
+        queries: Option<PathBuf>,
+    },
+    /// Build an index directory from templates written by other tools
+    Import {
+        #[command(subcommand)]
+        format: ImportFormat,
+    },
+    /// Write the templates of an index directory for other tools
+    Export {
+        #[command(subcommand)]
+        format: ExportFormat,
+    },
+    /// Enroll templates from stdin into a live index, printing a JSON decision per record
+    Ingest {
+        #[command(flatten)]
+        experiment: ExperimentArgs,
+        /// Encoding of the templates on stdin
+        #[arg(long, value_enum, default_value_t = IngestFormat::Ndjson)]
+        format: IngestFormat,
+        /// Directory to start from if it holds an index, written back once stdin is exhausted
+        #[arg(long)]
+        index_dir: Option<PathBuf>,
+    },
+    /// Enroll templates consumed from a Kafka topic, producing a decision for each to another
+    #[cfg(feature = "kafka")]
+    Kafka(KafkaArgs),
+    /// Rewrite an index directory or record files written by an older version in current formats
+    Migrate {
+        /// Directory written by `build` or `import`, migrated with its query set
+        #[arg(long, required_unless_present = "dataset")]
+        index_dir: Option<PathBuf>,
+        /// Record file to migrate, may be repeated
+        #[arg(long)]
+        dataset: Vec<PathBuf>,
+    },
+    /// Query a previously built index
+    Search {
+        #[command(flatten)]
+        experiment: ExperimentArgs,
+        /// Directory or object store URL written by `build`
+        #[arg(long)]
+        index_dir: PathBuf,
+        /// File written by `groundtruth` for this index to report recall@k and threshold recall
+        /// against
+        #[arg(long)]
+        groundtruth: Option<PathBuf>,
+    },
+    /// Brute-force the exact neighbours of an index's query set, for computing recall later
+    Groundtruth {
+        #[command(flatten)]
+        experiment: ExperimentArgs,
+        /// Directory or object store URL written by `build`
+        #[arg(long)]
+        index_dir: PathBuf,
+        /// Nearest neighbours kept per query, defaults to knbn
+        #[arg(long)]
+        k: Option<usize>,
+        /// Entries closer than this fractional Hamming distance are kept beyond the `k` nearest
+        #[arg(long, default_value_t = MATCH_THRESHOLD_RATIO)]
+        threshold: f64,
+        /// JSON file the ground truth is written to
+        #[arg(long)]
+        output: PathBuf,
+    },
+    /// Serve an in-memory index over HTTP
+    Serve {
+        #[command(flatten)]
+        experiment: ExperimentArgs,
+        /// Directory written by `build` to start from, otherwise the index starts empty
+        #[arg(long)]
+        index_dir: Option<PathBuf>,
+        /// Log inserts to this file before applying them, and replay it on startup
+        #[arg(long)]
+        wal: Option<PathBuf>,
+        /// Address the HTTP server listens on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        listen: SocketAddr,
+    },
+    /// Serve an in-memory index over gRPC
+    #[cfg(feature = "grpc")]
+    ServeGrpc {
+        #[command(flatten)]
+        experiment: ExperimentArgs,
+        /// Directory written by `build` to start from, otherwise the index starts empty
+        #[arg(long)]
+        index_dir: Option<PathBuf>,
+        /// Log inserts to this file before applying them, and replay it on startup
+        #[arg(long)]
+        wal: Option<PathBuf>,
+        /// Address the gRPC server listens on
+        #[arg(long, default_value = "127.0.0.1:50051")]
+        listen: SocketAddr,
+    },
+    /// Run on an ann-benchmarks HDF5 dataset and write results in its format
+    #[cfg(feature = "hdf5")]
+    AnnBenchmark {
+        #[command(flatten)]
+        experiment: ExperimentArgs,
+        /// Dataset with `train`, `test` and `neighbors`, bit points under hamming distance
+        #[arg(long)]
+        dataset: PathBuf,
+        /// HDF5 file the results are written to
+        #[arg(long)]
+        results: PathBuf,
+        /// Neighbours returned per test point
+        #[arg(long, default_value_t = 10)]
+        count: usize,
+    },
+    /// Report all matching pairs in a record file, using the index for candidate generation
+    Dedup {
+        #[command(flatten)]
+        experiment: ExperimentArgs,
+        /// Record file in the format of the query set written by `build`, local or at a URL
+        #[arg(long)]
+        input: PathBuf,
+        /// Neighbours retrieved per record before exact verification
+        #[arg(long, default_value_t = 10)]
+        candidates: usize,
+        /// CSV file the matching pairs are written to, defaults to stdout
+        #[arg(long)]
+        pairs_csv: Option<PathBuf>,
+    },
+    /// Exact FMR/FNMR accuracy report over a labeled dataset
+    Eval {
+        #[command(flatten)]
+        experiment: ExperimentArgs,
+        /// Record file in the format of the query set written by `build`, generated if absent
+        #[arg(long, requires = "labels")]
+        input: Option<PathBuf>,
+        /// CSV file with `id,identity` columns for the records in `--input`
+        #[arg(long, requires = "input")]
+        labels: Option<PathBuf>,
+        /// Identities in the generated dataset
+        #[arg(long, default_value_t = 1000)]
+        identities: usize,
+        /// Noisy samples per identity in the generated dataset
+        #[arg(long, default_value_t = 5)]
+        samples_per_identity: usize,
+        /// Decision threshold on the fractional Hamming distance
+        #[arg(long, default_value_t = MATCH_THRESHOLD_RATIO)]
+        threshold: f64,
+        /// JSON file the full report is written to
+        #[arg(long)]
+        report: Option<PathBuf>,
+        #[command(flatten)]
+        aging: AgingArgs,
+    },
+    /// Build and query an index in one run
+    Bench {
+        #[command(flatten)]
+        experiment: ExperimentArgs,
+        /// File written by `groundtruth` for an index built from the same seeded dataset, to
+        /// report recall@k and threshold recall against
+        #[arg(long)]
+        groundtruth: Option<PathBuf>,
+    },
+    /// Search for the cheapest M and ef_search that reach a target recall
+    Autotune {
+        #[command(flatten)]
+        experiment: ExperimentArgs,
+        /// Recall to reach, in percent
+        #[arg(long, default_value_t = 99.0)]
+        target_recall: f32,
+        /// Comma-separated M candidates, each requiring one build
+        #[arg(long, value_delimiter = ',', default_values_t = [16, 32, 64, 128])]
+        tune_max_nb_connection: Vec<usize>,
+        /// Upper bound for the ef_search binary search
+        #[arg(long, default_value_t = 1024)]
+        max_ef_search: usize,
+    },
+    /// Build and query indexes over a grid of HNSW parameters on one shared dataset
+    Sweep {
+        #[command(flatten)]
+        experiment: ExperimentArgs,
+        /// Comma-separated M values
+        #[arg(long, value_delimiter = ',')]
+        sweep_max_nb_connection: Vec<usize>,
+        /// Comma-separated ef_construction values
+        #[arg(long, value_delimiter = ',')]
+        sweep_ef_construction: Vec<usize>,
+        /// Comma-separated ef_search values
+        #[arg(long, value_delimiter = ',')]
+        sweep_ef_search: Vec<usize>,
+        /// CSV file with one row per parameter combination
+        #[arg(long)]
+        sweep_csv: PathBuf,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum IngestFormat {
+    /// One `{id, code_b64, mask_b64, meta}` object per line, as for `import ndjson`
+    Ndjson,
+    /// The dataset format written by `generate`
+    Binary,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ImportFormat {
+    /// One `{id, code_b64, mask_b64, meta}` object per line, base64 encoded as by open-iris
+    Ndjson(ImportArgs),
+    /// Columns `id`, `code` and `mask`, the templates hex encoded, other columns become metadata
+    Csv(ImportArgs),
+    /// Columns `id`, `code` and `mask`, the templates as raw bytes, and `meta` as JSON
+    #[cfg(feature = "parquet")]
+    Parquet(ImportArgs),
+    /// Rows of a query against an enrollment database, fetched in batches
+    #[cfg(feature = "postgres")]
+    Postgres(PostgresArgs),
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ExportFormat {
+    /// One `{id, code_b64, mask_b64, meta}` object per line, base64 encoded as by open-iris
+    Ndjson(ExportArgs),
+    /// Columns `id`, `code` and `mask`, the templates as raw bytes, and `meta` as JSON
+    #[cfg(feature = "parquet")]
+    Parquet(ExportArgs),
+    /// `ids`, `codes` and `masks` as bit-packed numpy arrays, in one `.npz` archive
+    Npz(NumpyArgs),
+    /// The arrays of `npz` as separate `.npy` files in the directory given by --output
+    Npy(NumpyArgs),
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct ImportArgs {
+    #[command(flatten)]
+    pub experiment: ExperimentArgs,
+    /// File or object store URL the templates are read from
+    #[arg(long)]
+    pub input: PathBuf,
+    /// Directory or object store URL the index is written to
+    #[arg(long)]
+    pub index_dir: PathBuf,
+}
+
+#[cfg(feature = "postgres")]
+#[derive(Args, Debug, Clone)]
+pub struct PostgresArgs {
+    #[command(flatten)]
+    pub experiment: ExperimentArgs,
+    /// Connection string, e.g. `host=localhost user=iris dbname=enrollment`
+    #[arg(long)]
+    pub url: String,
+    /// Query returning bigint `id` and bytea `code` and `mask`, other text columns become metadata
+    #[arg(long, default_value = "SELECT id, code, mask FROM templates")]
+    pub query: String,
+    /// Rows fetched per round trip
+    #[arg(long, default_value_t = 10_000, value_parser = clap::value_parser!(i32).range(1..))]
+    pub batch_size: i32,
+    /// Directory the index is written to
+    #[arg(long)]
+    pub index_dir: PathBuf,
+}
+
+#[cfg(feature = "kafka")]
+#[derive(Args, Debug, Clone)]
+pub struct KafkaArgs {
+    #[command(flatten)]
+    pub experiment: ExperimentArgs,
+    /// Comma-separated bootstrap servers
+    #[arg(long, default_value = "localhost:9092")]
+    pub brokers: String,
+    /// Topic of templates, one `{id, code_b64, mask_b64, meta}` object per message
+    #[arg(long)]
+    pub input_topic: String,
+    /// Topic the decisions are produced to as JSON, keyed by record id
+    #[arg(long)]
+    pub output_topic: String,
+    /// Consumer group, whose committed offsets consumption resumes from
+    #[arg(long, default_value = "hnsw-hamming")]
+    pub group_id: String,
+    /// Stop once no message arrived for this many seconds, otherwise consume until killed
+    #[arg(long)]
+    pub idle_timeout_secs: Option<u64>,
+    /// Directory to start from if it holds an index, written back once consumption stops
+    #[arg(long, requires = "idle_timeout_secs")]
+    pub index_dir: Option<PathBuf>,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct ExportArgs {
+    /// Directory or object store URL written by `build` or `import`
+    #[arg(long)]
+    pub index_dir: PathBuf,
+    /// File the templates are written to, defaults to stdout
+    #[arg(long)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct NumpyArgs {
+    #[command(flatten)]
+    pub export: ExportArgs,
+    /// Also write the matrix of pairwise fractional distances, quadratic in the index size
+    #[arg(long)]
+    pub distances: bool,
+}
+
+/// Longitudinal part of `eval`: every sample is aged step by step and compared against fresh
+/// captures of the aged eye.
+#[derive(Args, Debug, Clone)]
+pub struct AgingArgs {
+    /// Aging steps to simulate, none by default
+    #[arg(long, default_value_t = 0)]
+    pub aging_steps: usize,
+    /// Probability of a code bit flipping for good per step
+    #[arg(long, default_value_t = 0.01)]
+    pub aging_flip_rate: f64,
+    /// Probability of a mask bit being lost per step
+    #[arg(long, default_value_t = 0.005)]
+    pub aging_erosion_rate: f64,
+}
+
+/// Flags override values loaded from `--config`, which in turn override the built-in defaults.
+#[derive(Args, Debug, Clone)]
+pub struct ExperimentArgs {
+    /// TOML experiment definition
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+
+    /// Number of random iris codes inserted into the index [default: 100000]
+    #[arg(long)]
+    pub n_points: Option<usize>,
+
+    /// Number of indexed codes that are perturbed and used as queries [default: 10000]
+    #[arg(long)]
+    pub random_queries: Option<usize>,
+
+    /// Seed for the dataset, query sampling and query noise, so the dataset and queries are
+    /// reproducible. The graph isn't: inserts stay parallel and HNSW levels are drawn at random
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Share of mask bits removed from each generated code [default: 0.1]
+    #[arg(long)]
+    pub mask_dropout: Option<f64>,
+
+    /// Probability of flipping each code and mask bit when generating queries [default: 0.05]
+    #[arg(long)]
+    pub flip_probability: Option<f64>,
+
+    /// Probability of flipping each mask bit, defaults to flip_probability
+    #[arg(long)]
+    pub mask_flip_probability: Option<f64>,
+
+    /// Flip bits in adjacent pairs, like the duplicated last dimension of open-iris codes
+    #[arg(long)]
+    pub pairwise_flips: bool,
+
+    /// Maximum number of connections per node (M) [default: 128]
+    #[arg(long)]
+    pub max_nb_connection: Option<usize>,
+
+    /// Beam width used during construction [default: 128]
+    #[arg(long)]
+    pub ef_construction: Option<usize>,
+
+    /// Beam width used during search, defaults to ef_construction
+    #[arg(long)]
+    pub ef_search: Option<usize>,
+
+    /// Number of neighbours returned per query [default: 1]
+    #[arg(long)]
+    pub knbn: Option<usize>,
+
+    /// Number of HNSW layers, defaults to min(16, ln(n_points))
+    #[arg(long)]
+    pub nb_layer: Option<usize>,
+
+    /// Retrieve knbn times this many candidates and re-rank them by exact distance
+    #[arg(long)]
+    pub rerank_oversample: Option<usize>,
+
+    /// Memory-map a persisted index instead of reading it, so it serves queries sooner
+    #[arg(long)]
+    pub mmap: bool,
+
+    /// Run-length encode the masks of templates held by the index and of datasets written by
+    /// `generate`, trading exact-distance speed for memory
+    #[arg(long)]
+    pub compress_masks: bool,
+
+    /// Threads used for building, defaults to all cores
+    #[arg(long)]
+    pub build_threads: Option<usize>,
+
+    /// Threads used for searching, defaults to all cores
+    #[arg(long)]
+    pub search_threads: Option<usize>,
+
+    /// JSON file the run summary is written to
+    #[arg(long)]
+    pub output: Option<PathBuf>,
+
+    /// CSV file with one row per query (id, result, distance, evals, latency)
+    #[arg(long)]
+    pub queries_csv: Option<PathBuf>,
+
+    /// Parquet file with the rows of --queries-csv, needs the `parquet` feature
+    #[arg(long)]
+    pub queries_parquet: Option<PathBuf>,
+
+    /// Don't draw progress bars, e.g. when running under nohup or CI
+    #[arg(long)]
+    pub no_progress: bool,
+}
+
+impl ExperimentArgs {
+    pub fn to_config(&self) -> anyhow::Result<Config> {
+        self.to_config_with_base(None)
+    }
+
+    /// Like `to_config`, but falls back to `base` instead of the built-in defaults when no
+    /// `--config` is given.
+    pub fn to_config_with_base(&self, base: Option<&Path>) -> anyhow::Result<Config> {
+        let mut config = match self.config.as_deref().or(base) {
+            Some(path) => Config::load(path)?,
+            None => Config::default(),
+        };
+        self.apply(&mut config);
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn apply(&self, config: &mut Config) {
+        fn set<T: Clone>(dst: &mut T, src: &Option<T>) {
+            if let Some(value) = src {
+                *dst = value.clone();
+            }
+        }
+        set(&mut config.dataset.n_points, &self.n_points);
+        set(&mut config.dataset.random_queries, &self.random_queries);
+        set(&mut config.dataset.mask_dropout, &self.mask_dropout);
+        set(&mut config.noise.flip_probability, &self.flip_probability);
+        set(&mut config.hnsw.max_nb_connection, &self.max_nb_connection);
+        set(&mut config.hnsw.ef_construction, &self.ef_construction);
+        set(&mut config.hnsw.knbn, &self.knbn);
+        if self.seed.is_some() {
+            config.dataset.seed = self.seed;
+        }
+        if self.mask_flip_probability.is_some() {
+            config.noise.mask_flip_probability = self.mask_flip_probability;
+        }
+        if self.pairwise_flips {
+            config.noise.pairwise = true;
+        }
+        if self.ef_search.is_some() {
+            config.hnsw.ef_search = self.ef_search;
+        }
+        if self.nb_layer.is_some() {
+            config.hnsw.nb_layer = self.nb_layer;
+        }
+        if self.rerank_oversample.is_some() {
+            config.hnsw.rerank_oversample = self.rerank_oversample;
+        }
+        if self.mmap {
+            config.hnsw.mmap = true;
+        }
+        if self.compress_masks {
+            config.hnsw.compress_masks = true;
+        }
+        if self.build_threads.is_some() {
+            config.threads.build = self.build_threads;
+        }
+        if self.search_threads.is_some() {
+            config.threads.search = self.search_threads;
+        }
+        if self.output.is_some() {
+            config.output.results.clone_from(&self.output);
+        }
+        if self.queries_csv.is_some() {
+            config.output.queries_csv.clone_from(&self.queries_csv);
+        }
+        if self.queries_parquet.is_some() {
+            config
+                .output
+                .queries_parquet
+                .clone_from(&self.queries_parquet);
+        }
+        if self.no_progress {
+            config.output.progress = false;
+        }
+    }
+}
@@ -0,0 +1,180 @@
+use std::{fs::File, path::Path, sync::Arc};
+
+use anyhow::{bail, ensure, Context};
+use arrow_array::{
+    cast::AsArray,
+    types::{Int64Type, UInt64Type},
+    Array, ArrayRef, FixedSizeBinaryArray, Float32Array, RecordBatch, StringArray, UInt64Array,
+};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use hnsw_hamming::{IrisCode, IrisCodeArray, Metadata};
+use parquet::{
+    arrow::{arrow_reader::ParquetRecordBatchReaderBuilder, ArrowWriter},
+    basic::{Compression, ZstdLevel},
+    file::properties::WriterProperties,
+};
+
+use crate::{pipeline::QueryResult, store::TemplateRecord};
+
+// rows per record batch and thus per row group written
+const BATCH_ROWS: usize = 64 * 1024;
+
+fn writer(path: &Path, schema: SchemaRef) -> anyhow::Result<ArrowWriter<File>> {
+    let file = File::create(path).with_context(|| format!("creating {}", path.display()))?;
+    let props = WriterProperties::builder()
+        .set_compression(Compression::ZSTD(ZstdLevel::default()))
+        .build();
+    Ok(ArrowWriter::try_new(file, schema, Some(props))?)
+}
+
+fn template_schema() -> SchemaRef {
+    let bytes = <IrisCodeArray>::IRIS_CODE_SIZE_BYTES as i32;
+    Arc::new(Schema::new(vec![
+        Field::new("id", DataType::UInt64, false),
+        Field::new("code", DataType::FixedSizeBinary(bytes), false),
+        Field::new("mask", DataType::FixedSizeBinary(bytes), false),
+        Field::new("meta", DataType::Utf8, true),
+    ]))
+}
+
+/// Writes `id`, `code` and `mask` columns, the templates as raw bytes in the layout of
+/// `IrisCodeArray::as_raw_slice`, and the metadata as a JSON object, null where there is none.
+pub fn write_templates(path: &Path, records: &[TemplateRecord]) -> anyhow::Result<()> {
+    let schema = template_schema();
+    let mut writer = writer(path, schema.clone())?;
+    for chunk in records.chunks(BATCH_ROWS) {
+        let ids = UInt64Array::from_iter_values(chunk.iter().map(|record| record.id as u64));
+        let codes = FixedSizeBinaryArray::try_from_iter(
+            chunk.iter().map(|record| record.code.code.as_raw_slice()),
+        )?;
+        let masks = FixedSizeBinaryArray::try_from_iter(
+            chunk.iter().map(|record| record.code.mask.as_raw_slice()),
+        )?;
+        let meta = chunk
+            .iter()
+            .map(|record| {
+                (!record.meta.is_empty())
+                    .then(|| serde_json::to_string(&record.meta))
+                    .transpose()
+            })
+            .collect::<Result<StringArray, _>>()?;
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(ids),
+            Arc::new(codes),
+            Arc::new(masks),
+            Arc::new(meta),
+        ];
+        writer.write(&RecordBatch::try_new(schema.clone(), columns)?)?;
+    }
+    writer.close()?;
+    Ok(())
+}
+
+/// Reads the columns written by [`write_templates`]. Ids may also be signed, as pandas writes
+/// them, templates may also be variable-length binary, and `meta` may be left out.
+pub fn read_templates(path: &Path) -> anyhow::Result<Vec<TemplateRecord>> {
+    let file = File::open(path).with_context(|| format!("opening {}", path.display()))?;
+    let reader = ParquetRecordBatchReaderBuilder::try_new(file)?.build()?;
+    let mut records = vec![];
+    for batch in reader {
+        let batch = batch?;
+        let ids = ids(&batch)?;
+        let codes = binary_values(&batch, "code")?;
+        let masks = binary_values(&batch, "mask")?;
+        let meta = match batch.column_by_name("meta") {
+            Some(column) => {
+                let column = column
+                    .as_string_opt::<i32>()
+                    .context("column \"meta\" isn't a string column")?;
+                column.iter().collect()
+            }
+            None => vec![None; batch.num_rows()],
+        };
+        for (i, meta) in meta.into_iter().enumerate() {
+            let row = records.len();
+            let record = IrisCode::from_bytes(codes[i], masks[i])
+                .map_err(anyhow::Error::from)
+                .and_then(|code| {
+                    let meta: Metadata = match meta {
+                        Some(json) => serde_json::from_str(json)?,
+                        None => Metadata::new(),
+                    };
+                    Ok(TemplateRecord {
+                        id: ids[i],
+                        code,
+                        meta,
+                    })
+                })
+                .with_context(|| format!("row {row}"))?;
+            records.push(record);
+        }
+    }
+    Ok(records)
+}
+
+fn ids(batch: &RecordBatch) -> anyhow::Result<Vec<usize>> {
+    let column = batch
+        .column_by_name("id")
+        .context("missing column \"id\"")?;
+    ensure!(column.null_count() == 0, "null id");
+    if let Some(ids) = column.as_primitive_opt::<UInt64Type>() {
+        return Ok(ids.values().iter().map(|&id| id as usize).collect());
+    }
+    let ids = column
+        .as_primitive_opt::<Int64Type>()
+        .context("column \"id\" isn't an integer column")?;
+    ids.values()
+        .iter()
+        .map(|&id| usize::try_from(id).with_context(|| format!("negative id {id}")))
+        .collect()
+}
+
+fn binary_values<'a>(batch: &'a RecordBatch, name: &str) -> anyhow::Result<Vec<&'a [u8]>> {
+    let column = batch
+        .column_by_name(name)
+        .with_context(|| format!("missing column {name:?}"))?;
+    let values: Option<Vec<_>> = if let Some(array) = column.as_fixed_size_binary_opt() {
+        array.iter().collect()
+    } else if let Some(array) = column.as_binary_opt::<i32>() {
+        array.iter().collect()
+    } else {
+        bail!("column {name:?} isn't a binary column");
+    };
+    values.with_context(|| format!("null in column {name:?}"))
+}
+
+/// One row per query, like the CSV written for `queries_csv`.
+pub fn write_query_results(path: &Path, results: &[QueryResult]) -> anyhow::Result<()> {
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("query_idx", DataType::UInt64, false),
+        Field::new("returned_id", DataType::UInt64, true),
+        Field::new("distance", DataType::Float32, true),
+        Field::new("evals", DataType::UInt64, false),
+        Field::new("latency_us", DataType::UInt64, false),
+    ]));
+    let mut writer = writer(path, schema.clone())?;
+    for chunk in results.chunks(BATCH_ROWS) {
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(UInt64Array::from_iter_values(
+                chunk.iter().map(|result| result.query_idx as u64),
+            )),
+            Arc::new(UInt64Array::from_iter(
+                chunk
+                    .iter()
+                    .map(|result| result.returned_id.map(|id| id as u64)),
+            )),
+            Arc::new(Float32Array::from_iter(
+                chunk.iter().map(|result| result.distance),
+            )),
+            Arc::new(UInt64Array::from_iter_values(
+                chunk.iter().map(|result| result.evals as u64),
+            )),
+            Arc::new(UInt64Array::from_iter_values(
+                chunk.iter().map(|result| result.latency_us),
+            )),
+        ];
+        writer.write(&RecordBatch::try_new(schema.clone(), columns)?)?;
+    }
+    writer.close()?;
+    Ok(())
+}
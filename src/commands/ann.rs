@@ -0,0 +1,79 @@
+use std::{collections::HashSet, path::Path, time::Instant};
+
+use ndarray::Array2;
+use tracing::{info, info_span};
+
+use crate::{
+    annbench::{self, AnnRun},
+    cli::ExperimentArgs,
+    pipeline,
+};
+
+/// Builds an index over the train points of an ann-benchmarks dataset, answers its test queries
+/// one at a time, as the harness does, and writes the results for its evaluation.
+pub fn run(
+    experiment: &ExperimentArgs,
+    dataset_path: &Path,
+    results_path: &Path,
+    count: usize,
+) -> anyhow::Result<()> {
+    let config = experiment.to_config()?;
+    let dataset = annbench::read_dataset(dataset_path)?;
+
+    let start = Instant::now();
+    let mut index = pipeline::new_index(&config, Some(dataset.train.len()));
+    {
+        let _span = info_span!("build", n_points = dataset.train.len()).entered();
+        let bar = pipeline::progress(&config, "Insert", dataset.train.len());
+        pipeline::insert_codes(&index, &dataset.train, 0, bar.as_ref());
+        bar.finish();
+    }
+    index.set_searching_mode(true);
+    let build_time = start.elapsed();
+
+    let _span = info_span!("search", queries = dataset.test.len(), count).entered();
+    let mut neighbors = Array2::from_elem((dataset.test.len(), count), -1);
+    let mut distances = Array2::from_elem((dataset.test.len(), count), f32::INFINITY);
+    let mut times = Vec::with_capacity(dataset.test.len());
+    let mut found = 0;
+    for (i, query) in dataset.test.iter().enumerate() {
+        let start = Instant::now();
+        let hits = index.search(query, count);
+        times.push(start.elapsed().as_secs_f64());
+
+        let truth: HashSet<i64> = dataset
+            .neighbors
+            .row(i)
+            .iter()
+            .take(count)
+            .copied()
+            .collect();
+        for (j, hit) in hits.iter().enumerate() {
+            neighbors[[i, j]] = hit.id as i64;
+            distances[[i, j]] = (hit.distance * dataset.dimension as f32).round();
+            found += usize::from(truth.contains(&(hit.id as i64)));
+        }
+    }
+    let recall = found as f64 / (dataset.test.len() * count).max(1) as f64;
+    let qps = times.len() as f64 / times.iter().sum::<f64>();
+    info!(recall, qps, "answered test queries");
+
+    let stats = index.stats();
+    let hnsw = &config.hnsw;
+    let run = AnnRun {
+        algo: "hnsw-iris".to_string(),
+        name: format!(
+            "hnsw-iris(M={}, ef_construction={}, ef_search={})",
+            hnsw.max_nb_connection,
+            hnsw.ef_construction,
+            config.ef_search()
+        ),
+        build_time_secs: build_time.as_secs_f64(),
+        index_size_kb: (stats.vector_bytes + stats.edge_bytes + stats.payload_bytes) as f64
+            / 1024.0,
+        times,
+        neighbors,
+        distances,
+    };
+    annbench::write_results(results_path, &dataset, &run)
+}
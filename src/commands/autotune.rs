@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+
+use anyhow::bail;
+use tracing::{info, info_span, warn};
+
+use crate::{cli::ExperimentArgs, config::HnswConfig, pipeline};
+
+struct Candidate {
+    max_nb_connection: usize,
+    ef_search: usize,
+    recall: f32,
+    avg_evals: usize,
+}
+
+/// For every M, binary-searches the smallest ef_search that reaches `target_recall` (percent) on
+/// a shared dataset, then picks the combination with the fewest average distance evaluations.
+pub fn run(
+    experiment: &ExperimentArgs,
+    target_recall: f32,
+    max_nb_connections: &[usize],
+    max_ef_search: usize,
+) -> anyhow::Result<()> {
+    let config = experiment.to_config()?;
+    for &max_nb_connection in max_nb_connections {
+        let mut config = config.clone();
+        config.hnsw.max_nb_connection = max_nb_connection;
+        config.hnsw.ef_search = Some(max_ef_search);
+        config.validate()?;
+    }
+
+    let dataset = pipeline::generate_dataset(&config);
+    let mut best: Option<Candidate> = None;
+
+    for &max_nb_connection in max_nb_connections {
+        let mut config = config.clone();
+        config.hnsw.max_nb_connection = max_nb_connection;
+        let _span = info_span!("autotune", max_nb_connection).entered();
+        let hnsw = pipeline::build_index(&config, &dataset);
+
+        let knbn = config.hnsw.knbn;
+        let mut evaluated = HashMap::new();
+        let mut evaluate = |ef_search: usize| -> (f32, usize) {
+            *evaluated.entry(ef_search).or_insert_with(|| {
+                config.hnsw.ef_search = Some(ef_search);
+                let stats = pipeline::run_queries(&hnsw, &dataset.queries, dataset.seed, &config);
+                info!(
+                    ef_search,
+                    recall = stats.recall,
+                    avg_evals = stats.avg_evals
+                );
+                (stats.recall, stats.avg_evals)
+            })
+        };
+
+        if evaluate(max_ef_search).0 < target_recall {
+            warn!(
+                max_nb_connection,
+                max_ef_search, "target recall not reachable"
+            );
+            continue;
+        }
+        // recall grows with ef_search, so look for the smallest beam that still hits the target
+        let (mut lo, mut hi) = (knbn, max_ef_search);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            if evaluate(mid).0 >= target_recall {
+                hi = mid;
+            } else {
+                lo = mid + 1;
+            }
+        }
+
+        let (recall, avg_evals) = evaluate(hi);
+        let candidate = Candidate {
+            max_nb_connection,
+            ef_search: hi,
+            recall,
+            avg_evals,
+        };
+        if best
+            .as_ref()
+            .is_none_or(|b| candidate.avg_evals < b.avg_evals)
+        {
+            best = Some(candidate);
+        }
+    }
+
+    let Some(best) = best else {
+        bail!("no configuration reached {target_recall}% recall with ef_search <= {max_ef_search}");
+    };
+    info!(
+        max_nb_connection = best.max_nb_connection,
+        ef_search = best.ef_search,
+        recall = best.recall,
+        avg_evals = best.avg_evals,
+        "selected configuration"
+    );
+    let hnsw = HnswConfig {
+        max_nb_connection: best.max_nb_connection,
+        ef_search: Some(best.ef_search),
+        ..config.hnsw.clone()
+    };
+    // printed as a config section so it can be pasted straight into an experiment file
+    println!("[hnsw]\n{}", toml::to_string(&hnsw)?);
+    Ok(())
+}
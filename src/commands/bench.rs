@@ -0,0 +1,30 @@
+use std::{path::Path, time::Instant};
+
+use anyhow::ensure;
+
+use crate::{cli::ExperimentArgs, pipeline};
+
+/// With `groundtruth`, written for an index built from the same seeded dataset, the queries are
+/// perturbed with its seed and the results scored against it.
+pub fn run(experiment: &ExperimentArgs, groundtruth: Option<&Path>) -> anyhow::Result<()> {
+    let config = experiment.to_config()?;
+    ensure!(
+        groundtruth.is_none() || config.dataset.seed.is_some(),
+        "a ground truth only applies to a seeded dataset"
+    );
+    let truth = groundtruth
+        .map(|path| pipeline::load_groundtruth(&config, path))
+        .transpose()?;
+
+    let dataset = pipeline::generate_dataset(&config);
+    let start = Instant::now();
+    let hnsw = pipeline::build_index(&config, &dataset);
+    let build_time = start.elapsed();
+    let seed = truth.as_ref().map_or(dataset.seed, |truth| truth.seed);
+    let mut stats = pipeline::run_queries(&hnsw, &dataset.queries, seed, &config);
+    if let Some(truth) = &truth {
+        pipeline::score_against(&mut stats, truth)?;
+    }
+
+    pipeline::report(&config, &stats, Some(build_time))
+}
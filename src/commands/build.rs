@@ -0,0 +1,171 @@
+use std::{
+    num::{NonZeroU64, NonZeroUsize},
+    ops::Range,
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{ensure, Context};
+use hnsw_hamming::{dataset::MappedDataset, IrisCode, IrisHnsw};
+use tracing::{debug, info, info_span, Level};
+
+use crate::{
+    checkpointer::Checkpointer,
+    cli::ExperimentArgs,
+    pipeline, remote,
+    store::{self, CONFIG_FILE, INDEX_BASENAME, QUERIES_FILE},
+};
+
+// templates the index is built from
+enum Codes {
+    Generated(Vec<IrisCode>),
+    Mapped(Arc<MappedDataset>),
+}
+
+impl Codes {
+    fn len(&self) -> usize {
+        match self {
+            Codes::Generated(codes) => codes.len(),
+            Codes::Mapped(mapped) => mapped.len(),
+        }
+    }
+
+    // positions whose ids aren't in `index` yet, as ranges; checkpoints taken during parallel
+    // inserts leave gaps anywhere
+    fn missing(&self, index: &IrisHnsw) -> Vec<Range<usize>> {
+        let mut runs: Vec<Range<usize>> = vec![];
+        for position in 0..self.len() {
+            let id = match self {
+                Codes::Generated(_) => position,
+                Codes::Mapped(mapped) => mapped.id(position),
+            };
+            if index.contains(id) {
+                continue;
+            }
+            match runs.last_mut() {
+                Some(run) if run.end == position => run.end += 1,
+                _ => runs.push(position..position + 1),
+            }
+        }
+        runs
+    }
+}
+
+/// A remote `index_dir` is built in its local mirror and uploaded once complete. `dataset` is a
+/// dataset file and its query set to build from, instead of generating both. Checkpoints are
+/// written in the background every `checkpoint_every` inserts or `checkpoint_minutes`.
+pub fn run(
+    experiment: &ExperimentArgs,
+    target_dir: &Path,
+    dataset: Option<(&Path, &Path)>,
+    checkpoint_every: Option<NonZeroUsize>,
+    checkpoint_minutes: Option<NonZeroU64>,
+    resume: bool,
+) -> anyhow::Result<()> {
+    let index_dir = &remote::staging_dir(target_dir)?;
+    let checkpoint = if resume {
+        let checkpoint = store::find_checkpoint(index_dir)?
+            .with_context(|| format!("no checkpoint in {}", index_dir.display()))?;
+        Some(checkpoint)
+    } else {
+        None
+    };
+    let mut config = if resume {
+        experiment.to_config_with_base(Some(&index_dir.join(CONFIG_FILE)))?
+    } else {
+        experiment.to_config()?
+    };
+
+    let seed = match &checkpoint {
+        Some((_, state)) => state.seed,
+        None => pipeline::resolve_seed(&config),
+    };
+    let (codes, queries) = match dataset {
+        Some((dataset, queries)) => {
+            let dataset = remote::fetch_file(dataset)?;
+            let mapped = MappedDataset::open(&dataset)
+                .with_context(|| format!("mapping {}", dataset.display()))?;
+            ensure!(!mapped.is_empty(), "{} has no records", dataset.display());
+            let queries =
+                store::read_records(&remote::fetch_file(queries)?).context("reading query set")?;
+            config.dataset.n_points = mapped.len();
+            (Codes::Mapped(Arc::new(mapped)), queries)
+        }
+        None => {
+            let dataset = pipeline::generate_dataset_with_seed(&config, seed);
+            (Codes::Generated(dataset.codes), dataset.queries)
+        }
+    };
+    let n_points = codes.len();
+    if let Some((_, state)) = &checkpoint {
+        ensure!(
+            state.n_points == n_points,
+            "checkpoint was taken for {} points, but n_points is {n_points}",
+            state.n_points
+        );
+    } else {
+        std::fs::create_dir_all(index_dir)
+            .with_context(|| format!("creating {}", index_dir.display()))?;
+        store::write_records(&index_dir.join(QUERIES_FILE), &queries)
+            .context("writing query set")?;
+        std::fs::write(index_dir.join(CONFIG_FILE), toml::to_string(&config)?)
+            .context("writing build config")?;
+    }
+
+    let index = match &checkpoint {
+        Some((dir, state)) => {
+            let mut index = IrisHnsw::load(dir, &state.basename)
+                .with_context(|| format!("loading checkpoint from {}", dir.display()))?;
+            pipeline::configure_index(&config, &mut index);
+            info!(inserted = state.inserted, "resuming from checkpoint");
+            index
+        }
+        None => pipeline::new_index(&config, Some(n_points)),
+    };
+    let index = Arc::new(index);
+
+    let build_span = info_span!("build", n_points, seed).entered();
+    let missing = codes.missing(&index);
+    let bar = pipeline::progress(&config, "Insert", n_points);
+    bar.inc((n_points - missing.iter().map(ExactSizeIterator::len).sum::<usize>()) as u64);
+    let checkpointer = (checkpoint_every.is_some() || checkpoint_minutes.is_some()).then(|| {
+        Checkpointer::start(
+            index.clone(),
+            index_dir.clone(),
+            seed,
+            n_points,
+            checkpoint_every.map(NonZeroUsize::get),
+            checkpoint_minutes.map(|minutes| Duration::from_secs(60 * minutes.get())),
+        )
+    });
+    for run in missing {
+        match &codes {
+            Codes::Generated(codes) => {
+                pipeline::insert_codes(&index, &codes[run.clone()], run.start, bar.as_ref())
+            }
+            Codes::Mapped(mapped) => pipeline::insert_mapped(&index, mapped, run, bar.as_ref()),
+        }
+    }
+    drop(checkpointer);
+    bar.finish();
+    let mut index = Arc::into_inner(index).expect("the checkpointer is stopped");
+    index.set_searching_mode(true);
+    drop(build_span);
+    if tracing::enabled!(Level::DEBUG) {
+        debug!(stats = ?index.stats(), "built index");
+    }
+
+    let _span = info_span!("dump", index_dir = %index_dir.display()).entered();
+    index.dump(index_dir, INDEX_BASENAME)?;
+    store::remove_checkpoints(index_dir).context("removing checkpoints")?;
+    remote::publish_dir(index_dir, target_dir)?;
+
+    info!(
+        "Wrote index with {} points and {} queries to {}",
+        n_points,
+        queries.len(),
+        target_dir.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,94 @@
+use std::{fs::File, io::Write, path::Path};
+
+use anyhow::Context;
+use hnsw_hamming::MATCH_THRESHOLD_RATIO;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use serde::Serialize;
+use tracing::{info, info_span};
+
+use crate::{cli::ExperimentArgs, pipeline, remote, store};
+
+#[derive(Serialize)]
+struct MatchingPair {
+    id_a: usize,
+    id_b: usize,
+    distance: f64,
+    hamming: u32,
+    overlap: u32,
+}
+
+/// Reports every pair of records in `input` that matches under `MATCH_THRESHOLD_RATIO`. The graph
+/// only proposes `candidates` neighbours per record, every pair is re-checked exactly.
+pub fn run(
+    experiment: &ExperimentArgs,
+    input: &Path,
+    candidates: usize,
+    pairs_csv: Option<&Path>,
+) -> anyhow::Result<()> {
+    let config = experiment.to_config()?;
+    let records = store::read_records(&remote::fetch_file(input)?)
+        .with_context(|| format!("reading records from {}", input.display()))?;
+
+    let index = pipeline::new_index(&config, Some(records.len()));
+    {
+        let _span = info_span!("build", n_points = records.len()).entered();
+        let bar = pipeline::progress(&config, "Insert", records.len());
+        index
+            .insert_batch(&records, |_| bar.inc(1))
+            .context("inserting records")?;
+        bar.finish();
+    }
+
+    let _span = info_span!("dedup", candidates).entered();
+    let ef = config.ef_search().max(candidates);
+    let bar = pipeline::progress(&config, "Dedup", records.len());
+    let mut pairs: Vec<MatchingPair> = index.in_search_pool(|| {
+        records
+            .par_iter()
+            .flat_map_iter(|(code, id)| {
+                let neighbours = index.search_with_ef(code, candidates, ef);
+                bar.inc(1);
+                neighbours
+                    .into_iter()
+                    .filter(|n| n.id != *id)
+                    .filter_map(|n| {
+                        let other = index.get(n.id)?;
+                        let distance = code.get_distance_with_bound(&other, MATCH_THRESHOLD_RATIO);
+                        (distance < MATCH_THRESHOLD_RATIO).then(|| {
+                            let exact = code.get_masked_distance(&other);
+                            MatchingPair {
+                                id_a: (*id).min(n.id),
+                                id_b: (*id).max(n.id),
+                                distance: exact.ratio,
+                                hamming: exact.hamming,
+                                overlap: exact.overlap,
+                            }
+                        })
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    });
+    bar.finish();
+    // most pairs are found from both sides
+    pairs.sort_by_key(|p| (p.id_a, p.id_b));
+    pairs.dedup_by_key(|p| (p.id_a, p.id_b));
+
+    info!(
+        records = records.len(),
+        pairs = pairs.len(),
+        "dedup finished"
+    );
+    let output: Box<dyn Write> = match pairs_csv {
+        Some(path) => {
+            Box::new(File::create(path).with_context(|| format!("creating {}", path.display()))?)
+        }
+        None => Box::new(std::io::stdout()),
+    };
+    let mut writer = csv::Writer::from_writer(output);
+    for pair in &pairs {
+        writer.serialize(pair)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
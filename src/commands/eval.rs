@@ -0,0 +1,255 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::{ensure, Context};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use tracing::{info, info_span};
+
+use hnsw_hamming::{noise::AgingModel, IrisCode};
+
+use crate::{
+    cli::{AgingArgs, ExperimentArgs},
+    pipeline, remote, store,
+};
+
+// resolution of the distance histograms behind the FMR/FNMR curve
+const HISTOGRAM_BINS: usize = 1000;
+const CURVE_STEP: f64 = 0.025;
+
+#[derive(Deserialize)]
+struct Label {
+    id: usize,
+    identity: usize,
+}
+
+#[derive(Serialize)]
+struct CurvePoint {
+    threshold: f64,
+    fmr: f64,
+    fnmr: f64,
+}
+
+/// Genuine comparisons after `step` aging steps.
+#[derive(Serialize)]
+struct AgingPoint {
+    step: usize,
+    fnmr: f64,
+    mean_distance: f64,
+}
+
+#[derive(Serialize)]
+struct AccuracyReport {
+    samples: usize,
+    identities: usize,
+    threshold: f64,
+    genuine_pairs: u64,
+    impostor_pairs: u64,
+    false_matches: u64,
+    false_non_matches: u64,
+    fmr: f64,
+    fnmr: f64,
+    mean_genuine_distance: f64,
+    mean_impostor_distance: f64,
+    eer: f64,
+    curve: Vec<CurvePoint>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    aging: Vec<AgingPoint>,
+}
+
+/// Pair statistics of one comparison class (genuine or impostor).
+#[derive(Clone)]
+struct PairStats {
+    count: u64,
+    below_threshold: u64,
+    distance_sum: f64,
+    histogram: Vec<u64>,
+}
+
+impl PairStats {
+    fn new() -> Self {
+        Self {
+            count: 0,
+            below_threshold: 0,
+            distance_sum: 0.0,
+            histogram: vec![0; HISTOGRAM_BINS + 1],
+        }
+    }
+
+    fn add(&mut self, distance: f64, threshold: f64) {
+        self.count += 1;
+        self.below_threshold += u64::from(distance < threshold);
+        self.distance_sum += distance;
+        let bin = (distance.clamp(0.0, 1.0) * HISTOGRAM_BINS as f64) as usize;
+        self.histogram[bin] += 1;
+    }
+
+    fn merge(mut self, other: Self) -> Self {
+        self.count += other.count;
+        self.below_threshold += other.below_threshold;
+        self.distance_sum += other.distance_sum;
+        for (a, b) in self.histogram.iter_mut().zip(other.histogram) {
+            *a += b;
+        }
+        self
+    }
+
+    /// Fraction of pairs whose distance falls into a bin below `threshold`.
+    fn fraction_below(&self, threshold: f64) -> f64 {
+        let bins = (threshold * HISTOGRAM_BINS as f64).ceil() as usize;
+        let below: u64 = self.histogram.iter().take(bins).sum();
+        below as f64 / self.count.max(1) as f64
+    }
+}
+
+fn load_labeled(input: &Path, labels: &Path) -> anyhow::Result<Vec<(IrisCode, usize)>> {
+    let records = store::read_records(&remote::fetch_file(input)?)
+        .with_context(|| format!("reading records from {}", input.display()))?;
+    let identities = csv::Reader::from_path(remote::fetch_file(labels)?)
+        .with_context(|| format!("reading labels from {}", labels.display()))?
+        .deserialize()
+        .map(|row| row.map(|label: Label| (label.id, label.identity)))
+        .collect::<Result<HashMap<_, _>, _>>()?;
+    records
+        .into_iter()
+        .map(|(code, id)| {
+            let identity = identities
+                .get(&id)
+                .with_context(|| format!("no label for record {id}"))?;
+            Ok((code, *identity))
+        })
+        .collect()
+}
+
+/// Compares every pair of samples exactly and reports false match and false non-match rates at
+/// `threshold`, independent of the graph.
+pub fn run(
+    experiment: &ExperimentArgs,
+    labeled: Option<(&Path, &Path)>,
+    identities: usize,
+    samples_per_identity: usize,
+    threshold: f64,
+    report: Option<&Path>,
+    aging: &AgingArgs,
+) -> anyhow::Result<()> {
+    let config = experiment.to_config()?;
+    let samples = match labeled {
+        Some((input, labels)) => load_labeled(input, labels)?,
+        None => pipeline::generate_labeled_dataset(&config, identities, samples_per_identity),
+    };
+    ensure!(samples.len() >= 2, "need at least two samples");
+
+    let _span = info_span!("eval", samples = samples.len()).entered();
+    let bar = pipeline::progress(&config, "Compare", samples.len());
+    let (genuine, impostor) = (0..samples.len())
+        .into_par_iter()
+        .fold(
+            || (PairStats::new(), PairStats::new()),
+            |(mut genuine, mut impostor), i| {
+                let (code, identity) = &samples[i];
+                for (other, other_identity) in &samples[i + 1..] {
+                    let distance = code.get_distance(other);
+                    if identity == other_identity {
+                        genuine.add(distance, threshold);
+                    } else {
+                        impostor.add(distance, threshold);
+                    }
+                }
+                bar.inc(1);
+                (genuine, impostor)
+            },
+        )
+        .reduce(
+            || (PairStats::new(), PairStats::new()),
+            |a, b| (a.0.merge(b.0), a.1.merge(b.1)),
+        );
+    bar.finish();
+
+    let curve: Vec<CurvePoint> = (0..=(0.5 / CURVE_STEP).round() as usize)
+        .map(|step| {
+            let threshold = step as f64 * CURVE_STEP;
+            CurvePoint {
+                threshold,
+                fmr: impostor.fraction_below(threshold),
+                fnmr: 1.0 - genuine.fraction_below(threshold),
+            }
+        })
+        .collect();
+    // the equal error rate is where the two error curves cross, located on the histogram bins
+    let eer = (0..=HISTOGRAM_BINS)
+        .map(|bin| {
+            let threshold = bin as f64 / HISTOGRAM_BINS as f64;
+            let fmr = impostor.fraction_below(threshold);
+            let fnmr = 1.0 - genuine.fraction_below(threshold);
+            ((fmr - fnmr).abs(), (fmr + fnmr) / 2.0)
+        })
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+        .map_or(0.0, |(_, eer)| eer);
+
+    let aging_curve = if aging.aging_steps > 0 {
+        let model = AgingModel {
+            flip_rate: aging.aging_flip_rate,
+            erosion_rate: aging.aging_erosion_rate,
+        };
+        pipeline::aging_distances(&config, &samples, &model, aging.aging_steps)?
+            .into_iter()
+            .enumerate()
+            .map(|(step, distances)| {
+                // a NaN distance, no bits in common, is no match either
+                let non_matches = distances
+                    .iter()
+                    .filter(|d| d.is_nan() || **d >= threshold)
+                    .count();
+                AgingPoint {
+                    step: step + 1,
+                    fnmr: non_matches as f64 / distances.len() as f64,
+                    mean_distance: distances.iter().sum::<f64>() / distances.len() as f64,
+                }
+            })
+            .collect()
+    } else {
+        vec![]
+    };
+
+    let mut distinct: Vec<usize> = samples.iter().map(|(_, identity)| *identity).collect();
+    distinct.sort_unstable();
+    distinct.dedup();
+    let report_data = AccuracyReport {
+        samples: samples.len(),
+        identities: distinct.len(),
+        threshold,
+        genuine_pairs: genuine.count,
+        impostor_pairs: impostor.count,
+        false_matches: impostor.below_threshold,
+        false_non_matches: genuine.count - genuine.below_threshold,
+        fmr: impostor.below_threshold as f64 / impostor.count.max(1) as f64,
+        fnmr: (genuine.count - genuine.below_threshold) as f64 / genuine.count.max(1) as f64,
+        mean_genuine_distance: genuine.distance_sum / genuine.count.max(1) as f64,
+        mean_impostor_distance: impostor.distance_sum / impostor.count.max(1) as f64,
+        eer,
+        curve,
+        aging: aging_curve,
+    };
+
+    info!(
+        threshold,
+        genuine_pairs = report_data.genuine_pairs,
+        impostor_pairs = report_data.impostor_pairs,
+        fmr = report_data.fmr,
+        fnmr = report_data.fnmr,
+        eer = report_data.eer,
+        "accuracy"
+    );
+    if let Some(last) = report_data.aging.last() {
+        info!(
+            steps = last.step,
+            fnmr = last.fnmr,
+            mean_distance = last.mean_distance,
+            "after aging"
+        );
+    }
+    if let Some(path) = report {
+        std::fs::write(path, serde_json::to_string_pretty(&report_data)?)
+            .with_context(|| format!("writing report to {}", path.display()))?;
+    }
+    Ok(())
+}
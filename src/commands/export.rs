@@ -0,0 +1,78 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::Context;
+use hnsw_hamming::IrisHnsw;
+use tracing::info;
+
+use crate::{
+    cli::{ExportArgs, NumpyArgs},
+    numpy, remote,
+    store::{self, TemplateRecord, INDEX_BASENAME},
+};
+
+pub fn ndjson(args: &ExportArgs) -> anyhow::Result<()> {
+    let records = load_records(args)?;
+    store::write_ndjson(output(args)?, &records)?;
+    info!("Exported {} templates", records.len());
+    Ok(())
+}
+
+#[cfg(feature = "parquet")]
+pub fn parquet(args: &ExportArgs) -> anyhow::Result<()> {
+    let path = args
+        .output
+        .as_deref()
+        .context("Parquet can't be written to stdout, pass --output")?;
+    let records = load_records(args)?;
+    crate::columnar::write_templates(path, &records)?;
+    info!("Exported {} templates", records.len());
+    Ok(())
+}
+
+pub fn npz(args: &NumpyArgs) -> anyhow::Result<()> {
+    let path = numpy_output(&args.export)?;
+    let records = load_records(&args.export)?;
+    numpy::write_npz(path, &numpy::template_arrays(&records, args.distances))?;
+    info!("Exported {} templates", records.len());
+    Ok(())
+}
+
+pub fn npy(args: &NumpyArgs) -> anyhow::Result<()> {
+    let dir = numpy_output(&args.export)?;
+    let records = load_records(&args.export)?;
+    numpy::write_npy_dir(dir, &numpy::template_arrays(&records, args.distances))?;
+    info!("Exported {} templates", records.len());
+    Ok(())
+}
+
+fn numpy_output(args: &ExportArgs) -> anyhow::Result<&Path> {
+    args.output
+        .as_deref()
+        .context("numpy arrays can't be written to stdout, pass --output")
+}
+
+/// The live templates of the index directory in id order, with their metadata.
+fn load_records(args: &ExportArgs) -> anyhow::Result<Vec<TemplateRecord>> {
+    let index = IrisHnsw::load(&remote::fetch_dir(&args.index_dir)?, INDEX_BASENAME)?;
+    Ok(index
+        .iter()
+        .map(|(id, code)| TemplateRecord {
+            id,
+            code,
+            meta: index.metadata(id).unwrap_or_default(),
+        })
+        .collect())
+}
+
+fn output(args: &ExportArgs) -> anyhow::Result<Box<dyn Write>> {
+    Ok(match &args.output {
+        Some(path) => Box::new(BufWriter::new(
+            File::create(path).with_context(|| format!("creating {}", path.display()))?,
+        )),
+        None => Box::new(BufWriter::new(std::io::stdout())),
+    })
+}
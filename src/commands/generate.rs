@@ -0,0 +1,44 @@
+use std::{fs::File, path::Path};
+
+use anyhow::Context;
+use hnsw_hamming::dataset::DatasetWriter;
+use tracing::info;
+
+use crate::{cli::ExperimentArgs, pipeline, store};
+
+/// Writes the codes `build` would generate to `output`, numbered by position, and optionally the
+/// query set to `queries`, so the same corpus can be reused without regenerating it.
+pub fn run(
+    experiment: &ExperimentArgs,
+    output: &Path,
+    queries: Option<&Path>,
+) -> anyhow::Result<()> {
+    let config = experiment.to_config()?;
+    let dataset = pipeline::generate_dataset(&config);
+
+    let file = File::create(output).with_context(|| format!("creating {}", output.display()))?;
+    let len = dataset.codes.len() as u64;
+    let mut writer = if config.hnsw.compress_masks {
+        DatasetWriter::compressed(file, len, false)?
+    } else {
+        DatasetWriter::new(file, len, false)?
+    };
+    for code in &dataset.codes {
+        writer.write(code, None)?;
+    }
+    writer
+        .finish()
+        .with_context(|| format!("writing {}", output.display()))?;
+    if let Some(path) = queries {
+        store::write_records(path, &dataset.queries)
+            .with_context(|| format!("writing {}", path.display()))?;
+    }
+
+    info!(
+        "Wrote {} codes with seed {} to {}",
+        dataset.codes.len(),
+        dataset.seed,
+        output.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,72 @@
+use std::path::Path;
+
+use anyhow::Context;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use tracing::{info, info_span};
+
+use crate::{
+    cli::ExperimentArgs,
+    pipeline, remote,
+    store::{self, GroundTruth, QueryTruth, CONFIG_FILE, INDEX_BASENAME, QUERIES_FILE},
+};
+
+/// Compares every query of the index's query set, perturbed as `search` does, against all live
+/// entries and writes the `k` nearest plus all matches below `threshold` to `output`.
+pub fn run(
+    experiment: &ExperimentArgs,
+    index_dir: &Path,
+    k: Option<usize>,
+    threshold: f64,
+    output: &Path,
+) -> anyhow::Result<()> {
+    let index_dir = &remote::fetch_dir(index_dir)?;
+    let config = experiment.to_config_with_base(Some(&index_dir.join(CONFIG_FILE)))?;
+
+    let load_span = info_span!("load", index_dir = %index_dir.display()).entered();
+    let index = pipeline::load_index(&config, index_dir, INDEX_BASENAME)?;
+    let queries =
+        store::read_records(&index_dir.join(QUERIES_FILE)).context("reading query set")?;
+    drop(load_span);
+
+    let k = k.unwrap_or(config.hnsw.knbn);
+    let seed = pipeline::resolve_seed(&config);
+    let _span = info_span!("groundtruth", queries = queries.len(), k, seed).entered();
+    let bar = pipeline::progress(&config, "Scan", queries.len());
+    let truth: Vec<_> = index.in_search_pool(|| {
+        queries
+            .par_iter()
+            .map(|(code, idx)| {
+                let query = pipeline::noisy_query(&config, seed, code, *idx);
+                let hits = index.search_exact(&query, k, threshold);
+                bar.inc(1);
+                QueryTruth {
+                    query_idx: *idx,
+                    neighbours: hits.iter().map(|hit| hit.id).collect(),
+                    distances: hits.iter().map(|hit| hit.distance).collect(),
+                    matches: hits
+                        .iter()
+                        .filter(|hit| f64::from(hit.distance) < threshold)
+                        .count(),
+                }
+            })
+            .collect()
+    });
+    bar.finish();
+
+    let matched = truth.iter().filter(|query| query.matches > 0).count();
+    let truth = GroundTruth {
+        seed,
+        k,
+        threshold,
+        queries: truth,
+    };
+    store::write_groundtruth(output, &truth)
+        .with_context(|| format!("writing {}", output.display()))?;
+    info!(
+        queries = truth.queries.len(),
+        matched,
+        "Wrote ground truth to {}",
+        output.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,154 @@
+use std::{net::SocketAddr, path::Path, sync::Arc};
+
+use tonic::{transport::Server, Request, Response, Status, Streaming};
+use tracing::info;
+
+use hnsw_hamming::IrisCode;
+
+use crate::{
+    cli::ExperimentArgs,
+    service::{IndexService, InsertError},
+};
+
+mod proto {
+    tonic::include_proto!("iris");
+}
+
+use proto::{
+    iris_matcher_server::{IrisMatcher, IrisMatcherServer},
+    BulkInsertResponse, InsertResponse, IrisTemplate, Neighbour, SearchRequest, SearchResponse,
+    UniquenessResponse,
+};
+
+// streamed templates are inserted in parallel batches of this size
+const BULK_INSERT_BATCH: usize = 1024;
+
+struct GrpcService {
+    service: Arc<IndexService>,
+}
+
+impl IrisTemplate {
+    fn decode(&self) -> Result<IrisCode, Status> {
+        IrisCode::from_bytes(&self.code, &self.mask)
+            .map_err(|err| Status::invalid_argument(err.to_string()))
+    }
+}
+
+impl From<hnsw_hamming::SearchHit> for Neighbour {
+    fn from(hit: hnsw_hamming::SearchHit) -> Self {
+        Self {
+            id: hit.id as u64,
+            distance: hit.distance,
+            is_match: hit.is_match,
+        }
+    }
+}
+
+// graph operations are CPU-bound, so they run off the async workers
+async fn blocking<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> Result<T, Status> {
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|err| Status::internal(err.to_string()))
+}
+
+fn insert_status(err: InsertError) -> Status {
+    match err {
+        InsertError::Invalid(_) => Status::invalid_argument(err.to_string()),
+        InsertError::Wal(_) => Status::internal(err.to_string()),
+    }
+}
+
+impl GrpcService {
+    async fn insert_batch(&self, codes: Vec<IrisCode>) -> Result<Vec<u64>, Status> {
+        let service = self.service.clone();
+        let ids = blocking(move || service.insert_batch(&codes))
+            .await?
+            .map_err(insert_status)?;
+        Ok(ids.into_iter().map(|id| id as u64).collect())
+    }
+}
+
+#[tonic::async_trait]
+impl IrisMatcher for GrpcService {
+    async fn insert(
+        &self,
+        request: Request<IrisTemplate>,
+    ) -> Result<Response<InsertResponse>, Status> {
+        let code = request.get_ref().decode()?;
+        let service = self.service.clone();
+        let id = blocking(move || service.insert(&code))
+            .await?
+            .map_err(insert_status)?;
+        Ok(Response::new(InsertResponse { id: id as u64 }))
+    }
+
+    async fn bulk_insert(
+        &self,
+        request: Request<Streaming<IrisTemplate>>,
+    ) -> Result<Response<BulkInsertResponse>, Status> {
+        let mut stream = request.into_inner();
+        let mut ids = vec![];
+        let mut batch = Vec::with_capacity(BULK_INSERT_BATCH);
+        while let Some(template) = stream.message().await? {
+            batch.push(template.decode()?);
+            if batch.len() == BULK_INSERT_BATCH {
+                ids.extend(self.insert_batch(std::mem::take(&mut batch)).await?);
+            }
+        }
+        ids.extend(self.insert_batch(batch).await?);
+        Ok(Response::new(BulkInsertResponse { ids }))
+    }
+
+    async fn search(
+        &self,
+        request: Request<SearchRequest>,
+    ) -> Result<Response<SearchResponse>, Status> {
+        let request = request.into_inner();
+        let code = request
+            .template
+            .as_ref()
+            .ok_or_else(|| Status::invalid_argument("missing template"))?
+            .decode()?;
+        let k = (request.k > 0).then_some(request.k as usize);
+        let ef = (request.ef > 0).then_some(request.ef as usize);
+        let service = self.service.clone();
+        let matches = blocking(move || service.search(&code, k, ef)).await?;
+        Ok(Response::new(SearchResponse {
+            neighbours: matches.into_iter().map(Neighbour::from).collect(),
+        }))
+    }
+
+    async fn is_unique(
+        &self,
+        request: Request<IrisTemplate>,
+    ) -> Result<Response<UniquenessResponse>, Status> {
+        let code = request.get_ref().decode()?;
+        let service = self.service.clone();
+        let result = blocking(move || service.is_unique(&code)).await?;
+        Ok(Response::new(UniquenessResponse {
+            unique: result.unique,
+            closest: result.closest.map(Neighbour::from),
+        }))
+    }
+}
+
+pub fn run(
+    experiment: &ExperimentArgs,
+    index_dir: Option<&Path>,
+    wal: Option<&Path>,
+    listen: SocketAddr,
+) -> anyhow::Result<()> {
+    let config = experiment.to_config()?;
+    let service = GrpcService {
+        service: Arc::new(IndexService::load(config, index_dir, wal)?),
+    };
+
+    tokio::runtime::Runtime::new()?.block_on(async move {
+        info!(%listen, "serving gRPC");
+        Server::builder()
+            .add_service(IrisMatcherServer::new(service))
+            .serve(listen)
+            .await?;
+        Ok::<_, anyhow::Error>(())
+    })
+}
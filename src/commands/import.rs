@@ -0,0 +1,160 @@
+use std::{collections::HashSet, fs::File, io::BufReader, path::Path};
+
+use anyhow::{ensure, Context};
+#[cfg(feature = "postgres")]
+use hnsw_hamming::IrisCode;
+use hnsw_hamming::IrisHnsw;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use tracing::{info, info_span};
+
+use crate::{
+    cli::ImportArgs,
+    config::Config,
+    pipeline,
+    progress::Progress,
+    remote,
+    store::{self, TemplateRecord, CONFIG_FILE, INDEX_BASENAME},
+};
+#[cfg(feature = "postgres")]
+use crate::{cli::PostgresArgs, progress::NoProgress};
+
+pub fn ndjson(args: &ImportArgs) -> anyhow::Result<()> {
+    let file = File::open(remote::fetch_file(&args.input)?)
+        .with_context(|| format!("opening {}", args.input.display()))?;
+    let records = store::read_ndjson(BufReader::new(file))
+        .with_context(|| format!("reading {}", args.input.display()))?;
+    import(args, records)
+}
+
+pub fn csv(args: &ImportArgs) -> anyhow::Result<()> {
+    let file = File::open(remote::fetch_file(&args.input)?)
+        .with_context(|| format!("opening {}", args.input.display()))?;
+    let records = store::read_csv(BufReader::new(file))
+        .with_context(|| format!("reading {}", args.input.display()))?;
+    import(args, records)
+}
+
+#[cfg(feature = "parquet")]
+pub fn parquet(args: &ImportArgs) -> anyhow::Result<()> {
+    let records = crate::columnar::read_templates(&remote::fetch_file(&args.input)?)
+        .with_context(|| format!("reading {}", args.input.display()))?;
+    import(args, records)
+}
+
+/// Streams the rows of `args.query` in batches of `args.batch_size` through a cursor, inserting
+/// each batch in parallel before the next is fetched, so the table never has to fit in memory
+/// twice. The query has to return a bigint `id` and bytea `code` and `mask` columns, the latter
+/// two in the layout of `IrisCodeArray::as_raw_slice`; other text columns become metadata.
+#[cfg(feature = "postgres")]
+pub fn postgres(args: &PostgresArgs) -> anyhow::Result<()> {
+    let config = args.experiment.to_config()?;
+    let mut client = ::postgres::Client::connect(&args.url, ::postgres::NoTls)
+        .context("connecting to Postgres")?;
+    let mut transaction = client.transaction()?;
+    let portal = transaction
+        .bind(args.query.as_str(), &[])
+        .context("running the template query")?;
+
+    let _span = info_span!("import", query = %args.query).entered();
+    let index = pipeline::new_index(&config, None);
+    let mut seen = HashSet::new();
+    loop {
+        let rows = transaction.query_portal(&portal, args.batch_size)?;
+        if rows.is_empty() {
+            break;
+        }
+        let records = rows
+            .iter()
+            .map(template_from_row)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        insert_records(&index, &records, &mut seen, &NoProgress)?;
+        info!(imported = seen.len(), "inserted batch");
+    }
+    transaction.commit()?;
+    write_index_dir(&config, &index, &args.index_dir)?;
+
+    info!(
+        "Imported {} templates into {}",
+        seen.len(),
+        args.index_dir.display()
+    );
+    Ok(())
+}
+
+#[cfg(feature = "postgres")]
+fn template_from_row(row: &::postgres::Row) -> anyhow::Result<TemplateRecord> {
+    let id: i64 = row.try_get("id")?;
+    let code: &[u8] = row.try_get("code")?;
+    let mask: &[u8] = row.try_get("mask")?;
+    let meta = row
+        .columns()
+        .iter()
+        .enumerate()
+        .filter(|(_, column)| !["id", "code", "mask"].contains(&column.name()))
+        .filter_map(|(i, column)| {
+            let value = row.try_get::<_, Option<String>>(i).ok().flatten()?;
+            Some((column.name().to_string(), value))
+        })
+        .collect();
+    Ok(TemplateRecord {
+        id: usize::try_from(id).with_context(|| format!("negative id {id}"))?,
+        code: IrisCode::from_bytes(code, mask).with_context(|| format!("id {id}"))?,
+        meta,
+    })
+}
+
+/// Builds an index over `records`, with their metadata, and writes it to the index directory
+/// the way `build` does.
+fn import(args: &ImportArgs, records: Vec<TemplateRecord>) -> anyhow::Result<()> {
+    let config = args.experiment.to_config()?;
+    let index = pipeline::new_index(&config, Some(records.len()));
+    {
+        let _span = info_span!("build", n_points = records.len()).entered();
+        let bar = pipeline::progress(&config, "Insert", records.len());
+        insert_records(&index, &records, &mut HashSet::new(), bar.as_ref())?;
+        bar.finish();
+    }
+    write_index_dir(&config, &index, &args.index_dir)?;
+
+    info!(
+        "Imported {} templates into {}",
+        records.len(),
+        args.index_dir.display()
+    );
+    Ok(())
+}
+
+/// Inserts `records` in parallel, failing if any id is in `seen` already, which all of them are
+/// added to.
+fn insert_records(
+    index: &IrisHnsw,
+    records: &[TemplateRecord],
+    seen: &mut HashSet<usize>,
+    bar: &dyn Progress,
+) -> anyhow::Result<()> {
+    for record in records {
+        ensure!(
+            seen.insert(record.id),
+            "id {} appears more than once",
+            record.id
+        );
+    }
+    index.in_build_pool(|| {
+        records.par_iter().try_for_each(|record| {
+            bar.inc(1);
+            index
+                .insert_with_metadata(&record.code, record.id, record.meta.clone())
+                .with_context(|| format!("inserting id {}", record.id))
+        })
+    })
+}
+
+fn write_index_dir(config: &Config, index: &IrisHnsw, target_dir: &Path) -> anyhow::Result<()> {
+    let index_dir = &remote::staging_dir(target_dir)?;
+    std::fs::create_dir_all(index_dir)
+        .with_context(|| format!("creating {}", index_dir.display()))?;
+    std::fs::write(index_dir.join(CONFIG_FILE), toml::to_string(config)?)
+        .context("writing build config")?;
+    index.dump(index_dir, INDEX_BASENAME)?;
+    remote::publish_dir(index_dir, target_dir)
+}
@@ -0,0 +1,133 @@
+use std::{
+    io::{self, BufRead, Write},
+    path::Path,
+};
+
+use anyhow::Context;
+use hnsw_hamming::{dataset::DatasetReader, IrisHnsw, UniquenessResult};
+use serde::Serialize;
+use tracing::info;
+
+use crate::{
+    cli::{ExperimentArgs, IngestFormat},
+    config::Config,
+    pipeline,
+    store::{self, TemplateRecord, CONFIG_FILE, INDEX_BASENAME},
+};
+
+/// Made for every record read, printed by `ingest` and produced to Kafka by `kafka`.
+#[derive(Serialize)]
+pub struct Decision {
+    id: usize,
+    /// Whether the record was enrolled, i.e. matched nobody.
+    enrolled: bool,
+    #[serde(flatten)]
+    result: Option<UniquenessResult>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Enrolls the templates read from stdin one at a time, printing one JSON decision per line as
+/// soon as it is made. Records that match an enrolled template or fail validation are not
+/// inserted. With `index_dir`, starts from the index in it, if any, and writes the index back
+/// once stdin is exhausted.
+pub fn run(
+    experiment: &ExperimentArgs,
+    format: IngestFormat,
+    index_dir: Option<&Path>,
+) -> anyhow::Result<()> {
+    let (config, index) = open_index(experiment, index_dir)?;
+
+    let stdin = io::stdin().lock();
+    let mut stdout = io::stdout().lock();
+    let mut decide = |record: TemplateRecord| -> anyhow::Result<()> {
+        let decision = enroll(&index, record);
+        serde_json::to_writer(&mut stdout, &decision)?;
+        writeln!(stdout)?;
+        // the next stage of the pipeline sees each decision right away
+        stdout.flush()?;
+        Ok(())
+    };
+    let mut records = 0;
+    match format {
+        IngestFormat::Ndjson => {
+            for (i, line) in stdin.lines().enumerate() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let record = store::parse_ndjson_line(&line)
+                    .with_context(|| format!("line {} of stdin", i + 1))?;
+                decide(record)?;
+                records += 1;
+            }
+        }
+        IngestFormat::Binary => {
+            for record in DatasetReader::new(stdin).context("reading dataset header")? {
+                let (code, id) = record?;
+                decide(TemplateRecord {
+                    id,
+                    code,
+                    meta: Default::default(),
+                })?;
+                records += 1;
+            }
+        }
+    }
+
+    info!(records, entries = index.len(), "stdin exhausted");
+    if let Some(dir) = index_dir {
+        write_index(&config, &index, dir)?;
+    }
+    Ok(())
+}
+
+/// The index in `index_dir` if it holds one, otherwise an empty one.
+pub fn open_index(
+    experiment: &ExperimentArgs,
+    index_dir: Option<&Path>,
+) -> anyhow::Result<(Config, IrisHnsw)> {
+    let existing = index_dir
+        .map(|dir| dir.join(CONFIG_FILE))
+        .filter(|path| path.exists());
+    let config = experiment.to_config_with_base(existing.as_deref())?;
+    let index = match (index_dir, &existing) {
+        (Some(dir), Some(_)) => pipeline::load_index(&config, dir, INDEX_BASENAME)?,
+        _ => pipeline::new_index(&config, None),
+    };
+    Ok((config, index))
+}
+
+pub fn write_index(config: &Config, index: &IrisHnsw, dir: &Path) -> anyhow::Result<()> {
+    std::fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+    std::fs::write(dir.join(CONFIG_FILE), toml::to_string(config)?)
+        .context("writing build config")?;
+    index.dump(dir, INDEX_BASENAME)?;
+    Ok(())
+}
+
+/// Inserts `record` unless it matches an enrolled template, then attaches its metadata.
+pub fn enroll(index: &IrisHnsw, record: TemplateRecord) -> Decision {
+    let result = IrisHnsw::validate_metadata(&record.meta)
+        .and_then(|()| index.insert_unique(&record.code, record.id));
+    if let Ok(UniquenessResult { unique: true, .. }) = result {
+        if !record.meta.is_empty() {
+            // can't fail, the metadata was validated above
+            let _ = index.set_metadata(record.id, record.meta);
+        }
+    }
+    match result {
+        Ok(result) => Decision {
+            id: record.id,
+            enrolled: result.unique,
+            result: Some(result),
+            error: None,
+        },
+        Err(err) => Decision {
+            id: record.id,
+            enrolled: false,
+            result: None,
+            error: Some(err.to_string()),
+        },
+    }
+}
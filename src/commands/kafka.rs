@@ -0,0 +1,133 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use rdkafka::{
+    config::ClientConfig,
+    consumer::{BaseConsumer, CommitMode, Consumer},
+    error::{KafkaError, RDKafkaErrorCode},
+    message::Message,
+    producer::{BaseRecord, DeliveryResult, Producer, ProducerContext, ThreadedProducer},
+    ClientContext,
+};
+use tracing::{info, warn};
+
+use super::ingest::{enroll, open_index, write_index};
+use crate::{cli::KafkaArgs, store};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(30);
+// messages consumed between offset commits, besides whenever the topic runs dry
+const COMMIT_EVERY: usize = 1000;
+
+struct LogFailedDeliveries;
+
+impl ClientContext for LogFailedDeliveries {}
+
+impl ProducerContext for LogFailedDeliveries {
+    type DeliveryOpaque = ();
+
+    fn delivery(&self, result: &DeliveryResult<'_>, _: ()) {
+        if let Err((err, message)) = result {
+            warn!(%err, partition = message.partition(), "decision not delivered");
+        }
+    }
+}
+
+/// Enrolls the templates consumed from the input topic one at a time, producing each decision
+/// to the output topic. Offsets are committed only once the decisions before them are flushed,
+/// so after a crash ingestion resumes at least from the first template without a decision.
+/// Messages that aren't a template are logged and skipped.
+pub fn run(args: &KafkaArgs) -> anyhow::Result<()> {
+    let (config, index) = open_index(&args.experiment, args.index_dir.as_deref())?;
+
+    let consumer: BaseConsumer = ClientConfig::new()
+        .set("bootstrap.servers", &args.brokers)
+        .set("group.id", &args.group_id)
+        .set("enable.auto.commit", "false")
+        .set("auto.offset.reset", "earliest")
+        .create()
+        .context("creating consumer")?;
+    consumer.subscribe(&[&args.input_topic])?;
+    let producer: ThreadedProducer<LogFailedDeliveries> = ClientConfig::new()
+        .set("bootstrap.servers", &args.brokers)
+        .set("enable.idempotence", "true")
+        .create_with_context(LogFailedDeliveries)
+        .context("creating producer")?;
+
+    let idle_timeout = args.idle_timeout_secs.map(Duration::from_secs);
+    let mut last_message = Instant::now();
+    let mut uncommitted = 0;
+    let mut records = 0;
+    let mut skipped = 0;
+    let commit = |uncommitted: &mut usize| -> anyhow::Result<()> {
+        if *uncommitted > 0 {
+            producer.flush(FLUSH_TIMEOUT)?;
+            consumer.commit_consumer_state(CommitMode::Sync)?;
+            *uncommitted = 0;
+        }
+        Ok(())
+    };
+    loop {
+        let Some(message) = consumer.poll(POLL_INTERVAL) else {
+            commit(&mut uncommitted)?;
+            if idle_timeout.is_some_and(|timeout| last_message.elapsed() >= timeout) {
+                break;
+            }
+            continue;
+        };
+        let message = message?;
+        last_message = Instant::now();
+        uncommitted += 1;
+
+        let record = message
+            .payload()
+            .context("empty message")
+            .and_then(|payload| Ok(std::str::from_utf8(payload)?))
+            .and_then(store::parse_ndjson_line);
+        match record {
+            Ok(record) => {
+                let key = record.id.to_string();
+                let payload = serde_json::to_vec(&enroll(&index, record))?;
+                let mut outgoing = BaseRecord::to(&args.output_topic)
+                    .key(&key)
+                    .payload(&payload);
+                // the producer's queue drains in the background
+                while let Err((err, unsent)) = producer.send(outgoing) {
+                    match err {
+                        KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull) => {
+                            outgoing = unsent;
+                            thread::sleep(POLL_INTERVAL);
+                        }
+                        err => return Err(err).context("producing decision"),
+                    }
+                }
+                records += 1;
+            }
+            Err(err) => {
+                warn!(
+                    partition = message.partition(),
+                    offset = message.offset(),
+                    "skipping message: {err:#}"
+                );
+                skipped += 1;
+            }
+        }
+        if uncommitted >= COMMIT_EVERY {
+            commit(&mut uncommitted)?;
+        }
+    }
+
+    info!(
+        records,
+        skipped,
+        entries = index.len(),
+        "topic idle, stopping"
+    );
+    if let Some(dir) = &args.index_dir {
+        write_index(&config, &index, dir)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,171 @@
+use std::{
+    fs::{self, File},
+    io::{BufReader, Read},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{ensure, Context};
+use hnsw_hamming::{
+    dataset::{DatasetReader, DatasetWriter, DATASET_FORMAT_VERSION, DATASET_MAGIC},
+    index::INDEX_FORMAT_VERSION,
+    IrisHnsw,
+};
+use tracing::info;
+
+use crate::store::{INDEX_BASENAME, QUERIES_FILE};
+
+// the index is dumped here first, so an interrupted migration leaves the old one intact
+const MIGRATE_PARTIAL_DIR: &str = "migrate.partial";
+
+/// Rewrites the index in `index_dir` with its query set, and each of `datasets`, in the current
+/// formats if they are in an older one.
+pub fn run(index_dir: Option<&Path>, datasets: &[PathBuf]) -> anyhow::Result<()> {
+    if let Some(dir) = index_dir {
+        migrate_index(dir)?;
+        let queries = dir.join(QUERIES_FILE);
+        if queries.exists() {
+            migrate_dataset(&queries)?;
+        }
+    }
+    for path in datasets {
+        migrate_dataset(path)?;
+    }
+    Ok(())
+}
+
+fn migrate_index(dir: &Path) -> anyhow::Result<()> {
+    let version = IrisHnsw::format_version(dir, INDEX_BASENAME)?;
+    if version == INDEX_FORMAT_VERSION {
+        info!(index_dir = %dir.display(), version, "index is current");
+        return Ok(());
+    }
+    let index = IrisHnsw::load(dir, INDEX_BASENAME)?;
+    let partial = dir.join(MIGRATE_PARTIAL_DIR);
+    if partial.exists() {
+        fs::remove_dir_all(&partial)?;
+    }
+    fs::create_dir(&partial).with_context(|| format!("creating {}", partial.display()))?;
+    index.dump(&partial, INDEX_BASENAME)?;
+    for entry in fs::read_dir(&partial)? {
+        let entry = entry?;
+        fs::rename(entry.path(), dir.join(entry.file_name()))?;
+    }
+    fs::remove_dir(&partial)?;
+    info!(
+        index_dir = %dir.display(),
+        from = version,
+        to = INDEX_FORMAT_VERSION,
+        "migrated index"
+    );
+    Ok(())
+}
+
+fn migrate_dataset(path: &Path) -> anyhow::Result<()> {
+    let mut file = File::open(path).with_context(|| format!("opening {}", path.display()))?;
+    let mut start = [0; DATASET_MAGIC.len() + 1];
+    file.read_exact(&mut start)
+        .with_context(|| format!("reading {}", path.display()))?;
+    ensure!(
+        start[..DATASET_MAGIC.len()] == DATASET_MAGIC,
+        "{} is not a record file",
+        path.display()
+    );
+    let version = start[DATASET_MAGIC.len()];
+    if version == DATASET_FORMAT_VERSION {
+        info!(path = %path.display(), version, "dataset is current");
+        return Ok(());
+    }
+    let reader: DatasetReader<_> = DatasetReader::new(BufReader::new(File::open(path)?))
+        .with_context(|| format!("reading {}", path.display()))?;
+    let header = reader.header();
+    let partial = path.with_extension("partial");
+    let mut writer = DatasetWriter::new(File::create(&partial)?, header.len, header.has_ids)?;
+    for record in reader {
+        let (code, id) = record.with_context(|| format!("reading {}", path.display()))?;
+        writer.write(&code, header.has_ids.then_some(id))?;
+    }
+    writer
+        .finish()
+        .with_context(|| format!("writing {}", partial.display()))?;
+    fs::rename(&partial, path)?;
+    info!(
+        path = %path.display(),
+        from = version,
+        to = DATASET_FORMAT_VERSION,
+        records = header.len,
+        "migrated dataset"
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use hnsw_hamming::IrisCode;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+    use crate::store;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+        dir
+    }
+
+    fn codes(len: usize) -> Vec<IrisCode> {
+        let mut rng = StdRng::seed_from_u64(0);
+        (0..len).map(|_| IrisCode::random_rng(&mut rng)).collect()
+    }
+
+    #[test]
+    fn migrates_index_from_version_1() {
+        let dir = temp_dir("migrate-index");
+        let codes = codes(20);
+        let index = IrisHnsw::builder().build();
+        for (id, code) in codes.iter().enumerate() {
+            index.insert(code, id).unwrap();
+        }
+        index.dump(&dir, INDEX_BASENAME).unwrap();
+        drop(index);
+        // version 1 dumped neither settings nor entries, its points were the ids
+        for extension in ["settings.json", "entries"] {
+            fs::remove_file(dir.join(format!("{INDEX_BASENAME}.{extension}"))).unwrap();
+        }
+        assert_eq!(IrisHnsw::format_version(&dir, INDEX_BASENAME).unwrap(), 1);
+
+        run(Some(&dir), &[]).unwrap();
+        assert_eq!(
+            IrisHnsw::format_version(&dir, INDEX_BASENAME).unwrap(),
+            INDEX_FORMAT_VERSION
+        );
+        assert!(!dir.join(MIGRATE_PARTIAL_DIR).exists());
+        let index = IrisHnsw::load(&dir, INDEX_BASENAME).unwrap();
+        assert_eq!(index.len(), codes.len());
+        for (id, code) in codes.iter().enumerate() {
+            assert_eq!(index.get(id).as_ref(), Some(code));
+        }
+        drop(index);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn migrates_dataset_from_version_1() {
+        let dir = temp_dir("migrate-dataset");
+        let path = dir.join("records.bin");
+        let records: Vec<_> = codes(5).into_iter().zip((10..).step_by(10)).collect();
+        store::write_records(&path, &records).unwrap();
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[DATASET_MAGIC.len()] = 1;
+        fs::write(&path, &bytes).unwrap();
+
+        run(None, &[path.clone()]).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        assert_eq!(bytes[DATASET_MAGIC.len()], DATASET_FORMAT_VERSION);
+        assert_eq!(store::read_records(&path).unwrap(), records);
+        // current files are left alone
+        run(None, &[path.clone()]).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), bytes);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
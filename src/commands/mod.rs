@@ -0,0 +1,20 @@
+#[cfg(feature = "hdf5")]
+pub mod ann;
+pub mod autotune;
+pub mod bench;
+pub mod build;
+pub mod dedup;
+pub mod eval;
+pub mod export;
+pub mod generate;
+pub mod groundtruth;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod import;
+pub mod ingest;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+pub mod migrate;
+pub mod search;
+pub mod serve;
+pub mod sweep;
@@ -0,0 +1,41 @@
+use std::path::Path;
+
+use anyhow::Context;
+use tracing::info_span;
+
+use crate::{
+    cli::ExperimentArgs,
+    pipeline, remote,
+    store::{self, CONFIG_FILE, INDEX_BASENAME, QUERIES_FILE},
+};
+
+/// With `groundtruth`, the queries are perturbed with its seed and the results scored against it.
+pub fn run(
+    experiment: &ExperimentArgs,
+    index_dir: &Path,
+    groundtruth: Option<&Path>,
+) -> anyhow::Result<()> {
+    let index_dir = &remote::fetch_dir(index_dir)?;
+    let config = experiment.to_config_with_base(Some(&index_dir.join(CONFIG_FILE)))?;
+
+    let load_span = info_span!("load", index_dir = %index_dir.display()).entered();
+    let mut index = pipeline::load_index(&config, index_dir, INDEX_BASENAME)?;
+    index.set_searching_mode(true);
+    let queries =
+        store::read_records(&index_dir.join(QUERIES_FILE)).context("reading query set")?;
+    drop(load_span);
+
+    let truth = groundtruth
+        .map(|path| pipeline::load_groundtruth(&config, path))
+        .transpose()?;
+    let seed = match &truth {
+        Some(truth) => truth.seed,
+        None => pipeline::resolve_seed(&config),
+    };
+    let mut stats = pipeline::run_queries(&index, &queries, seed, &config);
+    if let Some(truth) = &truth {
+        pipeline::score_against(&mut stats, truth)?;
+    }
+
+    pipeline::report(&config, &stats, None)
+}
@@ -0,0 +1,117 @@
+use std::{net::SocketAddr, path::Path, sync::Arc};
+
+use axum::{extract::State, http::StatusCode, routing::post, Json, Router};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use hnsw_hamming::{IrisCode, SearchHit, UniquenessResult};
+
+use crate::{
+    cli::ExperimentArgs,
+    service::{IndexService, InsertError},
+};
+
+type AppError = (StatusCode, String);
+
+/// Base64 of the raw little-endian code and mask words.
+#[derive(Deserialize)]
+struct Template {
+    code: String,
+    mask: String,
+}
+
+#[derive(Deserialize)]
+struct SearchRequest {
+    #[serde(flatten)]
+    template: Template,
+    k: Option<usize>,
+    ef: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct InsertResponse {
+    id: usize,
+}
+
+#[derive(Serialize)]
+struct SearchResponse {
+    neighbours: Vec<SearchHit>,
+}
+
+impl Template {
+    fn decode(&self) -> Result<IrisCode, AppError> {
+        let bad_request = |err: String| (StatusCode::BAD_REQUEST, err);
+        let code = STANDARD
+            .decode(&self.code)
+            .map_err(|err| bad_request(format!("invalid base64 code: {err}")))?;
+        let mask = STANDARD
+            .decode(&self.mask)
+            .map_err(|err| bad_request(format!("invalid base64 mask: {err}")))?;
+        IrisCode::from_bytes(&code, &mask).map_err(|err| bad_request(err.to_string()))
+    }
+}
+
+// graph operations are CPU-bound, so they run off the async workers
+async fn blocking<T: Send + 'static>(
+    f: impl FnOnce() -> T + Send + 'static,
+) -> Result<T, AppError> {
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))
+}
+
+async fn insert(
+    State(service): State<Arc<IndexService>>,
+    Json(template): Json<Template>,
+) -> Result<Json<InsertResponse>, AppError> {
+    let code = template.decode()?;
+    let id = blocking(move || service.insert(&code))
+        .await?
+        .map_err(|err| match err {
+            InsertError::Invalid(_) => (StatusCode::BAD_REQUEST, err.to_string()),
+            InsertError::Wal(_) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+        })?;
+    Ok(Json(InsertResponse { id }))
+}
+
+async fn search(
+    State(service): State<Arc<IndexService>>,
+    Json(request): Json<SearchRequest>,
+) -> Result<Json<SearchResponse>, AppError> {
+    let code = request.template.decode()?;
+    let neighbours = blocking(move || service.search(&code, request.k, request.ef)).await?;
+    Ok(Json(SearchResponse { neighbours }))
+}
+
+async fn is_unique(
+    State(service): State<Arc<IndexService>>,
+    Json(template): Json<Template>,
+) -> Result<Json<UniquenessResult>, AppError> {
+    let code = template.decode()?;
+    let result = blocking(move || service.is_unique(&code)).await?;
+    Ok(Json(result))
+}
+
+pub fn run(
+    experiment: &ExperimentArgs,
+    index_dir: Option<&Path>,
+    wal: Option<&Path>,
+    listen: SocketAddr,
+) -> anyhow::Result<()> {
+    let config = experiment.to_config()?;
+    let service = Arc::new(IndexService::load(config, index_dir, wal)?);
+
+    let app = Router::new()
+        .route("/insert", post(insert))
+        .route("/search", post(search))
+        .route("/is_unique", post(is_unique))
+        .with_state(service);
+
+    tokio::runtime::Runtime::new()?.block_on(async move {
+        let listener = tokio::net::TcpListener::bind(listen).await?;
+        info!(%listen, "serving HTTP");
+        axum::serve(listener, app).await?;
+        Ok::<_, anyhow::Error>(())
+    })
+}
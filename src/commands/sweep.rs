@@ -0,0 +1,103 @@
+use std::{path::Path, time::Instant};
+
+use anyhow::Context;
+use serde::Serialize;
+use tracing::{info, info_span};
+
+use crate::{cli::ExperimentArgs, config::Config, pipeline};
+
+#[derive(Serialize)]
+struct SweepRow {
+    max_nb_connection: usize,
+    ef_construction: usize,
+    ef_search: usize,
+    recall: f32,
+    avg_evals: usize,
+    build_time_secs: f64,
+    search_time_secs: f64,
+}
+
+fn grid_config(
+    config: &Config,
+    max_nb_connection: usize,
+    ef_construction: usize,
+    ef_search: usize,
+) -> Config {
+    let mut config = config.clone();
+    config.hnsw.max_nb_connection = max_nb_connection;
+    config.hnsw.ef_construction = ef_construction;
+    config.hnsw.ef_search = Some(ef_search);
+    config
+}
+
+/// Builds one index per (M, ef_construction) pair over a shared dataset and queries it with
+/// every ef_search value. Empty grids fall back to the single configured value.
+pub fn run(
+    experiment: &ExperimentArgs,
+    max_nb_connections: &[usize],
+    ef_constructions: &[usize],
+    ef_searches: &[usize],
+    csv_path: &Path,
+) -> anyhow::Result<()> {
+    let config = experiment.to_config()?;
+    let or_default = |values: &[usize], default: usize| {
+        if values.is_empty() {
+            vec![default]
+        } else {
+            values.to_vec()
+        }
+    };
+    let max_nb_connections = or_default(max_nb_connections, config.hnsw.max_nb_connection);
+    let ef_constructions = or_default(ef_constructions, config.hnsw.ef_construction);
+    let ef_searches = or_default(ef_searches, config.ef_search());
+
+    // reject bad combinations before spending time on any build
+    for &max_nb_connection in &max_nb_connections {
+        for &ef_construction in &ef_constructions {
+            for &ef_search in &ef_searches {
+                grid_config(&config, max_nb_connection, ef_construction, ef_search).validate()?;
+            }
+        }
+    }
+
+    let dataset = pipeline::generate_dataset(&config);
+    let mut writer = csv::Writer::from_path(csv_path)
+        .with_context(|| format!("creating {}", csv_path.display()))?;
+
+    for &max_nb_connection in &max_nb_connections {
+        for &ef_construction in &ef_constructions {
+            let build_config =
+                grid_config(&config, max_nb_connection, ef_construction, ef_searches[0]);
+            let _span = info_span!("sweep", max_nb_connection, ef_construction).entered();
+
+            let start = Instant::now();
+            let hnsw = pipeline::build_index(&build_config, &dataset);
+            let build_time = start.elapsed();
+
+            for &ef_search in &ef_searches {
+                let config = grid_config(&config, max_nb_connection, ef_construction, ef_search);
+                let stats = pipeline::run_queries(&hnsw, &dataset.queries, dataset.seed, &config);
+                info!(
+                    max_nb_connection,
+                    ef_construction,
+                    ef_search,
+                    recall = stats.recall,
+                    avg_evals = stats.avg_evals
+                );
+
+                writer.serialize(SweepRow {
+                    max_nb_connection,
+                    ef_construction,
+                    ef_search,
+                    recall: stats.recall,
+                    avg_evals: stats.avg_evals,
+                    build_time_secs: build_time.as_secs_f64(),
+                    search_time_secs: stats.search_time.as_secs_f64(),
+                })?;
+                // keep finished rows on disk in case a later combination is interrupted
+                writer.flush()?;
+            }
+        }
+    }
+    Ok(())
+}
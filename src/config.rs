@@ -0,0 +1,242 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{ensure, Context};
+use serde::{Deserialize, Serialize};
+
+use hnsw_hamming::{
+    index::{default_nb_layer, MAX_NB_CONNECTION_LIMIT, MAX_NB_LAYER},
+    iris::{NoiseParams, DEFAULT_FLIP_PROBABILITY, DEFAULT_MASK_DROPOUT},
+    noise::NoiseModel,
+    IrisCode,
+};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub dataset: DatasetConfig,
+    pub noise: NoiseConfig,
+    pub hnsw: HnswConfig,
+    pub output: OutputConfig,
+    pub threads: ThreadsConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatasetConfig {
+    pub n_points: usize,
+    pub random_queries: usize,
+    pub seed: Option<u64>,
+    pub mask_dropout: f64,
+    /// Width of the codes, fixed at compile time by the `toy-codes` and `full-codes` features. It
+    /// is saved with an index so that a binary of the other width refuses to load it.
+    pub code_bits: usize,
+}
+
+impl Default for DatasetConfig {
+    fn default() -> Self {
+        Self {
+            n_points: 100_000,
+            random_queries: 10_000,
+            seed: None,
+            mask_dropout: DEFAULT_MASK_DROPOUT,
+            code_bits: <IrisCode>::IRIS_CODE_SIZE,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NoiseConfig {
+    pub flip_probability: f64,
+    /// Defaults to `flip_probability`.
+    pub mask_flip_probability: Option<f64>,
+    pub pairwise: bool,
+    pub max_rotation: usize,
+    pub radial_shift_probability: f64,
+    pub occlusion_probability: f64,
+    pub max_occlusion_columns: usize,
+}
+
+impl Default for NoiseConfig {
+    fn default() -> Self {
+        Self {
+            flip_probability: DEFAULT_FLIP_PROBABILITY,
+            mask_flip_probability: None,
+            pairwise: false,
+            max_rotation: 0,
+            radial_shift_probability: 0.0,
+            occlusion_probability: 0.0,
+            max_occlusion_columns: 0,
+        }
+    }
+}
+
+impl NoiseConfig {
+    pub fn model(&self) -> NoiseModel {
+        NoiseModel {
+            max_rotation: self.max_rotation,
+            radial_shift_probability: self.radial_shift_probability,
+            occlusion_probability: self.occlusion_probability,
+            max_occlusion_columns: self.max_occlusion_columns,
+            ..NoiseModel::from_flips(NoiseParams {
+                code_flip_probability: self.flip_probability,
+                mask_flip_probability: self.mask_flip_probability.unwrap_or(self.flip_probability),
+                pairwise: self.pairwise,
+            })
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HnswConfig {
+    pub max_nb_connection: usize,
+    pub ef_construction: usize,
+    pub ef_search: Option<usize>,
+    pub knbn: usize,
+    pub nb_layer: Option<usize>,
+    pub rerank_oversample: Option<usize>,
+    /// Map a persisted index instead of reading it into memory, see `IrisHnsw::load_mapped`.
+    pub mmap: bool,
+    /// Keep stored masks run-length encoded, see `IrisHnswBuilder::compress_masks`.
+    pub compress_masks: bool,
+}
+
+impl Default for HnswConfig {
+    fn default() -> Self {
+        Self {
+            max_nb_connection: 128,
+            ef_construction: 128,
+            ef_search: None,
+            knbn: 1,
+            nb_layer: None,
+            rerank_oversample: None,
+            mmap: false,
+            compress_masks: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputConfig {
+    pub results: Option<PathBuf>,
+    pub queries_csv: Option<PathBuf>,
+    /// Same rows as `queries_csv`, needs the `parquet` feature.
+    pub queries_parquet: Option<PathBuf>,
+    pub progress: bool,
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        Self {
+            results: None,
+            queries_csv: None,
+            queries_parquet: None,
+            progress: true,
+        }
+    }
+}
+
+/// Dedicated rayon pool sizes, the global pool is used where unset.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThreadsConfig {
+    pub build: Option<usize>,
+    pub search: Option<usize>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("reading config {}", path.display()))?;
+        toml::from_str(&contents).with_context(|| format!("parsing config {}", path.display()))
+    }
+
+    pub fn nb_layer(&self) -> usize {
+        self.hnsw
+            .nb_layer
+            .unwrap_or_else(|| default_nb_layer(self.dataset.n_points))
+    }
+
+    pub fn ef_search(&self) -> usize {
+        self.hnsw.ef_search.unwrap_or(self.hnsw.ef_construction)
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let dataset = &self.dataset;
+        let hnsw = &self.hnsw;
+        ensure!(
+            dataset.code_bits == <IrisCode>::IRIS_CODE_SIZE,
+            "code_bits is {}, but this binary is built for {}-bit codes, see the `toy-codes` and \
+             `full-codes` features",
+            dataset.code_bits,
+            <IrisCode>::IRIS_CODE_SIZE
+        );
+        ensure!(
+            cfg!(feature = "parquet") || self.output.queries_parquet.is_none(),
+            "queries_parquet needs a build with the `parquet` feature"
+        );
+        ensure!(dataset.n_points > 0, "n_points must be positive");
+        ensure!(
+            dataset.random_queries > 0,
+            "random_queries must be positive"
+        );
+        ensure!(
+            dataset.random_queries <= dataset.n_points,
+            "random_queries ({}) exceeds n_points ({})",
+            dataset.random_queries,
+            dataset.n_points
+        );
+        ensure!(
+            (0.0..=1.0).contains(&dataset.mask_dropout),
+            "mask_dropout must be in [0, 1]"
+        );
+        ensure!(
+            (0.0..=1.0).contains(&self.noise.flip_probability),
+            "flip_probability must be in [0, 1]"
+        );
+        ensure!(
+            self.noise
+                .mask_flip_probability
+                .is_none_or(|p| (0.0..=1.0).contains(&p)),
+            "mask_flip_probability must be in [0, 1]"
+        );
+        ensure!(
+            (0.0..=1.0).contains(&self.noise.radial_shift_probability)
+                && (0.0..=1.0).contains(&self.noise.occlusion_probability),
+            "radial_shift_probability and occlusion_probability must be in [0, 1]"
+        );
+        ensure!(
+            (1..=MAX_NB_CONNECTION_LIMIT).contains(&hnsw.max_nb_connection),
+            "max_nb_connection must be in 1..={MAX_NB_CONNECTION_LIMIT}"
+        );
+        ensure!(
+            (1..=MAX_NB_LAYER).contains(&self.nb_layer()),
+            "nb_layer must be in 1..={MAX_NB_LAYER}"
+        );
+        ensure!(hnsw.ef_construction > 0, "ef_construction must be positive");
+        ensure!(hnsw.knbn > 0, "knbn must be positive");
+        ensure!(
+            hnsw.rerank_oversample != Some(0),
+            "rerank_oversample must be positive"
+        );
+        ensure!(
+            self.threads.build != Some(0) && self.threads.search != Some(0),
+            "thread counts must be positive"
+        );
+        ensure!(
+            hnsw.knbn <= dataset.n_points,
+            "knbn ({}) exceeds n_points ({})",
+            hnsw.knbn,
+            dataset.n_points
+        );
+        ensure!(
+            self.ef_search() >= hnsw.knbn,
+            "ef_search ({}) must be at least knbn ({})",
+            self.ef_search(),
+            hnsw.knbn
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,500 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use memmap2::Mmap;
+
+use crate::{
+    index::Id,
+    iris::{IrisCode, DEFAULT_IRIS_CODE_WORDS},
+    mask::CompressedMask,
+};
+
+/// First bytes of every dataset file.
+pub const DATASET_MAGIC: [u8; 4] = *b"IRDS";
+/// Version of the dataset encoding, see [`DatasetWriter`]. Version 1, which had no compressed
+/// masks, is still read.
+pub const DATASET_FORMAT_VERSION: u8 = 2;
+
+// magic, version, flags, two reserved bytes, code bits u32 LE, record count u64 LE
+const HEADER_LEN: usize = 20;
+const FLAG_IDS: u8 = 1;
+const FLAG_COMPRESSED_MASKS: u8 = 2;
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// What a dataset file holds, as given by its header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DatasetHeader {
+    pub code_bits: usize,
+    pub len: u64,
+    /// Whether every record carries an id, otherwise records are numbered from 0.
+    pub has_ids: bool,
+    /// Whether masks are stored as [`CompressedMask`]s.
+    pub compressed_masks: bool,
+}
+
+impl DatasetHeader {
+    fn encode(&self) -> [u8; HEADER_LEN] {
+        let mut bytes = [0; HEADER_LEN];
+        bytes[..4].copy_from_slice(&DATASET_MAGIC);
+        bytes[4] = DATASET_FORMAT_VERSION;
+        if self.has_ids {
+            bytes[5] |= FLAG_IDS;
+        }
+        if self.compressed_masks {
+            bytes[5] |= FLAG_COMPRESSED_MASKS;
+        }
+        bytes[8..12].copy_from_slice(&(self.code_bits as u32).to_le_bytes());
+        bytes[12..].copy_from_slice(&self.len.to_le_bytes());
+        bytes
+    }
+
+    fn decode(bytes: &[u8; HEADER_LEN]) -> io::Result<Self> {
+        if bytes[..4] != DATASET_MAGIC {
+            return Err(invalid_data("not an iris code dataset".to_string()));
+        }
+        if !(1..=DATASET_FORMAT_VERSION).contains(&bytes[4]) {
+            return Err(invalid_data(format!(
+                "unsupported dataset format version {}",
+                bytes[4]
+            )));
+        }
+        Ok(Self {
+            code_bits: u32::from_le_bytes(bytes[8..12].try_into().unwrap()) as usize,
+            len: u64::from_le_bytes(bytes[12..].try_into().unwrap()),
+            has_ids: bytes[5] & FLAG_IDS != 0,
+            compressed_masks: bytes[5] & FLAG_COMPRESSED_MASKS != 0,
+        })
+    }
+
+    /// Bytes per record following the header, `None` if masks are compressed, which makes the
+    /// size of every record differ.
+    pub fn record_len(&self) -> Option<usize> {
+        (!self.compressed_masks)
+            .then(|| usize::from(self.has_ids) * 8 + 2 * self.code_bits.div_ceil(8))
+    }
+}
+
+/// Writes a dataset file: a fixed header followed by one record per template, the id as u64 LE if
+/// the dataset has ids, then the code and the mask as raw little-endian words, see
+/// [`IrisCodeArray::as_raw_slice`](crate::iris::IrisCodeArray::as_raw_slice), without the
+/// version and width [`IrisCode::write_to`] starts with. With compressed masks, the mask is
+/// replaced by the length of its [`CompressedMask`] encoding as u32 LE and the encoding itself.
+/// The number of records is part of the header, so it has to be known upfront;
+/// [`DatasetWriter::finish`] checks that it was kept.
+pub struct DatasetWriter<W: Write, const WORDS: usize = DEFAULT_IRIS_CODE_WORDS> {
+    writer: BufWriter<W>,
+    header: DatasetHeader,
+    written: u64,
+}
+
+impl<W: Write, const WORDS: usize> DatasetWriter<W, WORDS> {
+    pub fn new(writer: W, len: u64, has_ids: bool) -> io::Result<Self> {
+        Self::with_header(writer, len, has_ids, false)
+    }
+
+    /// Like [`DatasetWriter::new`], but with compressed masks.
+    pub fn compressed(writer: W, len: u64, has_ids: bool) -> io::Result<Self> {
+        Self::with_header(writer, len, has_ids, true)
+    }
+
+    fn with_header(writer: W, len: u64, has_ids: bool, compressed_masks: bool) -> io::Result<Self> {
+        let header = DatasetHeader {
+            code_bits: IrisCode::<WORDS>::IRIS_CODE_SIZE,
+            len,
+            has_ids,
+            compressed_masks,
+        };
+        let mut writer = BufWriter::new(writer);
+        writer.write_all(&header.encode())?;
+        Ok(Self {
+            writer,
+            header,
+            written: 0,
+        })
+    }
+
+    /// Appends a record. `id` has to be given exactly if the dataset has ids.
+    pub fn write(&mut self, code: &IrisCode<WORDS>, id: Option<Id>) -> io::Result<()> {
+        if self.written == self.header.len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("dataset is declared with {} records", self.header.len),
+            ));
+        }
+        match (id, self.header.has_ids) {
+            (Some(id), true) => self.writer.write_all(&(id as u64).to_le_bytes())?,
+            (None, false) => {}
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "records need an id exactly if the dataset has ids",
+                ))
+            }
+        }
+        self.writer.write_all(code.code.as_raw_slice())?;
+        if self.header.compressed_masks {
+            let mask = CompressedMask::compress(&code.mask);
+            self.writer
+                .write_all(&(mask.as_bytes().len() as u32).to_le_bytes())?;
+            self.writer.write_all(mask.as_bytes())?;
+        } else {
+            self.writer.write_all(code.mask.as_raw_slice())?;
+        }
+        self.written += 1;
+        Ok(())
+    }
+
+    /// Flushes the records, failing if fewer were written than declared.
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.written != self.header.len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "dataset is declared with {} records, but {} were written",
+                    self.header.len, self.written
+                ),
+            ));
+        }
+        self.writer.flush()?;
+        self.writer.into_inner().map_err(|err| err.into_error())
+    }
+}
+
+fn check_code_bits<const WORDS: usize>(header: &DatasetHeader) -> io::Result<()> {
+    if header.code_bits != IrisCode::<WORDS>::IRIS_CODE_SIZE {
+        return Err(invalid_data(format!(
+            "dataset has {}-bit codes, expected {}",
+            header.code_bits,
+            IrisCode::<WORDS>::IRIS_CODE_SIZE
+        )));
+    }
+    Ok(())
+}
+
+/// Streams the records of a file written by [`DatasetWriter`], rejecting other versions and
+/// widths. Yields each template with its id, or its position if the dataset has no ids. A file
+/// cut short ends with an `UnexpectedEof` error, after which the reader is exhausted.
+pub struct DatasetReader<R: Read, const WORDS: usize = DEFAULT_IRIS_CODE_WORDS> {
+    reader: BufReader<R>,
+    header: DatasetHeader,
+    read: u64,
+}
+
+impl<R: Read, const WORDS: usize> DatasetReader<R, WORDS> {
+    pub fn new(reader: R) -> io::Result<Self> {
+        let mut reader = BufReader::new(reader);
+        let mut bytes = [0; HEADER_LEN];
+        reader.read_exact(&mut bytes)?;
+        let header = DatasetHeader::decode(&bytes)?;
+        check_code_bits::<WORDS>(&header)?;
+        Ok(Self {
+            reader,
+            header,
+            read: 0,
+        })
+    }
+
+    pub fn header(&self) -> DatasetHeader {
+        self.header
+    }
+
+    fn read_record(&mut self) -> io::Result<(IrisCode<WORDS>, Id)> {
+        let id = if self.header.has_ids {
+            let mut id = [0; 8];
+            self.reader.read_exact(&mut id)?;
+            u64::from_le_bytes(id) as Id
+        } else {
+            self.read as Id
+        };
+        let mut code = IrisCode::<WORDS>::default();
+        self.reader.read_exact(code.code.as_raw_mut_slice())?;
+        if self.header.compressed_masks {
+            let mut len = [0; 4];
+            self.reader.read_exact(&mut len)?;
+            let len = u32::from_le_bytes(len) as usize;
+            if len > CompressedMask::<WORDS>::MAX_LEN {
+                return Err(invalid_data(format!(
+                    "compressed mask of {len} bytes, at most {} expected",
+                    CompressedMask::<WORDS>::MAX_LEN
+                )));
+            }
+            let mut bytes = vec![0; len];
+            self.reader.read_exact(&mut bytes)?;
+            code.mask = CompressedMask::from_bytes(&bytes)
+                .map_err(|err| invalid_data(err.to_string()))?
+                .decompress();
+        } else {
+            self.reader.read_exact(code.mask.as_raw_mut_slice())?;
+        }
+        Ok((code, id))
+    }
+}
+
+impl<R: Read, const WORDS: usize> Iterator for DatasetReader<R, WORDS> {
+    type Item = io::Result<(IrisCode<WORDS>, Id)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.read == self.header.len {
+            return None;
+        }
+        let record = self.read_record();
+        self.read = if record.is_ok() {
+            self.read + 1
+        } else {
+            self.header.len
+        };
+        Some(record)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let left = (self.header.len - self.read) as usize;
+        (0, Some(left))
+    }
+}
+
+/// A dataset file mapped into memory rather than read, so its records can be accessed in any
+/// order without holding a copy of the file. Records are copied out one at a time on access.
+/// Masks must not be compressed, as records are found by their position. The file must not be
+/// modified while it is mapped.
+pub struct MappedDataset<const WORDS: usize = DEFAULT_IRIS_CODE_WORDS> {
+    mmap: Mmap,
+    header: DatasetHeader,
+    record_len: usize,
+}
+
+impl<const WORDS: usize> MappedDataset<WORDS> {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        // SAFETY: the mapping is only ever read, and callers must not modify the file meanwhile
+        let mmap = unsafe { Mmap::map(&file)? };
+        let bytes = mmap
+            .get(..HEADER_LEN)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+        let header = DatasetHeader::decode(bytes.try_into().unwrap())?;
+        check_code_bits::<WORDS>(&header)?;
+        let record_len = header.record_len().ok_or_else(|| {
+            invalid_data("datasets with compressed masks can't be mapped".to_string())
+        })?;
+        let records = (mmap.len() - HEADER_LEN) / record_len;
+        if (records as u64) < header.len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("dataset has {} records, expected {}", records, header.len),
+            ));
+        }
+        Ok(Self {
+            mmap,
+            header,
+            record_len,
+        })
+    }
+
+    pub fn header(&self) -> DatasetHeader {
+        self.header
+    }
+
+    pub fn len(&self) -> usize {
+        self.header.len as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Template and id of the record at `index`, see [`MappedDataset::id`]. Panics if `index` is
+    /// out of bounds.
+    pub fn get(&self, index: usize) -> (IrisCode<WORDS>, Id) {
+        let id = self.id(index);
+        let mut template = self.record(index);
+        if self.header.has_ids {
+            template = &template[8..];
+        }
+        let (code_bytes, mask_bytes) = template.split_at(template.len() / 2);
+        let mut code = IrisCode::<WORDS>::default();
+        code.code.as_raw_mut_slice().copy_from_slice(code_bytes);
+        code.mask.as_raw_mut_slice().copy_from_slice(mask_bytes);
+        (code, id)
+    }
+
+    /// Id of the record at `index`, which is `index` itself if the dataset has no ids.
+    pub fn id(&self, index: usize) -> Id {
+        if self.header.has_ids {
+            u64::from_le_bytes(self.record(index)[..8].try_into().unwrap()) as Id
+        } else {
+            index
+        }
+    }
+
+    fn record(&self, index: usize) -> &[u8] {
+        assert!(index < self.len(), "record {index} of {}", self.len());
+        let start = HEADER_LEN + index * self.record_len;
+        &self.mmap[start..start + self.record_len]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    type Reader<'a> = DatasetReader<&'a [u8], DEFAULT_IRIS_CODE_WORDS>;
+
+    fn records() -> Vec<(IrisCode, Id)> {
+        let mut rng = StdRng::seed_from_u64(0);
+        (0..5)
+            .map(|i| (IrisCode::random_rng(&mut rng), 1000 + 3 * i))
+            .collect()
+    }
+
+    fn write(records: &[(IrisCode, Id)], has_ids: bool, compressed: bool) -> Vec<u8> {
+        let len = records.len() as u64;
+        let mut writer = if compressed {
+            DatasetWriter::compressed(vec![], len, has_ids)
+        } else {
+            DatasetWriter::new(vec![], len, has_ids)
+        }
+        .unwrap();
+        for (code, id) in records {
+            writer.write(code, has_ids.then_some(*id)).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    // what a reader yields, ids replaced by positions if the dataset has none
+    fn expected(records: &[(IrisCode, Id)], has_ids: bool) -> Vec<(IrisCode, Id)> {
+        records
+            .iter()
+            .enumerate()
+            .map(|(i, (code, id))| (code.clone(), if has_ids { *id } else { i }))
+            .collect()
+    }
+
+    fn read_all(bytes: &[u8]) -> io::Result<Vec<(IrisCode, Id)>> {
+        Reader::new(bytes)?.collect()
+    }
+
+    #[test]
+    fn reader_round_trips() {
+        let records = records();
+        for has_ids in [false, true] {
+            let bytes = write(&records, has_ids, false);
+            assert_eq!(
+                Reader::new(&bytes).unwrap().header(),
+                DatasetHeader {
+                    code_bits: <IrisCode>::IRIS_CODE_SIZE,
+                    len: records.len() as u64,
+                    has_ids,
+                    compressed_masks: false,
+                }
+            );
+            assert_eq!(read_all(&bytes).unwrap(), expected(&records, has_ids));
+        }
+    }
+
+    #[test]
+    fn writer_keeps_the_declared_length() {
+        let records = records();
+        let mut writer = DatasetWriter::new(vec![], 1, true).unwrap();
+        writer.write(&records[0].0, None).unwrap_err();
+        writer.write(&records[0].0, Some(0)).unwrap();
+        writer.write(&records[1].0, Some(1)).unwrap_err();
+        writer.finish().unwrap();
+        let writer = DatasetWriter::<_, DEFAULT_IRIS_CODE_WORDS>::new(vec![], 2, false).unwrap();
+        writer.finish().unwrap_err();
+    }
+
+    #[test]
+    fn reader_reports_truncation() {
+        let records = records();
+        let bytes = write(&records, true, false);
+        let mut reader = Reader::new(&bytes[..bytes.len() - 1]).unwrap();
+        for _ in 0..records.len() - 1 {
+            reader.next().unwrap().unwrap();
+        }
+        let err = reader.next().unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn reader_rejects_other_widths() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut writer = DatasetWriter::new(vec![], 1, false).unwrap();
+        writer
+            .write(
+                &IrisCode::<4>::random_with_params(&mut rng, 0.1, true).unwrap(),
+                None,
+            )
+            .unwrap();
+        let bytes = writer.finish().unwrap();
+        let err = read_all(&bytes).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn compressed_reader_round_trips() {
+        let records = records();
+        for has_ids in [false, true] {
+            let bytes = write(&records, has_ids, true);
+            let header = Reader::new(&bytes).unwrap().header();
+            assert!(header.compressed_masks);
+            assert_eq!(header.record_len(), None);
+            assert_eq!(read_all(&bytes).unwrap(), expected(&records, has_ids));
+        }
+    }
+
+    #[test]
+    fn reader_rejects_oversized_compressed_masks() {
+        let records = records();
+        let mut bytes = write(&records[..1], false, true);
+        // the length of the first mask follows the header and the code
+        let at = HEADER_LEN + <IrisCode>::IRIS_CODE_SIZE / 8;
+        bytes[at..at + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        let err = read_all(&bytes).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn reads_version_1() {
+        let records = records();
+        let mut bytes = write(&records, true, false);
+        bytes[4] = 1;
+        assert_eq!(read_all(&bytes).unwrap(), expected(&records, true));
+        bytes[4] = DATASET_FORMAT_VERSION + 1;
+        let err = read_all(&bytes).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn mapped_round_trips() {
+        let records = records();
+        let dir = std::env::temp_dir();
+        for has_ids in [false, true] {
+            let path = dir.join(format!("dataset-{has_ids}-{}.bin", std::process::id()));
+            fs::write(&path, write(&records, has_ids, false)).unwrap();
+            let dataset = MappedDataset::<DEFAULT_IRIS_CODE_WORDS>::open(&path).unwrap();
+            assert_eq!(dataset.len(), records.len());
+            let read: Vec<_> = (0..dataset.len()).map(|i| dataset.get(i)).collect();
+            assert_eq!(read, expected(&records, has_ids));
+            drop(dataset);
+            fs::remove_file(&path).unwrap();
+        }
+
+        let path = dir.join(format!("dataset-compressed-{}.bin", std::process::id()));
+        fs::write(&path, write(&records, true, true)).unwrap();
+        let err = MappedDataset::<DEFAULT_IRIS_CODE_WORDS>::open(&path)
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,312 @@
+use std::{cell::Cell, sync::Arc};
+
+use anndists::dist::Distance;
+
+use crate::{
+    error::IrisError,
+    iris::{IrisCode, IrisCodeArray, DEFAULT_IRIS_CODE_WORDS},
+    simd,
+};
+
+thread_local! {
+    // hnsw_rs runs a single search on the calling thread, so per-thread counts give per-query
+    // eval counts even when queries run in parallel
+    static EVAL_COUNTER: Cell<usize> = const { Cell::new(0) };
+}
+
+pub fn thread_eval_count() -> usize {
+    EVAL_COUNTER.get()
+}
+
+/// Graph distance of templates whose masks don't overlap. NaN would break the ordering the graph
+/// relies on, so they count as maximally distant instead.
+pub const EMPTY_OVERLAP_DISTANCE: f32 = 1.0;
+
+fn or_empty_overlap(distance: f64) -> f32 {
+    if distance.is_nan() {
+        EMPTY_OVERLAP_DISTANCE
+    } else {
+        distance as f32
+    }
+}
+
+/// Distance functions an index can be built with.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum DistanceKind {
+    /// Fractional Hamming distance over the bits both masks mark valid.
+    #[default]
+    MaskedHamming,
+    /// Minimum masked Hamming distance over angular shifts of up to `max_shift` columns.
+    RotatedMaskedHamming { max_shift: usize },
+    /// Fraction of differing code bits, ignoring the masks.
+    Hamming,
+    /// Masked Hamming distance with every bit counting by its weight.
+    WeightedMaskedHamming(BitWeights),
+    /// Jaccard distance between the set code bits within the common mask.
+    Jaccard,
+}
+
+impl DistanceKind {
+    /// The distance between two templates, as the graph computes it.
+    pub fn eval(&self, a: &IrisCode, b: &IrisCode) -> f64 {
+        match self {
+            Self::MaskedHamming => a.get_distance(b),
+            Self::RotatedMaskedHamming { max_shift } => a.get_distance_rotated(b, *max_shift),
+            Self::Hamming => a.hamming_distance(b),
+            Self::WeightedMaskedHamming(weights) => a.get_weighted_distance(b, weights),
+            Self::Jaccard => a.get_jaccard_distance(b),
+        }
+    }
+}
+
+/// Per-bit weights of [`IrisCode::get_weighted_distance`], e.g. learned bit reliabilities. Cheap
+/// to clone.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BitWeights(Arc<[f32]>);
+
+impl BitWeights {
+    /// Fails unless there is one finite, non-negative weight per code bit.
+    pub fn new(weights: Vec<f32>) -> Result<Self, IrisError> {
+        if weights.len() != <IrisCode>::IRIS_CODE_SIZE {
+            return Err(IrisError::InvalidLength {
+                expected: <IrisCode>::IRIS_CODE_SIZE,
+                actual: weights.len(),
+            });
+        }
+        if let Some((bit, &weight)) = weights
+            .iter()
+            .enumerate()
+            .find(|(_, weight)| !(weight.is_finite() && **weight >= 0.0))
+        {
+            return Err(IrisError::InvalidWeight { bit, weight });
+        }
+        Ok(Self(weights.into()))
+    }
+
+    pub fn as_slice(&self) -> &[f32] {
+        &self.0
+    }
+
+    /// Total weight of the set bits of `bits`.
+    pub fn sum_over(&self, bits: &IrisCodeArray) -> f64 {
+        let mut sum = 0.0;
+        for (i, &word) in bits.0.iter().enumerate() {
+            let mut word = word;
+            while word != 0 {
+                sum += f64::from(self.0[i * 64 + word.trailing_zeros() as usize]);
+                word &= word - 1;
+            }
+        }
+        sum
+    }
+}
+
+/// Distance of the index graph, dispatching on its [`DistanceKind`].
+#[derive(Clone, Default)]
+pub struct HD {
+    kind: DistanceKind,
+    min_overlap: u32,
+}
+
+impl HD {
+    pub fn new(kind: DistanceKind) -> Self {
+        Self {
+            kind,
+            min_overlap: 0,
+        }
+    }
+
+    /// Saturates [`DistanceKind::MaskedHamming`] to 1.0 below `min_overlap` common mask bits, see
+    /// [`IrisCode::get_distance_with_min_overlap`].
+    pub fn with_min_overlap(mut self, min_overlap: u32) -> Self {
+        self.min_overlap = min_overlap;
+        self
+    }
+
+    /// Like [`HD::eval_codes`], given `mask.count_ones()` of both templates, see
+    /// [`IrisCode::get_distance_fraction_cached`].
+    pub fn eval_codes_cached(
+        &self,
+        a: &IrisCode,
+        a_mask_ones: u32,
+        b: &IrisCode,
+        b_mask_ones: u32,
+    ) -> f64 {
+        match self.kind {
+            DistanceKind::MaskedHamming => {
+                let (code_distance, combined_mask_len) =
+                    a.get_distance_fraction_cached(b, a_mask_ones, b_mask_ones);
+                fraction_distance(code_distance, combined_mask_len, self.min_overlap)
+            }
+            _ => self.eval_codes(a, b),
+        }
+    }
+
+    /// Like [`HD::eval_codes_cached`], but may stop counting once the distance is certain to
+    /// exceed `bound`, see [`IrisCode::get_distance_with_bound`].
+    pub fn eval_codes_bounded(
+        &self,
+        a: &IrisCode,
+        a_mask_ones: u32,
+        b: &IrisCode,
+        b_mask_ones: u32,
+        bound: f64,
+    ) -> f64 {
+        if matches!(self.kind, DistanceKind::MaskedHamming)
+            && self.min_overlap == 0
+            && bound.is_finite()
+        {
+            return a.get_distance_with_bound(b, bound);
+        }
+        self.eval_codes_cached(a, a_mask_ones, b, b_mask_ones)
+    }
+
+    /// The distance between two templates, as [`Distance::eval`] computes it on merged vectors.
+    pub fn eval_codes(&self, a: &IrisCode, b: &IrisCode) -> f64 {
+        let distance = match self.kind {
+            DistanceKind::MaskedHamming => a.get_distance_with_min_overlap(b, self.min_overlap),
+            _ => self.kind.eval(a, b),
+        };
+        if distance.is_nan() {
+            f64::from(EMPTY_OVERLAP_DISTANCE)
+        } else {
+            distance
+        }
+    }
+}
+
+impl Distance<u64> for HD {
+    fn eval(&self, va: &[u64], vb: &[u64]) -> f32 {
+        match &self.kind {
+            DistanceKind::MaskedHamming => masked_hamming(va, vb, self.min_overlap),
+            DistanceKind::RotatedMaskedHamming { max_shift } => RotatedHD {
+                max_shift: *max_shift,
+            }
+            .eval(va, vb),
+            DistanceKind::Hamming => PlainHD.eval(va, vb),
+            DistanceKind::WeightedMaskedHamming(weights) => {
+                weighted_masked_hamming(va, vb, weights)
+            }
+            DistanceKind::Jaccard => JaccardHD.eval(va, vb),
+        }
+    }
+}
+
+fn masked_hamming(va: &[u64], vb: &[u64], min_overlap: u32) -> f32 {
+    EVAL_COUNTER.set(EVAL_COUNTER.get() + 1);
+    // every vector reaches the graph as a merged IrisCode, so the halves always have this
+    // length; working on the slices avoids copying full-size codes on every eval
+    assert!(
+        va.len() == 2 * DEFAULT_IRIS_CODE_WORDS && vb.len() == 2 * DEFAULT_IRIS_CODE_WORDS,
+        "graph vectors are merged codes"
+    );
+    let (code1, mask1) = va.split_at(DEFAULT_IRIS_CODE_WORDS);
+    let (code2, mask2) = vb.split_at(DEFAULT_IRIS_CODE_WORDS);
+
+    let (code_distance, combined_mask_len) = simd::masked_hamming(code1, mask1, code2, mask2);
+    fraction_distance(code_distance, combined_mask_len, min_overlap) as f32
+}
+
+fn fraction_distance(code_distance: u32, combined_mask_len: u32, min_overlap: u32) -> f64 {
+    if combined_mask_len == 0 {
+        return f64::from(EMPTY_OVERLAP_DISTANCE);
+    }
+    if combined_mask_len < min_overlap {
+        return 1.0;
+    }
+    code_distance as f64 / combined_mask_len as f64
+}
+
+/// Rotation-tolerant variant of [`HD`], see [`IrisCode::get_distance_rotated`].
+pub struct RotatedHD {
+    pub max_shift: usize,
+}
+
+impl Distance<u64> for RotatedHD {
+    fn eval(&self, va: &[u64], vb: &[u64]) -> f32 {
+        EVAL_COUNTER.set(EVAL_COUNTER.get() + 1);
+        let (code1, code2) = merged_codes(va, vb);
+        or_empty_overlap(code1.get_distance_rotated(&code2, self.max_shift))
+    }
+}
+
+/// Weighted variant of [`HD`], see [`IrisCode::get_weighted_distance`].
+pub struct WeightedHD {
+    pub weights: BitWeights,
+}
+
+impl Distance<u64> for WeightedHD {
+    fn eval(&self, va: &[u64], vb: &[u64]) -> f32 {
+        weighted_masked_hamming(va, vb, &self.weights)
+    }
+}
+
+fn weighted_masked_hamming(va: &[u64], vb: &[u64], weights: &BitWeights) -> f32 {
+    EVAL_COUNTER.set(EVAL_COUNTER.get() + 1);
+    let (code1, code2) = merged_codes(va, vb);
+    or_empty_overlap(code1.get_weighted_distance(&code2, weights))
+}
+
+/// Jaccard distance over the masked bits, see [`IrisCode::get_jaccard_distance`].
+pub struct JaccardHD;
+
+impl Distance<u64> for JaccardHD {
+    fn eval(&self, va: &[u64], vb: &[u64]) -> f32 {
+        EVAL_COUNTER.set(EVAL_COUNTER.get() + 1);
+        let (code1, code2) = merged_codes(va, vb);
+        or_empty_overlap(code1.get_jaccard_distance(&code2))
+    }
+}
+
+fn merged_codes(va: &[u64], vb: &[u64]) -> (IrisCode, IrisCode) {
+    let code1 = IrisCode::from_merged_slice(va).expect("graph vectors are merged codes");
+    let code2 = IrisCode::from_merged_slice(vb).expect("graph vectors are merged codes");
+    (code1, code2)
+}
+
+/// Hamming distance over the code bits only, see [`IrisCode::hamming_distance`].
+pub struct PlainHD;
+
+impl Distance<u64> for PlainHD {
+    fn eval(&self, va: &[u64], vb: &[u64]) -> f32 {
+        EVAL_COUNTER.set(EVAL_COUNTER.get() + 1);
+        let code_distance: u32 = va[..DEFAULT_IRIS_CODE_WORDS]
+            .iter()
+            .zip(&vb[..DEFAULT_IRIS_CODE_WORDS])
+            .map(|(c1, c2)| (c1 ^ c2).count_ones())
+            .sum();
+        (code_distance as f64 / <IrisCode>::IRIS_CODE_SIZE as f64) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bit_weights_must_be_finite_and_non_negative() {
+        let size = <IrisCode>::IRIS_CODE_SIZE;
+        assert!(BitWeights::new(vec![0.0; size]).is_ok());
+        assert_eq!(
+            BitWeights::new(vec![1.0; size - 1]),
+            Err(IrisError::InvalidLength {
+                expected: size,
+                actual: size - 1
+            })
+        );
+        for weight in [-1.0, f32::INFINITY, f32::NEG_INFINITY] {
+            let mut weights = vec![1.0; size];
+            weights[7] = weight;
+            assert_eq!(
+                BitWeights::new(weights),
+                Err(IrisError::InvalidWeight { bit: 7, weight })
+            );
+        }
+        let mut weights = vec![1.0; size];
+        weights[3] = f32::NAN;
+        assert!(matches!(
+            BitWeights::new(weights),
+            Err(IrisError::InvalidWeight { bit: 3, .. })
+        ));
+    }
+}
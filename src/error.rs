@@ -0,0 +1,69 @@
+use alloc::string::String;
+use core::fmt;
+
+/// Errors from malformed input to the library's entry points.
+#[derive(Clone, Debug, PartialEq)]
+pub enum IrisError {
+    /// Raw code, mask or vector data of the wrong size.
+    InvalidLength { expected: usize, actual: usize },
+    /// A probability outside of [0, 1].
+    InvalidProbability(f64),
+    /// A bit weight that is negative, infinite or NaN.
+    InvalidWeight { bit: usize, weight: f32 },
+    /// Text that isn't valid in the expected encoding, e.g. base64.
+    InvalidEncoding(String),
+    /// A template layout that doesn't cover exactly the bits of its code.
+    LayoutMismatch {
+        layout_bits: usize,
+        code_bits: usize,
+    },
+    /// A template with fewer usable mask bits than required.
+    InsufficientMask { usable: usize, required: usize },
+    /// A mask whose bits `bit` and `bit + 1` differ, although they should be duplicates.
+    UnpairedMask { bit: usize },
+    /// A template whose quality score is below the required one.
+    LowQuality { score: f64, required: f64 },
+    /// A metadata key under the prefix reserved for the library's own data.
+    ReservedMetadataKey(String),
+    /// An id that doesn't fit in `usize` once `offset` is added, see `IdRemap`.
+    IdOverflow { id: usize, offset: usize },
+}
+
+impl fmt::Display for IrisError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidLength { expected, actual } => {
+                write!(f, "expected {expected} elements, got {actual}")
+            }
+            Self::InvalidProbability(p) => write!(f, "probability {p} is not in [0, 1]"),
+            Self::InvalidWeight { bit, weight } => {
+                write!(
+                    f,
+                    "weight {weight} of bit {bit} is not a finite non-negative number"
+                )
+            }
+            Self::InvalidEncoding(err) => write!(f, "invalid encoding: {err}"),
+            Self::LayoutMismatch {
+                layout_bits,
+                code_bits,
+            } => write!(
+                f,
+                "layout covers {layout_bits} bits, but the code has {code_bits}"
+            ),
+            Self::InsufficientMask { usable, required } => write!(
+                f,
+                "mask has {usable} usable bits, at least {required} are required"
+            ),
+            Self::UnpairedMask { bit } => {
+                write!(f, "mask bits {bit} and {} differ", bit + 1)
+            }
+            Self::LowQuality { score, required } => {
+                write!(f, "template quality {score:.3} is below {required}")
+            }
+            Self::ReservedMetadataKey(key) => write!(f, "metadata key {key:?} is reserved"),
+            Self::IdOverflow { id, offset } => write!(f, "id {id} plus {offset} overflows"),
+        }
+    }
+}
+
+impl core::error::Error for IrisError {}
@@ -0,0 +1,1791 @@
+use std::{
+    borrow::Cow,
+    collections::{hash_map::RandomState, BTreeMap, HashMap, HashSet},
+    fs,
+    hash::BuildHasher,
+    ops::Range,
+    path::Path,
+    ptr::NonNull,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    },
+};
+
+use anyhow::{ensure, Context};
+use hnsw_rs::{
+    api::AnnT,
+    hnsw::Hnsw,
+    hnswio::{HnswIo, ReloadOptions},
+};
+use rayon::{
+    iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator},
+    ThreadPool,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    dataset::MappedDataset,
+    distance::{thread_eval_count, DistanceKind, HD},
+    error::IrisError,
+    iris::{IrisCode, IrisCodeArray, ValidationRules, MATCH_THRESHOLD_RATIO},
+    mask::CompressedMask,
+    observer::IndexObserver,
+    snapshot::IndexSnapshot,
+};
+
+// hnsw_rs caps the number of layers at 16 and stores the connection count as u8
+pub const MAX_NB_LAYER: usize = 16;
+pub const MAX_NB_CONNECTION_LIMIT: usize = 255;
+
+// sidecar written next to the hnsw_rs files, one (point u64 LE, id u64 LE, live u8) per point
+const ENTRIES_EXTENSION: &str = "entries";
+const ENTRY_RECORD_LEN: usize = 17;
+// JSON object from id to metadata, for entries that have any
+const METADATA_EXTENSION: &str = "metadata.json";
+// JSON of the IndexSettings, which hnsw_rs doesn't keep
+const SETTINGS_EXTENSION: &str = "settings.json";
+
+/// Version of the files written by [`IrisHnsw::dump`]. [`IrisHnsw::load`] reads every version up
+/// to this one, so an old index is migrated by loading and dumping it again.
+pub const INDEX_FORMAT_VERSION: u32 = 2;
+// everything dumped before the version was recorded, with or without the entries sidecar
+const UNVERSIONED_FORMAT_VERSION: u32 = 1;
+
+const IRIS_CODE_MERGED_BYTES: usize = 2 * <IrisCodeArray>::IRIS_CODE_SIZE_BYTES;
+
+// initial allocation of indexes built without a capacity, the graph grows past it as needed
+const INITIAL_CAPACITY: usize = 1 << 16;
+
+// beam width of the first search_within round
+const SEARCH_WITHIN_INITIAL_K: usize = 16;
+
+/// Caller-chosen identifier of an indexed template.
+pub type Id = usize;
+
+/// Free-form attributes of an entry, e.g. enrollment date or source, usable in
+/// [`IrisHnsw::search_filtered`].
+pub type Metadata = BTreeMap<String, String>;
+
+/// Prefix of the metadata keys the library keeps its own data under, e.g. the payloads of
+/// [`PayloadIndex`](crate::PayloadIndex). Metadata passed in with such a key is rejected.
+pub const RESERVED_METADATA_PREFIX: &str = "hnsw_iris.";
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SearchHit {
+    pub id: Id,
+    pub distance: f32,
+    /// `distance` is below the index's match threshold.
+    pub is_match: bool,
+}
+
+/// Outcome of [`IrisHnsw::is_unique`].
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct UniquenessResult {
+    /// No entry matches under the index's match threshold.
+    pub unique: bool,
+    /// Nearest entry found, `None` only for an empty index.
+    pub closest: Option<SearchHit>,
+}
+
+/// How [`IrisHnsw::merge`] assigns ids to the merged entries.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IdRemap {
+    /// Keep the ids; an entry of the other index replaces one with the same id.
+    #[default]
+    Keep,
+    /// Add a fixed offset, e.g. the id range start of the shard.
+    Offset(Id),
+}
+
+impl IdRemap {
+    pub fn apply(self, id: Id) -> Result<Id, IrisError> {
+        match self {
+            Self::Keep => Ok(id),
+            Self::Offset(offset) => id
+                .checked_add(offset)
+                .ok_or(IrisError::IdOverflow { id, offset }),
+        }
+    }
+}
+
+/// Shape and size of an [`IrisHnsw`], see [`IrisHnsw::stats`].
+#[derive(Clone, Debug, Serialize)]
+pub struct IndexStats {
+    pub live_entries: usize,
+    pub nb_points: usize,
+    /// Points present on each layer, from layer 0 (all points) upwards.
+    pub points_per_layer: Vec<usize>,
+    /// Out-degree of points on layer 0.
+    pub degree: DegreeStats,
+    /// Estimated bytes held by vectors, edges and the template store.
+    pub vector_bytes: usize,
+    pub edge_bytes: usize,
+    pub payload_bytes: usize,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct DegreeStats {
+    pub mean: f64,
+    pub p50: usize,
+    pub p90: usize,
+    pub p99: usize,
+    pub max: usize,
+}
+
+impl DegreeStats {
+    fn from_degrees(mut degrees: Vec<usize>) -> Self {
+        if degrees.is_empty() {
+            return Self::default();
+        }
+        degrees.sort_unstable();
+        let percentile = |p: usize| degrees[(degrees.len() - 1) * p / 100];
+        Self {
+            mean: degrees.iter().sum::<usize>() as f64 / degrees.len() as f64,
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+            max: degrees[degrees.len() - 1],
+        }
+    }
+}
+
+/// HNSW graph over masked Hamming distance between iris codes.
+pub struct IrisHnsw {
+    hnsw: Hnsw<'static, u64, HD>,
+    ef_search: usize,
+    match_threshold: f64,
+    // serializes insert_unique, so its check and insert can't interleave with another one
+    enroll: Mutex<()>,
+    // held shared while points are linked into the graph and exclusively while it is dumped, as
+    // the graph files can't be written consistently while it changes
+    graph: RwLock<()>,
+    entries: RwLock<Entries>,
+    // dedicated pools for batch inserts and searches, the global rayon pool otherwise
+    build_pool: Option<Arc<ThreadPool>>,
+    search_pool: Option<Arc<ThreadPool>>,
+    observers: Vec<Arc<dyn IndexObserver>>,
+    // evaluates exact distances the way the graph does
+    distance: HD,
+    // rotations stored per entry on either side, see IrisHnswBuilder::rotation_expansion
+    rotation_shift: usize,
+    validation: ValidationRules,
+    // keep the masks of entries compressed, see IrisHnswBuilder::compress_masks
+    compress_masks: bool,
+    // what a reloaded graph borrows from, declared after it so that it is dropped after it
+    loader: Option<Loader>,
+}
+
+// owns the HnswIo a reloaded graph borrows its mapping and buffers from, for as long as the graph
+struct Loader(NonNull<HnswIo>);
+
+impl Loader {
+    fn new(hnswio: HnswIo) -> Self {
+        Self(NonNull::from(Box::leak(Box::new(hnswio))))
+    }
+
+    // SAFETY: the graph loaded through the reference must be dropped before the Loader
+    unsafe fn get(&self) -> &'static mut HnswIo {
+        &mut *self.0.as_ptr()
+    }
+}
+
+impl Drop for Loader {
+    fn drop(&mut self) {
+        // SAFETY: allocated by Loader::new, and the graph borrowing it is gone
+        drop(unsafe { Box::from_raw(self.0.as_ptr()) });
+    }
+}
+
+// SAFETY: the HnswIo is only touched again to be dropped
+unsafe impl Send for Loader {}
+unsafe impl Sync for Loader {}
+
+/// Settings of an [`IrisHnsw`] that are dumped with it, so a reloaded index searches and validates
+/// like the one that was dumped.
+#[derive(Serialize, Deserialize)]
+struct IndexSettings {
+    #[serde(default = "unversioned")]
+    format_version: u32,
+    /// Unknown for unversioned indexes.
+    #[serde(default)]
+    code_bits: Option<usize>,
+    ef_search: usize,
+    match_threshold: f64,
+    validation: ValidationRules,
+    #[serde(default)]
+    compress_masks: bool,
+}
+
+fn unversioned() -> u32 {
+    UNVERSIONED_FORMAT_VERSION
+}
+
+impl IndexSettings {
+    /// The settings dumped with the index, if it has any, failing for versions and code widths
+    /// this build can't read.
+    fn read(dir: &Path, basename: &str) -> anyhow::Result<Option<Self>> {
+        let path = dir.join(format!("{basename}.{SETTINGS_EXTENSION}"));
+        if !path.exists() {
+            return Ok(None);
+        }
+        let json = fs::read(&path).with_context(|| format!("reading {}", path.display()))?;
+        let settings: Self =
+            serde_json::from_slice(&json).with_context(|| format!("parsing {}", path.display()))?;
+        ensure!(
+            settings.format_version <= INDEX_FORMAT_VERSION,
+            "index in {} has format version {}, this build reads up to {INDEX_FORMAT_VERSION}",
+            dir.display(),
+            settings.format_version
+        );
+        if let Some(code_bits) = settings.code_bits {
+            ensure!(
+                code_bits == <IrisCode>::IRIS_CODE_SIZE,
+                "index in {} has {code_bits}-bit codes, this build uses {}",
+                dir.display(),
+                <IrisCode>::IRIS_CODE_SIZE
+            );
+        }
+        Ok(Some(settings))
+    }
+}
+
+/// Ids of the entries per template fingerprint, to find byte-identical templates without a graph
+/// search. Fingerprints can collide, so candidates have to be compared with the template.
+#[derive(Default)]
+pub struct DuplicateSet {
+    hasher: RandomState,
+    ids: HashMap<u64, Vec<Id>>,
+}
+
+impl DuplicateSet {
+    pub fn insert(&mut self, code: &IrisCode, id: Id) {
+        let fingerprint = self.hasher.hash_one(code);
+        self.ids.entry(fingerprint).or_default().push(id);
+    }
+
+    pub fn remove(&mut self, code: &IrisCode, id: Id) {
+        let fingerprint = self.hasher.hash_one(code);
+        if let Some(ids) = self.ids.get_mut(&fingerprint) {
+            ids.retain(|&other| other != id);
+            if ids.is_empty() {
+                self.ids.remove(&fingerprint);
+            }
+        }
+    }
+
+    /// Ids whose template may equal `code`.
+    pub fn candidates(&self, code: &IrisCode) -> &[Id] {
+        self.ids
+            .get(&self.hasher.hash_one(code))
+            .map_or(&[], Vec::as_slice)
+    }
+}
+
+/// Maps graph points to caller ids and keeps the live templates. Removed and replaced entries
+/// stay in the graph until [`IrisHnsw::compact`], so a point only counts if it is still a live
+/// point of its id.
+#[derive(Default)]
+struct Entries {
+    points: HashMap<usize, PointInfo>,
+    live: HashMap<Id, Entry>,
+    next_point: usize,
+    // points of live entries, an entry has several with rotation expansion
+    live_points: usize,
+    // bumped by every publish and removal, snapshots see the state as of one epoch
+    epoch: u64,
+    duplicates: DuplicateSet,
+}
+
+struct PointInfo {
+    id: Id,
+    published: Option<u64>,
+    retired: Option<u64>,
+}
+
+struct Entry {
+    // the point of the template itself first, followed by those of its rotations
+    points: Vec<usize>,
+    code: StoredCode,
+    // saves counting the stored mask again for every exact distance
+    mask_ones: u32,
+    metadata: Metadata,
+}
+
+// template of an entry, which the graph keeps a copy of as well
+enum StoredCode {
+    Plain(IrisCode),
+    Compressed {
+        code: IrisCodeArray,
+        mask: CompressedMask,
+    },
+    // record of a dataset mapped by the caller, read in place
+    Mapped {
+        dataset: Arc<MappedDataset>,
+        record: usize,
+    },
+}
+
+impl StoredCode {
+    fn new(code: IrisCode, compress_mask: bool) -> Self {
+        if compress_mask {
+            Self::Compressed {
+                mask: CompressedMask::compress(&code.mask),
+                code: code.code,
+            }
+        } else {
+            Self::Plain(code)
+        }
+    }
+
+    fn get(&self) -> Cow<'_, IrisCode> {
+        match self {
+            Self::Plain(code) => Cow::Borrowed(code),
+            Self::Compressed { code, mask } => Cow::Owned(IrisCode::new(*code, mask.decompress())),
+            Self::Mapped { dataset, record } => Cow::Owned(dataset.get(*record).0),
+        }
+    }
+
+    fn bytes(&self) -> usize {
+        match self {
+            Self::Plain(_) => size_of::<IrisCode>(),
+            Self::Mapped { .. } => 0,
+            Self::Compressed { mask, .. } => size_of::<IrisCodeArray>() + mask.as_bytes().len(),
+        }
+    }
+}
+
+impl Entries {
+    /// `codes` holds the templates of the graph points, from which the live ones are taken.
+    fn from_points(
+        points: impl IntoIterator<Item = (usize, Id, bool)>,
+        codes: &mut HashMap<usize, IrisCode>,
+        compress_masks: bool,
+    ) -> anyhow::Result<Self> {
+        let mut entries = Self::default();
+        let mut live_points: HashMap<Id, Vec<usize>> = HashMap::new();
+        for (point, id, live) in points {
+            entries.points.insert(
+                point,
+                PointInfo {
+                    id,
+                    published: live.then_some(0),
+                    retired: None,
+                },
+            );
+            if live {
+                live_points.entry(id).or_default().push(point);
+            }
+            entries.next_point = entries.next_point.max(point + 1);
+        }
+        for (id, mut points) in live_points {
+            // a template is inserted before its rotations, so it has the lowest point
+            points.sort_unstable();
+            let code = codes.remove(&points[0]).with_context(|| {
+                format!("point {} of id {id} is missing from the graph", points[0])
+            })?;
+            let metadata = Metadata::new();
+            entries.live_points += points.len();
+            entries.duplicates.insert(&code, id);
+            entries.live.insert(
+                id,
+                Entry {
+                    points,
+                    mask_ones: code.mask.count_ones() as u32,
+                    code: StoredCode::new(code, compress_masks),
+                    metadata,
+                },
+            );
+        }
+        Ok(entries)
+    }
+
+    fn is_live(&self, id: Id, point: usize) -> bool {
+        self.live
+            .get(&id)
+            .is_some_and(|entry| entry.points.contains(&point))
+    }
+
+    /// Most points any live entry has, 1 unless rotation expansion was used.
+    fn max_points_per_entry(&self) -> usize {
+        self.live
+            .values()
+            .map(|entry| entry.points.len())
+            .max()
+            .unwrap_or(1)
+    }
+
+    fn live_id(&self, point: usize) -> Option<Id> {
+        let id = self.points.get(&point)?.id;
+        self.is_live(id, point).then_some(id)
+    }
+
+    fn id_at(&self, point: usize, epoch: u64) -> Option<Id> {
+        let info = self.points.get(&point)?;
+        let visible = info.published.is_some_and(|published| published <= epoch)
+            && info.retired.is_none_or(|retired| retired > epoch);
+        visible.then_some(info.id)
+    }
+
+    fn stale(&self) -> usize {
+        self.points.len() - self.live_points
+    }
+
+    /// Makes the points of `entry` the live points of `id`, retiring the previous ones.
+    fn publish(&mut self, id: Id, entry: Entry) -> Option<Entry> {
+        self.epoch += 1;
+        let previous = self.retire(id);
+        for point in &entry.points {
+            if let Some(info) = self.points.get_mut(point) {
+                info.published = Some(self.epoch);
+            }
+        }
+        self.live_points += entry.points.len();
+        self.duplicates.insert(&entry.code.get(), id);
+        self.live.insert(id, entry);
+        previous
+    }
+
+    fn remove(&mut self, id: Id) -> Option<Entry> {
+        self.epoch += 1;
+        self.retire(id)
+    }
+
+    fn retire(&mut self, id: Id) -> Option<Entry> {
+        let previous = self.live.remove(&id)?;
+        for point in &previous.points {
+            if let Some(info) = self.points.get_mut(point) {
+                info.retired = Some(self.epoch);
+            }
+        }
+        self.live_points -= previous.points.len();
+        self.duplicates.remove(&previous.code.get(), id);
+        Some(previous)
+    }
+
+    fn find_duplicate(&self, code: &IrisCode) -> Option<(Id, &Entry)> {
+        self.duplicates.candidates(code).iter().find_map(|&id| {
+            let entry = &self.live[&id];
+            (*entry.code.get() == *code).then_some((id, entry))
+        })
+    }
+
+    fn read(
+        path: &Path,
+        codes: &mut HashMap<usize, IrisCode>,
+        compress_masks: bool,
+    ) -> anyhow::Result<Self> {
+        let bytes = fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+        ensure!(
+            bytes.len() % ENTRY_RECORD_LEN == 0,
+            "truncated entries file {}",
+            path.display()
+        );
+        let points = bytes.chunks_exact(ENTRY_RECORD_LEN).map(|record| {
+            let point = u64::from_le_bytes(record[0..8].try_into().unwrap()) as usize;
+            let id = u64::from_le_bytes(record[8..16].try_into().unwrap()) as Id;
+            (point, id, record[16] != 0)
+        });
+        Self::from_points(points, codes, compress_masks)
+    }
+
+    fn read_metadata(&mut self, path: &Path) -> anyhow::Result<()> {
+        let json = fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+        let metadata: HashMap<Id, Metadata> =
+            serde_json::from_slice(&json).with_context(|| format!("parsing {}", path.display()))?;
+        for (id, metadata) in metadata {
+            if let Some(entry) = self.live.get_mut(&id) {
+                entry.metadata = metadata;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_metadata(&self, path: &Path) -> anyhow::Result<()> {
+        let metadata: HashMap<Id, &Metadata> = self
+            .live
+            .iter()
+            .filter(|(_, entry)| !entry.metadata.is_empty())
+            .map(|(&id, entry)| (id, &entry.metadata))
+            .collect();
+        fs::write(path, serde_json::to_vec(&metadata)?)
+            .with_context(|| format!("writing {}", path.display()))
+    }
+
+    fn write(&self, path: &Path) -> anyhow::Result<()> {
+        let mut bytes = Vec::with_capacity(self.points.len() * ENTRY_RECORD_LEN);
+        for (&point, &PointInfo { id, .. }) in &self.points {
+            bytes.extend((point as u64).to_le_bytes());
+            bytes.extend((id as u64).to_le_bytes());
+            bytes.push(u8::from(self.is_live(id, point)));
+        }
+        fs::write(path, bytes).with_context(|| format!("writing {}", path.display()))
+    }
+}
+
+fn in_pool<R: Send>(pool: Option<&ThreadPool>, op: impl FnOnce() -> R + Send) -> R {
+    match pool {
+        Some(pool) => pool.install(op),
+        None => op(),
+    }
+}
+
+/// Layer count suited to `capacity` points: ln(capacity), clamped to 1..=16.
+pub fn default_nb_layer(capacity: usize) -> usize {
+    MAX_NB_LAYER
+        .min((capacity as f32).ln().trunc() as usize)
+        .max(1)
+}
+
+/// Construction parameters of an [`IrisHnsw`], defaulting to M = ef_construction = 128 and a layer
+/// count derived from the capacity, or all 16 layers when the final size isn't known.
+#[derive(Clone)]
+pub struct IrisHnswBuilder {
+    max_nb_connection: usize,
+    ef_construction: usize,
+    ef_search: Option<usize>,
+    match_threshold: f64,
+    nb_layer: Option<usize>,
+    capacity: Option<usize>,
+    distance: DistanceKind,
+    min_overlap: u32,
+    rotation_shift: usize,
+    validation: ValidationRules,
+    compress_masks: bool,
+    build_pool: Option<Arc<ThreadPool>>,
+    search_pool: Option<Arc<ThreadPool>>,
+    observers: Vec<Arc<dyn IndexObserver>>,
+}
+
+impl Default for IrisHnswBuilder {
+    fn default() -> Self {
+        Self {
+            max_nb_connection: 128,
+            ef_construction: 128,
+            ef_search: None,
+            match_threshold: MATCH_THRESHOLD_RATIO,
+            nb_layer: None,
+            capacity: None,
+            distance: DistanceKind::default(),
+            min_overlap: 0,
+            rotation_shift: 0,
+            validation: ValidationRules::default(),
+            compress_masks: false,
+            build_pool: None,
+            search_pool: None,
+            observers: vec![],
+        }
+    }
+}
+
+impl IrisHnswBuilder {
+    /// Maximum number of connections per node (M).
+    pub fn max_nb_connection(mut self, max_nb_connection: usize) -> Self {
+        self.max_nb_connection = max_nb_connection;
+        self
+    }
+
+    /// Beam width used during construction.
+    pub fn ef_construction(mut self, ef_construction: usize) -> Self {
+        self.ef_construction = ef_construction;
+        self
+    }
+
+    /// Default beam width of searches, ef_construction when unset.
+    pub fn ef_search(mut self, ef_search: usize) -> Self {
+        self.ef_search = Some(ef_search);
+        self
+    }
+
+    /// Fractional distance below which hits count as matches, `MATCH_THRESHOLD_RATIO` by default.
+    pub fn match_threshold(mut self, match_threshold: f64) -> Self {
+        self.match_threshold = match_threshold;
+        self
+    }
+
+    /// Number of layers, see [`default_nb_layer`] when unset.
+    pub fn nb_layer(mut self, nb_layer: usize) -> Self {
+        self.nb_layer = Some(nb_layer);
+        self
+    }
+
+    /// Expected number of points, used to size the initial allocation and the layer count. The
+    /// index keeps growing past it; leave it unset when the final size can't be guessed.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = Some(capacity);
+        self
+    }
+
+    /// Distance the graph is built and searched with. It isn't part of the dump, reloaded
+    /// indexes use [`DistanceKind::MaskedHamming`].
+    pub fn distance(mut self, distance: DistanceKind) -> Self {
+        self.distance = distance;
+        self
+    }
+
+    /// Common mask bits below which the masked Hamming distance counts as 1.0, none by default.
+    /// Like the distance kind, it isn't part of the dump.
+    pub fn min_overlap(mut self, min_overlap: u32) -> Self {
+        self.min_overlap = min_overlap;
+        self
+    }
+
+    /// Stores every rotation of a template by up to `max_shift` columns under its id, so plain
+    /// searches tolerate head tilt. Costs `2 * max_shift + 1` graph points per entry. Exact
+    /// distances are the configured distance to the nearest rotation.
+    pub fn rotation_expansion(mut self, max_shift: usize) -> Self {
+        self.rotation_shift = max_shift;
+        self
+    }
+
+    /// Requirements every inserted template has to meet, none by default. Not part of the dump
+    /// either.
+    pub fn validation(mut self, rules: ValidationRules) -> Self {
+        self.validation = rules;
+        self
+    }
+
+    /// Keeps the stored templates with their masks run-length encoded, see [`CompressedMask`],
+    /// which cuts their footprint to little more than the code at the cost of decoding the mask
+    /// for exact distances. The graph's own copy of every template is unaffected. Part of the
+    /// dump.
+    pub fn compress_masks(mut self, compress: bool) -> Self {
+        self.compress_masks = compress;
+        self
+    }
+
+    /// Pool that [`IrisHnsw::insert_batch`] runs on.
+    pub fn build_pool(mut self, pool: Arc<ThreadPool>) -> Self {
+        self.build_pool = Some(pool);
+        self
+    }
+
+    /// Pool that [`IrisHnsw::search_batch`] runs on.
+    pub fn search_pool(mut self, pool: Arc<ThreadPool>) -> Self {
+        self.search_pool = Some(pool);
+        self
+    }
+
+    pub fn observer(mut self, observer: Arc<dyn IndexObserver>) -> Self {
+        self.observers.push(observer);
+        self
+    }
+
+    pub fn build(self) -> IrisHnsw {
+        let nb_layer = self
+            .nb_layer
+            .unwrap_or_else(|| self.capacity.map_or(MAX_NB_LAYER, default_nb_layer));
+        let mut index = IrisHnsw::from_hnsw(
+            Hnsw::new(
+                self.max_nb_connection,
+                self.capacity.unwrap_or(INITIAL_CAPACITY),
+                nb_layer,
+                self.ef_construction,
+                HD::new(self.distance.clone()).with_min_overlap(self.min_overlap),
+            ),
+            Entries::default(),
+        );
+        index.build_pool = self.build_pool;
+        index.search_pool = self.search_pool;
+        index.observers = self.observers;
+        index.match_threshold = self.match_threshold;
+        index.distance = HD::new(self.distance).with_min_overlap(self.min_overlap);
+        index.rotation_shift = self.rotation_shift;
+        index.validation = self.validation;
+        index.compress_masks = self.compress_masks;
+        if let Some(ef_search) = self.ef_search {
+            index.ef_search = ef_search;
+        }
+        index
+    }
+}
+
+impl IrisHnsw {
+    pub fn builder() -> IrisHnswBuilder {
+        IrisHnswBuilder::default()
+    }
+
+    fn from_hnsw(hnsw: Hnsw<'static, u64, HD>, entries: Entries) -> Self {
+        Self {
+            ef_search: hnsw.get_ef_construction(),
+            match_threshold: MATCH_THRESHOLD_RATIO,
+            hnsw,
+            enroll: Mutex::new(()),
+            graph: RwLock::new(()),
+            entries: RwLock::new(entries),
+            build_pool: None,
+            search_pool: None,
+            observers: vec![],
+            distance: HD::default(),
+            rotation_shift: 0,
+            validation: ValidationRules::default(),
+            compress_masks: false,
+            loader: None,
+        }
+    }
+
+    /// Beam width used by [`IrisHnsw::search`] and the other searches without an explicit one.
+    pub fn ef_search(&self) -> usize {
+        self.ef_search
+    }
+
+    pub fn set_ef_search(&mut self, ef_search: usize) {
+        self.ef_search = ef_search;
+    }
+
+    pub fn match_threshold(&self) -> f64 {
+        self.match_threshold
+    }
+
+    pub fn set_match_threshold(&mut self, match_threshold: f64) {
+        self.match_threshold = match_threshold;
+    }
+
+    /// Registers `observer` for all following inserts and searches.
+    pub fn add_observer(&mut self, observer: Arc<dyn IndexObserver>) {
+        self.observers.push(observer);
+    }
+
+    pub fn set_build_pool(&mut self, pool: Option<Arc<ThreadPool>>) {
+        self.build_pool = pool;
+    }
+
+    pub fn set_search_pool(&mut self, pool: Option<Arc<ThreadPool>>) {
+        self.search_pool = pool;
+    }
+
+    /// Runs `op` on the build pool, so parallel work inside it is bounded like batch inserts.
+    pub fn in_build_pool<R: Send>(&self, op: impl FnOnce() -> R + Send) -> R {
+        in_pool(self.build_pool.as_deref(), op)
+    }
+
+    /// Runs `op` on the search pool, so parallel work inside it is bounded like batch searches.
+    pub fn in_search_pool<R: Send>(&self, op: impl FnOnce() -> R + Send) -> R {
+        in_pool(self.search_pool.as_deref(), op)
+    }
+
+    /// Reloads an index written by [`IrisHnsw::dump`], with its codes, metadata and settings.
+    pub fn load(dir: &Path, basename: &str) -> anyhow::Result<Self> {
+        Self::load_with(dir, basename, ReloadOptions::default())
+    }
+
+    /// Like [`IrisHnsw::load`], but maps the data file of the graph instead of reading it into
+    /// the heap. Startup doesn't wait for every vector to be deserialized, and pages of the
+    /// graph's vectors are faulted in as searches touch them and can be evicted again under
+    /// memory pressure. The live templates are still copied out once to rebuild the entries.
+    pub fn load_mapped(dir: &Path, basename: &str) -> anyhow::Result<Self> {
+        Self::load_with(dir, basename, ReloadOptions::default().set_mmap(true))
+    }
+
+    /// Format version of the index dumped in `dir`, see [`INDEX_FORMAT_VERSION`].
+    pub fn format_version(dir: &Path, basename: &str) -> anyhow::Result<u32> {
+        Ok(
+            IndexSettings::read(dir, basename)?.map_or(UNVERSIONED_FORMAT_VERSION, |settings| {
+                settings.format_version
+            }),
+        )
+    }
+
+    fn load_with(dir: &Path, basename: &str, options: ReloadOptions) -> anyhow::Result<Self> {
+        let settings = IndexSettings::read(dir, basename)?;
+        // the reloaded graph borrows its loader, and with it the mapping, which therefore has to
+        // live as long as the index
+        let loader = Loader::new(HnswIo::new_with_options(dir, basename, options));
+        // SAFETY: locals drop in reverse order, so the graph goes first, and the index holds the
+        // loader in a field declared after the graph
+        let hnsw = unsafe { loader.get() }
+            .load_hnsw()
+            .with_context(|| format!("loading index from {}", dir.display()))?;
+        let entries_path = dir.join(format!("{basename}.{ENTRIES_EXTENSION}"));
+        let mut codes: HashMap<usize, IrisCode> = hnsw
+            .get_point_indexation()
+            .into_iter()
+            .map(|point| {
+                let code = IrisCode::from_merged_slice(point.get_v())?;
+                Ok((point.get_origin_id(), code))
+            })
+            .collect::<Result<_, IrisError>>()
+            .context("reading graph points")?;
+        let compress_masks = settings
+            .as_ref()
+            .is_some_and(|settings| settings.compress_masks);
+        let mut entries = if entries_path.exists() {
+            Entries::read(&entries_path, &mut codes, compress_masks)?
+        } else {
+            // graphs dumped without the sidecar used the caller ids as points
+            let points: Vec<_> = codes.keys().map(|&id| (id, id, true)).collect();
+            Entries::from_points(points, &mut codes, compress_masks)?
+        };
+        let metadata_path = dir.join(format!("{basename}.{METADATA_EXTENSION}"));
+        if metadata_path.exists() {
+            entries.read_metadata(&metadata_path)?;
+        }
+        let rotation_shift = (entries.max_points_per_entry() - 1) / 2;
+        let mut index = Self::from_hnsw(hnsw, entries);
+        index.loader = Some(loader);
+        index.rotation_shift = rotation_shift;
+        index.compress_masks = compress_masks;
+        if let Some(settings) = settings {
+            index.ef_search = settings.ef_search;
+            index.match_threshold = settings.match_threshold;
+            index.validation = settings.validation;
+        }
+        Ok(index)
+    }
+
+    /// Writes graph and data files into `dir`, returning the basename actually used. Safe to call
+    /// while inserting, but inserts stall until the whole graph is written, which takes minutes
+    /// for ten million points. Searches go on.
+    pub fn dump(&self, dir: &Path, basename: &str) -> anyhow::Result<String> {
+        let _graph = self.graph.write().unwrap();
+        let basename = self
+            .hnsw
+            .file_dump(dir, basename)
+            .with_context(|| format!("dumping index to {}", dir.display()))?;
+        // entries published since are in the dumped graph too, as linking waits for the dump
+        let entries = self.entries.read().unwrap();
+        entries.write(&dir.join(format!("{basename}.{ENTRIES_EXTENSION}")))?;
+        entries.write_metadata(&dir.join(format!("{basename}.{METADATA_EXTENSION}")))?;
+        let settings = IndexSettings {
+            format_version: INDEX_FORMAT_VERSION,
+            code_bits: Some(<IrisCode>::IRIS_CODE_SIZE),
+            ef_search: self.ef_search,
+            match_threshold: self.match_threshold,
+            validation: self.validation,
+            compress_masks: self.compress_masks,
+        };
+        let settings_path = dir.join(format!("{basename}.{SETTINGS_EXTENSION}"));
+        fs::write(&settings_path, serde_json::to_vec(&settings)?)
+            .with_context(|| format!("writing {}", settings_path.display()))?;
+        Ok(basename)
+    }
+
+    /// Checks `code` against the index's validation rules, see [`IrisHnswBuilder::validation`].
+    pub fn validate(&self, code: &IrisCode) -> Result<(), IrisError> {
+        code.validate(&self.validation)
+    }
+
+    /// Adds `code` under `id`, replacing the entry `id` had so far. Safe to call concurrently from
+    /// many threads. Fails without changing the index if `code` doesn't pass
+    /// [`IrisHnsw::validate`].
+    pub fn insert(&self, code: &IrisCode, id: Id) -> Result<(), IrisError> {
+        self.insert_point(code, id, false, Some(Metadata::new()), None)?;
+        Ok(())
+    }
+
+    /// Like [`IrisHnsw::insert`], attaching `metadata`. Fails for keys under
+    /// [`RESERVED_METADATA_PREFIX`].
+    pub fn insert_with_metadata(
+        &self,
+        code: &IrisCode,
+        id: Id,
+        metadata: Metadata,
+    ) -> Result<(), IrisError> {
+        Self::validate_metadata(&metadata)?;
+        self.insert_with_reserved_metadata(code, id, metadata)
+    }
+
+    // insert_with_metadata for the library's own data, which may use reserved keys
+    pub(crate) fn insert_with_reserved_metadata(
+        &self,
+        code: &IrisCode,
+        id: Id,
+        metadata: Metadata,
+    ) -> Result<(), IrisError> {
+        self.insert_point(code, id, false, Some(metadata), None)?;
+        Ok(())
+    }
+
+    /// Rejects metadata with keys under [`RESERVED_METADATA_PREFIX`].
+    pub fn validate_metadata(metadata: &Metadata) -> Result<(), IrisError> {
+        match metadata
+            .keys()
+            .find(|key| key.starts_with(RESERVED_METADATA_PREFIX))
+        {
+            Some(key) => Err(IrisError::ReservedMetadataKey(key.clone())),
+            None => Ok(()),
+        }
+    }
+
+    /// Stores the consensus of several captures under `id`, see [`IrisCode::fuse`]. A fused
+    /// template is more stable than any single capture, which lowers false non-matches.
+    pub fn insert_fused(&self, samples: &[IrisCode], id: Id) -> Result<(), IrisError> {
+        self.insert(&IrisCode::fuse(samples)?, id)
+    }
+
+    /// Replaces the template of an existing entry, keeping its metadata, and returns false if
+    /// `id` isn't in the index. Searches see either the old or the new template, never neither.
+    pub fn update(&self, id: Id, code: &IrisCode) -> Result<bool, IrisError> {
+        self.insert_point(code, id, true, None, None)
+    }
+
+    /// Replaces the metadata of `id`, returning false if it isn't in the index. Fails for keys
+    /// under [`RESERVED_METADATA_PREFIX`].
+    pub fn set_metadata(&self, id: Id, metadata: Metadata) -> Result<bool, IrisError> {
+        Self::validate_metadata(&metadata)?;
+        Ok(self.set_reserved_metadata(id, metadata))
+    }
+
+    // set_metadata for the library's own data, which may use reserved keys
+    pub(crate) fn set_reserved_metadata(&self, id: Id, metadata: Metadata) -> bool {
+        let mut entries = self.entries.write().unwrap();
+        match entries.live.get_mut(&id) {
+            Some(entry) => {
+                entry.metadata = metadata;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn metadata(&self, id: Id) -> Option<Metadata> {
+        let entries = self.entries.read().unwrap();
+        entries.live.get(&id).map(|entry| entry.metadata.clone())
+    }
+
+    // the new points only become visible once they are published as the live points of `id`,
+    // which retires the previous ones in the same step
+    // `metadata` of None keeps the metadata of the replaced entry
+    // `mapped` is the dataset record `code` was read from, which is kept instead of a copy
+    fn insert_point(
+        &self,
+        code: &IrisCode,
+        id: Id,
+        existing_only: bool,
+        metadata: Option<Metadata>,
+        mapped: Option<(&Arc<MappedDataset>, usize)>,
+    ) -> Result<bool, IrisError> {
+        self.validate(code)?;
+        let points = {
+            let mut entries = self.entries.write().unwrap();
+            if existing_only && !entries.live.contains_key(&id) {
+                return Ok(false);
+            }
+            let points: Vec<_> = (entries.next_point..)
+                .take(self.points_per_entry())
+                .collect();
+            entries.next_point += points.len();
+            for &point in &points {
+                entries.points.insert(
+                    point,
+                    PointInfo {
+                        id,
+                        published: None,
+                        retired: None,
+                    },
+                );
+            }
+            points
+        };
+        let evals_before = thread_eval_count();
+        {
+            let _graph = self.graph.read().unwrap();
+            self.hnsw.insert_slice((&code.as_merged_array(), points[0]));
+            let shift = self.rotation_shift as isize;
+            let rotations = (-shift..=shift).filter(|&shift| shift != 0);
+            for (&point, shift) in points[1..].iter().zip(rotations) {
+                self.hnsw
+                    .insert_slice((&code.rotated(shift).as_merged_array(), point));
+            }
+        }
+        let evals = thread_eval_count() - evals_before;
+        {
+            let mut entries = self.entries.write().unwrap();
+            if existing_only && !entries.live.contains_key(&id) {
+                // removed while the points were being linked, which leaves them stale
+                return Ok(false);
+            }
+            let metadata = match metadata {
+                Some(metadata) => metadata,
+                None => entries
+                    .live
+                    .get(&id)
+                    .map(|entry| entry.metadata.clone())
+                    .unwrap_or_default(),
+            };
+            let stored = match mapped {
+                Some((dataset, record)) => StoredCode::Mapped {
+                    dataset: dataset.clone(),
+                    record,
+                },
+                None => StoredCode::new(code.clone(), self.compress_masks),
+            };
+            entries.publish(
+                id,
+                Entry {
+                    points,
+                    mask_ones: code.mask.count_ones() as u32,
+                    code: stored,
+                    metadata,
+                },
+            );
+        }
+        for observer in &self.observers {
+            observer.on_insert(id, evals);
+        }
+        Ok(true)
+    }
+
+    /// Inserts all `items` in parallel, calling `progress` with the number inserted so far after
+    /// each one. The resulting graph depends on thread scheduling. All items are validated up
+    /// front, so an invalid one fails the batch before anything is inserted.
+    pub fn insert_batch(
+        &self,
+        items: &[(IrisCode, Id)],
+        progress: impl Fn(usize) + Sync,
+    ) -> Result<(), IrisError> {
+        items.iter().try_for_each(|(code, _)| self.validate(code))?;
+        let inserted = AtomicUsize::new(0);
+        self.in_build_pool(|| {
+            items.par_iter().try_for_each(|(code, id)| {
+                self.insert(code, *id)?;
+                progress(inserted.fetch_add(1, Ordering::Relaxed) + 1);
+                Ok(())
+            })
+        })
+    }
+
+    /// Adds the record at `record` of `dataset` under its id, like [`IrisHnsw::insert`], and
+    /// returns the id. The record is copied into the graph, as hnsw_rs owns the vectors of
+    /// inserted points, but the index doesn't store the template again next to it and reads it
+    /// from the mapping for exact distances and lookups instead.
+    pub fn insert_mapped(
+        &self,
+        dataset: &Arc<MappedDataset>,
+        record: usize,
+    ) -> Result<Id, IrisError> {
+        let (code, id) = dataset.get(record);
+        self.insert_point(
+            &code,
+            id,
+            false,
+            Some(Metadata::new()),
+            Some((dataset, record)),
+        )?;
+        Ok(id)
+    }
+
+    /// [`IrisHnsw::insert_mapped`] for all `records` in parallel, like [`IrisHnsw::insert_batch`].
+    pub fn insert_mapped_batch(
+        &self,
+        dataset: &Arc<MappedDataset>,
+        records: Range<usize>,
+        progress: impl Fn(usize) + Sync,
+    ) -> Result<(), IrisError> {
+        records
+            .clone()
+            .try_for_each(|record| self.validate(&dataset.get(record).0))?;
+        let inserted = AtomicUsize::new(0);
+        self.in_build_pool(|| {
+            records.into_par_iter().try_for_each(|record| {
+                self.insert_mapped(dataset, record)?;
+                progress(inserted.fetch_add(1, Ordering::Relaxed) + 1);
+                Ok(())
+            })
+        })
+    }
+
+    /// Re-inserts the live entries of `other` into this index with their metadata, returning how
+    /// many were merged. Lets shards be built in parallel and combined afterwards. All ids are
+    /// remapped and all templates validated before anything is inserted.
+    pub fn merge(&self, other: &IrisHnsw, remap: IdRemap) -> Result<usize, IrisError> {
+        let items = {
+            let entries = other.entries.read().unwrap();
+            entries
+                .live
+                .iter()
+                .map(|(&id, entry)| {
+                    let mapped = match &entry.code {
+                        StoredCode::Mapped { dataset, record } => Some((dataset.clone(), *record)),
+                        _ => None,
+                    };
+                    let code = entry.code.get().into_owned();
+                    Ok((remap.apply(id)?, code, entry.metadata.clone(), mapped))
+                })
+                .collect::<Result<Vec<_>, IrisError>>()?
+        };
+        items
+            .iter()
+            .try_for_each(|(_, code, _, _)| self.validate(code))?;
+        self.in_build_pool(|| {
+            items
+                .par_iter()
+                .try_for_each(|(id, code, metadata, mapped)| {
+                    let mapped = mapped.as_ref().map(|(dataset, record)| (dataset, *record));
+                    self.insert_point(code, *id, false, Some(metadata.clone()), mapped)?;
+                    Ok(())
+                })
+        })?;
+        Ok(items.len())
+    }
+
+    /// Removes `id`, returning false if it wasn't in the index. Its point stays in the graph,
+    /// which keeps routing through it, but is no longer returned.
+    pub fn remove(&self, id: Id) -> bool {
+        self.entries.write().unwrap().remove(id).is_some()
+    }
+
+    pub fn contains(&self, id: Id) -> bool {
+        self.entries.read().unwrap().live.contains_key(&id)
+    }
+
+    /// Template stored for `id`. Returned by value, as concurrent inserts may be changing the
+    /// store.
+    pub fn get(&self, id: Id) -> Option<IrisCode> {
+        let entries = self.entries.read().unwrap();
+        entries
+            .live
+            .get(&id)
+            .map(|entry| entry.code.get().into_owned())
+    }
+
+    /// All live entries in id order. They are copied up front, so inserts aren't blocked while
+    /// the caller iterates.
+    pub fn iter(&self) -> impl Iterator<Item = (Id, IrisCode)> {
+        let entries = self.entries.read().unwrap();
+        let mut all: Vec<_> = entries
+            .live
+            .iter()
+            .map(|(&id, entry)| (id, entry.code.get().into_owned()))
+            .collect();
+        drop(entries);
+        all.sort_unstable_by_key(|&(id, _)| id);
+        all.into_iter()
+    }
+
+    /// Graph points left behind by removals and updates, to be dropped by [`IrisHnsw::compact`].
+    pub fn tombstones(&self) -> usize {
+        self.entries.read().unwrap().stale()
+    }
+
+    /// Rebuilds the graph from the live entries, dropping the stale points left behind by
+    /// removals and updates, and returns how many were dropped. Ids, templates and metadata are
+    /// kept, graph points are renumbered. Costs as much as inserting every entry again, and the
+    /// graph has to be put back into searching mode afterwards.
+    ///
+    /// It needs exclusive access, so it is meant for offline maintenance: an index shared behind
+    /// an `Arc` can be compacted once the other owners are gone, or dumped and compacted after
+    /// reloading. Fails without changing the index if a template no longer passes validation.
+    pub fn compact(&mut self) -> Result<usize, IrisError> {
+        for entry in self.entries.read().unwrap().live.values() {
+            self.validate(&entry.code.get())?;
+        }
+        let dropped = self.tombstones();
+        let old = std::mem::take(self.entries.get_mut().unwrap());
+        self.hnsw = Hnsw::new(
+            usize::from(self.hnsw.get_max_nb_connection()),
+            old.live_points.max(INITIAL_CAPACITY),
+            self.hnsw.get_max_level(),
+            self.hnsw.get_ef_construction(),
+            self.distance.clone(),
+        );
+        // nothing borrows from it anymore
+        self.loader = None;
+        let live: Vec<_> = old.live.iter().collect();
+        self.in_build_pool(|| {
+            live.par_iter().try_for_each(|&(&id, entry)| {
+                let mapped = match &entry.code {
+                    StoredCode::Mapped { dataset, record } => Some((dataset, *record)),
+                    _ => None,
+                };
+                self.insert_point(
+                    &entry.code.get(),
+                    id,
+                    false,
+                    Some(entry.metadata.clone()),
+                    mapped,
+                )?;
+                Ok(())
+            })
+        })?;
+        Ok(dropped)
+    }
+
+    /// The `k` nearest entries, searching with the default ef_search.
+    pub fn search(&self, query: &IrisCode, k: usize) -> Vec<SearchHit> {
+        self.search_with_ef(query, k, self.ef_search)
+    }
+
+    /// The `k` nearest entries, searching with a beam of `ef` (at least `k`). Stale points are
+    /// skipped while traversing the graph, so inserts wait until the search is done.
+    pub fn search_with_ef(&self, query: &IrisCode, k: usize, ef: usize) -> Vec<SearchHit> {
+        if self.is_empty() || k == 0 {
+            return vec![];
+        }
+        self.observed(query, || {
+            let entries = self.entries.read().unwrap();
+            let live = |point: &usize| entries.live_id(*point).is_some();
+            // over-fetch so that k entries remain even if further rotations of a hit are nearest
+            let fetch = k * self.points_per_entry();
+            let found = self.hnsw.search_filter(
+                &query.as_merged_array(),
+                fetch,
+                ef.max(fetch),
+                Some(&live),
+            );
+            self.to_hits(found, k, |point| entries.live_id(point))
+        })
+    }
+
+    /// Consistent view of the entries as of now, see [`IndexSnapshot`].
+    pub fn snapshot(self: &Arc<Self>) -> IndexSnapshot {
+        let entries = self.entries.read().unwrap();
+        IndexSnapshot::new(self.clone(), entries.epoch, entries.live.len())
+    }
+
+    /// Search restricted to the entries that were live at `epoch`.
+    pub(crate) fn search_at(
+        &self,
+        query: &IrisCode,
+        k: usize,
+        ef: usize,
+        epoch: u64,
+    ) -> Vec<SearchHit> {
+        // whether there was anything to find at `epoch` is up to the snapshot, the index may have
+        // been emptied since
+        if k == 0 {
+            return vec![];
+        }
+        self.observed(query, || {
+            let entries = self.entries.read().unwrap();
+            let visible = |point: &usize| entries.id_at(*point, epoch).is_some();
+            let fetch = k * self.points_per_entry();
+            let found = self.hnsw.search_filter(
+                &query.as_merged_array(),
+                fetch,
+                ef.max(fetch),
+                Some(&visible),
+            );
+            self.to_hits(found, k, |point| entries.id_at(point, epoch))
+        })
+    }
+
+    /// The `k` nearest entries whose metadata satisfies `filter`. The predicate is applied while
+    /// traversing the graph, so non-matching entries don't crowd out the result. Inserts wait
+    /// until the search is done.
+    pub fn search_filtered(
+        &self,
+        query: &IrisCode,
+        k: usize,
+        filter: impl Fn(&Metadata) -> bool,
+    ) -> Vec<SearchHit> {
+        if self.is_empty() || k == 0 {
+            return vec![];
+        }
+        self.observed(query, || {
+            let entries = self.entries.read().unwrap();
+            let accept = |point: &usize| {
+                entries
+                    .live_id(*point)
+                    .is_some_and(|id| filter(&entries.live[&id].metadata))
+            };
+            let fetch = k * self.points_per_entry();
+            let found = self.hnsw.search_filter(
+                &query.as_merged_array(),
+                fetch,
+                self.ef_search.max(fetch),
+                Some(&accept),
+            );
+            self.to_hits(found, k, |point| entries.live_id(point))
+        })
+    }
+
+    fn observed(
+        &self,
+        query: &IrisCode,
+        search: impl FnOnce() -> Vec<SearchHit>,
+    ) -> Vec<SearchHit> {
+        for observer in &self.observers {
+            observer.on_search_start(query);
+        }
+        let evals_before = thread_eval_count();
+        let hits = search();
+        let evals = thread_eval_count() - evals_before;
+        for observer in &self.observers {
+            observer.on_search_done(&hits, evals);
+        }
+        hits
+    }
+
+    /// The first `k` distinct entries among `found`, resolving points to ids with `id`, which
+    /// leaves out points it returns `None` for.
+    fn to_hits(
+        &self,
+        found: Vec<hnsw_rs::hnsw::Neighbour>,
+        k: usize,
+        id: impl Fn(usize) -> Option<Id>,
+    ) -> Vec<SearchHit> {
+        // several rotations of an entry may be found, the nearest one comes first
+        let mut seen = HashSet::new();
+        found
+            .into_iter()
+            .filter_map(|n| {
+                Some(SearchHit {
+                    id: id(n.d_id)?,
+                    distance: n.distance,
+                    is_match: f64::from(n.distance) < self.match_threshold,
+                })
+            })
+            .filter(|hit| seen.insert(hit.id))
+            .take(k)
+            .collect()
+    }
+
+    fn points_per_entry(&self) -> usize {
+        2 * self.rotation_shift + 1
+    }
+
+    /// Exact distance matching what the graph approximates, over rotations if they are stored.
+    /// Anything above `bound` may be returned once the distance is known to exceed it.
+    fn exact_distance(
+        &self,
+        query: &IrisCode,
+        query_mask_ones: u32,
+        entry: &Entry,
+        bound: f64,
+    ) -> f64 {
+        let code = entry.code.get();
+        if self.rotation_shift == 0 {
+            return self.distance.eval_codes_bounded(
+                query,
+                query_mask_ones,
+                &code,
+                entry.mask_ones,
+                bound,
+            );
+        }
+        // the configured distance to the nearest rotation, each bounded by the best one so far
+        let shift = self.rotation_shift as isize;
+        (-shift..=shift).fold(f64::INFINITY, |best, shift| {
+            let rotated = code.rotated(shift);
+            let mask_ones = rotated.mask.count_ones() as u32;
+            let distance = self.distance.eval_codes_bounded(
+                query,
+                query_mask_ones,
+                &rotated,
+                mask_ones,
+                bound.min(best),
+            );
+            distance.min(best)
+        })
+    }
+
+    /// Retrieves `k * oversample` candidates from the graph and keeps the `k` nearest by the exact
+    /// distance to their stored templates, which raises recall at a fixed ef_search.
+    pub fn search_reranked(&self, query: &IrisCode, k: usize, oversample: usize) -> Vec<SearchHit> {
+        let candidates = self.search(query, k * oversample.max(1));
+        let query_mask_ones = query.mask.count_ones() as u32;
+        let entries = self.entries.read().unwrap();
+        let mut hits: Vec<SearchHit> = Vec::with_capacity(k + 1);
+        for candidate in candidates {
+            let Some(entry) = entries.live.get(&candidate.id) else {
+                continue;
+            };
+            // once there are k hits, counting can stop for candidates farther than all of them
+            let bound = if hits.len() == k {
+                f64::from(hits[k - 1].distance)
+            } else {
+                f64::INFINITY
+            };
+            let distance = self.exact_distance(query, query_mask_ones, entry, bound);
+            if distance > bound {
+                continue;
+            }
+            let hit = SearchHit {
+                id: candidate.id,
+                distance: distance as f32,
+                is_match: distance < self.match_threshold,
+            };
+            let position = hits.partition_point(|other| other.distance <= hit.distance);
+            hits.insert(position, hit);
+            hits.truncate(k);
+        }
+        hits
+    }
+
+    /// The `k` nearest entries over all rotations of `query` by up to `max_shift` columns, each at
+    /// its smallest distance. Runs one search per rotation, a cheaper alternative to
+    /// [`DistanceKind::RotatedMaskedHamming`] inside the graph.
+    pub fn search_rotations(&self, query: &IrisCode, k: usize, max_shift: usize) -> Vec<SearchHit> {
+        let max_shift = max_shift as isize;
+        let mut hits: Vec<_> = (-max_shift..=max_shift)
+            .flat_map(|shift| self.search(&query.rotated(shift), k))
+            .collect();
+        hits.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+        let mut seen = HashSet::new();
+        hits.retain(|hit| seen.insert(hit.id));
+        hits.truncate(k);
+        hits
+    }
+
+    /// Every entry closer than `threshold`, nearest first. The beam is doubled until the farthest
+    /// neighbour found is no longer a match, so the result is as complete as the graph allows.
+    pub fn search_within(&self, query: &IrisCode, threshold: f64) -> Vec<SearchHit> {
+        let len = self.len();
+        let mut k = SEARCH_WITHIN_INITIAL_K.min(len);
+        loop {
+            let mut neighbours = self.search_with_ef(query, k, k.max(self.ef_search));
+            let exhausted = neighbours.len() < k || k == len;
+            let frontier_matches = neighbours
+                .last()
+                .is_some_and(|n| f64::from(n.distance) < threshold);
+            if exhausted || !frontier_matches {
+                return self.verify_within(query, neighbours, threshold);
+            }
+            k = (2 * k).min(len);
+        }
+    }
+
+    // keeps the candidates whose exact distance is below `threshold`, counting only until a
+    // candidate is certain to miss it
+    fn verify_within(
+        &self,
+        query: &IrisCode,
+        candidates: Vec<SearchHit>,
+        threshold: f64,
+    ) -> Vec<SearchHit> {
+        let query_mask_ones = query.mask.count_ones() as u32;
+        let entries = self.entries.read().unwrap();
+        let mut hits: Vec<_> = candidates
+            .into_iter()
+            .filter_map(|hit| {
+                let entry = entries.live.get(&hit.id)?;
+                let distance = self.exact_distance(query, query_mask_ones, entry, threshold);
+                (distance < threshold).then(|| SearchHit {
+                    distance: distance as f32,
+                    is_match: distance < self.match_threshold,
+                    ..hit
+                })
+            })
+            .collect();
+        hits.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+        hits
+    }
+
+    /// The `k` nearest entries and every other one closer than `threshold`, nearest first, found
+    /// by comparing `query` against all live entries. Gives the results the graph search
+    /// approximates, at the cost of a full scan.
+    pub fn search_exact(&self, query: &IrisCode, k: usize, threshold: f64) -> Vec<SearchHit> {
+        let query_mask_ones = query.mask.count_ones() as u32;
+        let entries = self.entries.read().unwrap();
+        let mut matches = vec![];
+        // the k nearest of the rest, nearest first
+        let mut nearest: Vec<SearchHit> = Vec::with_capacity(k + 1);
+        for (&id, entry) in &entries.live {
+            // once there are k others, counting can stop for anything that is neither a match nor
+            // nearer than all of them
+            let bound = if nearest.len() == k {
+                nearest
+                    .last()
+                    .map_or(threshold, |kth| f64::from(kth.distance).max(threshold))
+            } else {
+                f64::INFINITY
+            };
+            let distance = self.exact_distance(query, query_mask_ones, entry, bound);
+            if distance > bound {
+                continue;
+            }
+            let hit = SearchHit {
+                id,
+                distance: distance as f32,
+                is_match: distance < self.match_threshold,
+            };
+            if distance < threshold {
+                matches.push(hit);
+            } else if k > 0 {
+                let position = nearest.partition_point(|other| other.distance <= hit.distance);
+                nearest.insert(position, hit);
+                nearest.truncate(k);
+            }
+        }
+        drop(entries);
+        let by_distance =
+            |a: &SearchHit, b: &SearchHit| a.distance.total_cmp(&b.distance).then(a.id.cmp(&b.id));
+        matches.sort_unstable_by(by_distance);
+        // the matches are nearer than all others, so the k nearest come from them first
+        let rest = k.saturating_sub(matches.len());
+        nearest.truncate(rest);
+        matches.extend(nearest);
+        matches
+    }
+
+    /// Id of an entry whose template is byte-identical to `code`.
+    pub fn find_duplicate(&self, code: &IrisCode) -> Option<Id> {
+        let entries = self.entries.read().unwrap();
+        entries.find_duplicate(code).map(|(id, _)| id)
+    }
+
+    /// Whether `code` matches no indexed entry. Re-enrollments of a stored template are answered
+    /// without a graph search.
+    pub fn is_unique(&self, code: &IrisCode) -> UniquenessResult {
+        let duplicate = {
+            let entries = self.entries.read().unwrap();
+            entries.find_duplicate(code).map(|(id, entry)| {
+                let mask_ones = code.mask.count_ones() as u32;
+                let distance = self.exact_distance(code, mask_ones, entry, f64::INFINITY);
+                SearchHit {
+                    id,
+                    distance: distance as f32,
+                    is_match: distance < self.match_threshold,
+                }
+            })
+        };
+        // a template without overlap doesn't even match itself
+        if let Some(hit) = duplicate.filter(|hit| hit.is_match) {
+            return UniquenessResult {
+                unique: false,
+                closest: Some(hit),
+            };
+        }
+        let closest = self.search(code, 1).into_iter().next();
+        UniquenessResult {
+            unique: closest.as_ref().is_none_or(|hit| !hit.is_match),
+            closest,
+        }
+    }
+
+    /// Inserts `code` only if [`IrisHnsw::is_unique`] holds, returning the check's result.
+    /// Concurrent calls are serialized, so two matching codes can't both be enrolled; plain
+    /// [`IrisHnsw::insert`] calls are not covered by this.
+    pub fn insert_unique(&self, code: &IrisCode, id: Id) -> Result<UniquenessResult, IrisError> {
+        self.validate(code)?;
+        let _guard = self.enroll.lock().unwrap_or_else(|err| err.into_inner());
+        let result = self.is_unique(code);
+        if result.unique {
+            self.insert(code, id)?;
+        }
+        Ok(result)
+    }
+
+    /// Runs all `queries` in parallel, returning their neighbours in query order.
+    pub fn search_batch(&self, queries: &[IrisCode], k: usize, ef: usize) -> Vec<Vec<SearchHit>> {
+        self.in_search_pool(|| {
+            queries
+                .par_iter()
+                .map(|query| self.search_with_ef(query, k, ef))
+                .collect()
+        })
+    }
+
+    /// Walks the whole graph, so this takes a while on large indexes.
+    pub fn stats(&self) -> IndexStats {
+        let mut points_per_layer = vec![];
+        let mut degrees = vec![];
+        let mut nb_edges = 0;
+        let mut nb_points = 0;
+        for point in self.hnsw.get_point_indexation() {
+            nb_points += 1;
+            let level = usize::from(point.get_point_id().0);
+            if points_per_layer.len() <= level {
+                points_per_layer.resize(level + 1, 0);
+            }
+            points_per_layer[..=level].iter_mut().for_each(|n| *n += 1);
+            let neighbourhood = point.get_neighborhood_id();
+            nb_edges += neighbourhood.iter().map(Vec::len).sum::<usize>();
+            degrees.push(neighbourhood.first().map_or(0, Vec::len));
+        }
+        let (live_entries, payload_bytes) = {
+            let entries = self.entries.read().unwrap();
+            let payload_bytes = entries.live.values().map(|entry| entry.code.bytes()).sum();
+            (entries.live.len(), payload_bytes)
+        };
+        IndexStats {
+            live_entries,
+            nb_points,
+            points_per_layer,
+            degree: DegreeStats::from_degrees(degrees),
+            vector_bytes: nb_points * IRIS_CODE_MERGED_BYTES,
+            edge_bytes: nb_edges * size_of::<hnsw_rs::hnsw::Neighbour>(),
+            payload_bytes,
+        }
+    }
+
+    /// Number of live entries.
+    pub fn len(&self) -> usize {
+        self.entries.read().unwrap().live.len()
+    }
+
+    /// Number of points in the graph, stale ones included.
+    pub fn nb_points(&self) -> usize {
+        self.hnsw.get_nb_point()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn set_searching_mode(&mut self, flag: bool) {
+        self.hnsw.set_searching_mode(flag);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+    use crate::distance::EMPTY_OVERLAP_DISTANCE;
+
+    fn codes(len: usize, seed: u64) -> Vec<IrisCode> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..len).map(|_| IrisCode::random_rng(&mut rng)).collect()
+    }
+
+    fn index(codes: &[IrisCode]) -> IrisHnsw {
+        let index = IrisHnsw::builder()
+            .max_nb_connection(16)
+            .ef_construction(64)
+            .capacity(1000)
+            .build();
+        for (id, code) in codes.iter().enumerate() {
+            index.insert(code, id).unwrap();
+        }
+        index
+    }
+
+    #[test]
+    fn merge_keeps_metadata_and_offsets_ids() {
+        let codes = codes(20, 0);
+        let other = index(&codes);
+        let metadata = Metadata::from([("site".to_string(), "berlin".to_string())]);
+        assert!(other.set_metadata(3, metadata.clone()).unwrap());
+        let merged = index(&[]);
+
+        assert_eq!(
+            merged.merge(&other, IdRemap::Offset(100)).unwrap(),
+            codes.len()
+        );
+        assert_eq!(merged.len(), codes.len());
+        assert_eq!(merged.metadata(103), Some(metadata));
+        assert_eq!(merged.metadata(104), Some(Metadata::new()));
+        for (id, code) in codes.iter().enumerate() {
+            assert_eq!(merged.get(100 + id).as_ref(), Some(code));
+        }
+        assert_eq!(merged.search(&codes[7], 1)[0].id, 107);
+    }
+
+    #[test]
+    fn merge_rejects_overflowing_ids() {
+        let other = index(&codes(5, 0));
+        let merged = index(&[]);
+        assert!(matches!(
+            merged.merge(&other, IdRemap::Offset(usize::MAX)),
+            Err(IrisError::IdOverflow {
+                offset: usize::MAX,
+                ..
+            })
+        ));
+        assert!(merged.is_empty());
+        assert_eq!(IdRemap::Offset(5).apply(7), Ok(12));
+        assert_eq!(IdRemap::Keep.apply(usize::MAX), Ok(usize::MAX));
+    }
+
+    #[test]
+    fn rotated_exact_distance_uses_the_configured_distance() {
+        let code = codes(1, 5).remove(0);
+        let distance = |builder: IrisHnswBuilder, query: &IrisCode| {
+            let index = builder.rotation_expansion(2).capacity(100).build();
+            index.insert(&code, 0).unwrap();
+            let entries = index.entries.read().unwrap();
+            let query_mask_ones = query.mask.count_ones() as u32;
+            index.exact_distance(query, query_mask_ones, &entries.live[&0], f64::INFINITY)
+        };
+        let tilted = code.rotated(2);
+        assert_eq!(distance(IrisHnsw::builder(), &tilted), 0.0);
+        // saturated below the minimum overlap, rotated or not
+        assert_eq!(
+            distance(IrisHnsw::builder().min_overlap(u32::MAX), &tilted),
+            1.0
+        );
+        // no mask bits in common, which only plain Hamming doesn't care about
+        let unmasked = IrisCode::new(tilted.code, IrisCodeArray::ZERO);
+        assert_eq!(
+            distance(IrisHnsw::builder(), &unmasked),
+            f64::from(EMPTY_OVERLAP_DISTANCE)
+        );
+        let hamming = IrisHnsw::builder().distance(DistanceKind::Hamming);
+        assert_eq!(distance(hamming, &unmasked), 0.0);
+    }
+
+    #[test]
+    fn compact_drops_removed_and_updated_points() {
+        let codes = codes(21, 9);
+        let mut index = index(&codes[..20]);
+        let metadata = Metadata::from([("name".to_string(), "four".to_string())]);
+        assert!(index.set_metadata(4, metadata.clone()).unwrap());
+        assert!(index.remove(3));
+        assert!(!index.remove(3));
+        assert!(index.update(4, &codes[20]).unwrap());
+        assert!(!index.update(3, &codes[20]).unwrap());
+        assert_eq!(index.len(), 19);
+        assert_eq!(index.tombstones(), 2);
+
+        assert_eq!(index.compact(), Ok(2));
+        index.set_searching_mode(true);
+        assert_eq!(index.tombstones(), 0);
+        assert_eq!(index.len(), 19);
+        assert!(!index.contains(3));
+        assert_eq!(index.get(4), Some(codes[20].clone()));
+        assert_eq!(index.metadata(4), Some(metadata));
+        for id in [0, 4, 5, 19] {
+            assert_eq!(index.search(index.get(id).as_ref().unwrap(), 1)[0].id, id);
+        }
+        assert!(index.search(&codes[3], 20).iter().all(|hit| hit.id != 3));
+    }
+
+    // `code` with its first `bits` code bits flipped
+    fn flipped(code: &IrisCode, bits: usize) -> IrisCode {
+        let mut code = code.clone();
+        for bit in 0..bits {
+            code.code.flip_bit(bit);
+        }
+        code
+    }
+
+    #[test]
+    fn search_within_finds_every_match() {
+        let mut codes = codes(60, 13);
+        // more matches than the first round fetches
+        let variants: Vec<_> = (1..=40).map(|i| flipped(&codes[0], 20 * i)).collect();
+        codes.extend(variants);
+        let index = index(&codes);
+        let query = flipped(&codes[0], 5);
+        let threshold = 0.1;
+        let mut expected: Vec<_> = (0..codes.len())
+            .filter(|&id| query.get_distance(&codes[id]) < threshold)
+            .collect();
+        assert!(expected.len() > SEARCH_WITHIN_INITIAL_K);
+        let hits = index.search_within(&query, threshold);
+        assert!(hits.windows(2).all(|w| w[0].distance <= w[1].distance));
+        let mut ids: Vec<_> = hits.iter().map(|hit| hit.id).collect();
+        ids.sort_unstable();
+        expected.sort_unstable();
+        assert_eq!(ids, expected);
+        assert!(index.search_within(&query, 0.0).is_empty());
+    }
+
+    #[test]
+    fn search_exact_matches_brute_force() {
+        let mut codes = codes(50, 17);
+        codes.push(flipped(&codes[7], 40));
+        let index = index(&codes);
+        let query = flipped(&codes[7], 10);
+        let mut brute: Vec<_> = (0..codes.len())
+            .map(|id| (query.get_distance(&codes[id]), id))
+            .collect();
+        brute.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+
+        let hits = index.search_exact(&query, 5, 0.1);
+        let ids: Vec<_> = hits.iter().map(|hit| hit.id).collect();
+        let expected: Vec<_> = brute[..5].iter().map(|&(_, id)| id).collect();
+        assert_eq!(ids, expected);
+        assert_eq!(ids[..2], [7, 50]);
+        assert!(hits[0].is_match);
+        for (hit, (distance, _)) in hits.iter().zip(&brute) {
+            assert_eq!(hit.distance, *distance as f32);
+        }
+        // every match is returned even beyond k
+        assert_eq!(index.search_exact(&query, 0, 0.1).len(), 2);
+        assert_eq!(index.search_exact(&query, 1, 1.0).len(), codes.len());
+    }
+}
@@ -1,27 +1,77 @@
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::{fmt, str::FromStr};
+#[cfg(feature = "std")]
+use std::io::{self, Read, Write};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+#[cfg(feature = "rand")]
 use rand::{
     distributions::{Bernoulli, Distribution},
     Rng,
 };
+use serde::{
+    de::{self, SeqAccess, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+#[cfg(feature = "std")]
+use crate::distance::BitWeights;
+use crate::{
+    error::IrisError,
+    simd,
+    template::{TemplateLayout, DEFAULT_LAYOUT},
+};
 
 pub const MATCH_THRESHOLD_RATIO: f64 = 0.375;
+pub const DEFAULT_FLIP_PROBABILITY: f64 = 0.05;
+/// Share of mask bits [`IrisCode::random_rng`] removes.
+pub const DEFAULT_MASK_DROPOUT: f64 = 0.1;
+/// Version of the binary template encoding, see [`IrisCode::write_to`].
+pub const TEMPLATE_FORMAT_VERSION: u8 = 1;
 
+/// Words per [`IrisCodeArray`] unless stated otherwise, i.e. 128-bit toy codes.
+#[cfg(not(feature = "full-codes"))]
+pub const DEFAULT_IRIS_CODE_WORDS: usize = 2;
+/// Words per [`IrisCodeArray`] unless stated otherwise, i.e. 12,800-bit production codes.
+#[cfg(feature = "full-codes")]
+pub const DEFAULT_IRIS_CODE_WORDS: usize = 12_800 / 64;
+
+/// A code of `WORDS * 64` bits. The width is given in u64 words rather than bits, since stable
+/// Rust can't size the backing array by `BITS / 64`.
 #[repr(transparent)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub struct IrisCodeArray(pub [u64; Self::IRIS_CODE_SIZE_U64]);
-impl Default for IrisCodeArray {
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct IrisCodeArray<const WORDS: usize = DEFAULT_IRIS_CODE_WORDS>(pub [u64; WORDS]);
+impl<const WORDS: usize> Default for IrisCodeArray<WORDS> {
     fn default() -> Self {
         Self::ZERO
     }
 }
 
-impl IrisCodeArray {
-    pub const IRIS_CODE_SIZE: usize = 128;
-    pub const IRIS_CODE_SIZE_BYTES: usize = (Self::IRIS_CODE_SIZE + 7) / 8;
-    pub const IRIS_CODE_SIZE_U64: usize = (Self::IRIS_CODE_SIZE + 63) / 64;
-    pub const ZERO: Self = IrisCodeArray([0; Self::IRIS_CODE_SIZE_U64]);
-    pub const ONES: Self = IrisCodeArray([u64::MAX; Self::IRIS_CODE_SIZE_U64]);
+impl<const WORDS: usize> IrisCodeArray<WORDS> {
+    pub const IRIS_CODE_SIZE: usize = WORDS * 64;
+    pub const IRIS_CODE_SIZE_BYTES: usize = WORDS * 8;
+    pub const IRIS_CODE_SIZE_U64: usize = WORDS;
+    pub const ZERO: Self = IrisCodeArray([0; WORDS]);
+    pub const ONES: Self = IrisCodeArray([u64::MAX; WORDS]);
+
+    /// Wraps raw words, bit i being bit `i % 64` of word `i / 64`. Usable in constants, e.g. for
+    /// fixtures and golden vectors.
+    pub const fn new(words: [u64; WORDS]) -> Self {
+        Self(words)
+    }
+
+    /// Copy with bit `i` set to `val`, to build codes bit by bit in constants.
+    pub const fn with_bit(mut self, i: usize, val: bool) -> Self {
+        self.set_bit(i, val);
+        self
+    }
+
     #[inline]
-    pub fn set_bit(&mut self, i: usize, val: bool) {
+    pub const fn set_bit(&mut self, i: usize, val: bool) {
         let word = i / 64;
         let bit = i % 64;
         if val {
@@ -30,35 +80,168 @@ impl IrisCodeArray {
             self.0[word] &= !(1u64 << bit);
         }
     }
-    pub fn bits(&self) -> Bits<'_> {
+    pub fn bits(&self) -> Bits<'_, WORDS> {
         Bits {
             code: self,
             current: 0,
             index: 0,
         }
     }
+    /// Indices of the set bits in ascending order.
+    pub fn ones(&self) -> Ones<'_, WORDS> {
+        Ones {
+            code: self,
+            current: self.0.first().copied().unwrap_or(0),
+            word: 0,
+        }
+    }
     #[inline]
-    pub fn get_bit(&self, i: usize) -> bool {
+    pub const fn get_bit(&self, i: usize) -> bool {
         let word = i / 64;
         let bit = i % 64;
         (self.0[word] >> bit) & 1 == 1
     }
     #[inline]
-    pub fn flip_bit(&mut self, i: usize) {
+    pub const fn flip_bit(&mut self, i: usize) {
         let word = i / 64;
         let bit = i % 64;
         self.0[word] ^= 1u64 << bit;
     }
 
+    /// Sets the bits in `start..end` to `val`, a word at a time.
+    pub fn set_range(&mut self, start: usize, end: usize, val: bool) {
+        assert!(start <= end && end <= Self::IRIS_CODE_SIZE);
+        let mut i = start;
+        while i < end {
+            let word = i / 64;
+            let bit = i % 64;
+            let len = (64 - bit).min(end - i);
+            let bits = if len == 64 {
+                u64::MAX
+            } else {
+                ((1u64 << len) - 1) << bit
+            };
+            if val {
+                self.0[word] |= bits;
+            } else {
+                self.0[word] &= !bits;
+            }
+            i += len;
+        }
+    }
+
+    pub fn clear_all(&mut self) {
+        self.0 = [0; WORDS];
+    }
+
+    pub fn fill(&mut self) {
+        self.0 = [u64::MAX; WORDS];
+    }
+
     #[inline]
+    #[cfg(feature = "rand")]
     pub fn random_rng<R: Rng>(rng: &mut R) -> Self {
-        let mut code = IrisCodeArray::ZERO;
+        let mut code = Self::ZERO;
         rng.fill(code.as_raw_mut_slice());
         code
     }
 
+    pub const fn is_zero(&self) -> bool {
+        let mut i = 0;
+        while i < WORDS {
+            if self.0[i] != 0 {
+                return false;
+            }
+            i += 1;
+        }
+        true
+    }
+
+    /// Whether every bit set in `self` is also set in `other`.
+    pub fn is_subset_of(&self, other: &Self) -> bool {
+        self.0.iter().zip(&other.0).all(|(a, b)| a & !b == 0)
+    }
+
     pub fn count_ones(&self) -> usize {
-        self.0.iter().map(|c| c.count_ones() as usize).sum()
+        simd::count_ones(&self.0) as usize
+    }
+
+    /// Parses the raw little-endian words, as returned by [`IrisCodeArray::as_raw_slice`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, IrisError> {
+        if bytes.len() != Self::IRIS_CODE_SIZE_BYTES {
+            return Err(IrisError::InvalidLength {
+                expected: Self::IRIS_CODE_SIZE_BYTES,
+                actual: bytes.len(),
+            });
+        }
+        let mut array = Self::ZERO;
+        for (word, chunk) in array.0.iter_mut().zip(bytes.chunks_exact(8)) {
+            *word = u64::from_le_bytes(chunk.try_into().unwrap());
+        }
+        Ok(array)
+    }
+
+    /// Takes bit i from the i-th item, e.g. a flattened numpy bool array. Fails unless there are
+    /// exactly [`IrisCodeArray::IRIS_CODE_SIZE`] items.
+    pub fn from_bits(bits: impl IntoIterator<Item = bool>) -> Result<Self, IrisError> {
+        let mut array = Self::ZERO;
+        let mut len = 0;
+        for bit in bits {
+            if len < Self::IRIS_CODE_SIZE {
+                array.set_bit(len, bit);
+            }
+            len += 1;
+        }
+        if len != Self::IRIS_CODE_SIZE {
+            return Err(IrisError::InvalidLength {
+                expected: Self::IRIS_CODE_SIZE,
+                actual: len,
+            });
+        }
+        Ok(array)
+    }
+
+    pub fn to_bit_vec(&self) -> Vec<bool> {
+        self.bits().collect()
+    }
+
+    /// Decodes the format of the open-iris encoder: the flattened (row, column, filter) bits
+    /// packed most significant bit first, as by `np.packbits`, then base64 encoded.
+    pub fn from_base64(encoded: &str) -> Result<Self, IrisError> {
+        let mut bytes = STANDARD
+            .decode(encoded)
+            .map_err(|err| IrisError::InvalidEncoding(err.to_string()))?;
+        // bit i of the code is the i-th bit of the stream, i.e. packed least significant first
+        bytes
+            .iter_mut()
+            .for_each(|byte| *byte = byte.reverse_bits());
+        Self::from_bytes(&bytes)
+    }
+
+    /// Inverse of [`IrisCodeArray::from_base64`].
+    pub fn to_base64(&self) -> String {
+        let bytes: Vec<u8> = self
+            .as_raw_slice()
+            .iter()
+            .map(|byte| byte.reverse_bits())
+            .collect();
+        STANDARD.encode(bytes)
+    }
+
+    /// Copy with the columns of every row rolled by `shift` positions under `layout`.
+    pub(crate) fn rotated(&self, layout: &TemplateLayout, shift: isize) -> Self {
+        let mut res = Self::ZERO;
+        for row in 0..layout.rows {
+            for column in 0..layout.columns {
+                let target = (column as isize + shift).rem_euclid(layout.columns as isize) as usize;
+                for filter in 0..layout.filters {
+                    if self.get_bit(layout.bit_index(row, column, filter)) {
+                        res.set_bit(layout.bit_index(row, target, filter), true);
+                    }
+                }
+            }
+        }
+        res
     }
 
     pub fn as_raw_slice(&self) -> &[u8] {
@@ -69,7 +252,7 @@ impl IrisCodeArray {
     }
 }
 
-impl std::ops::BitAndAssign for IrisCodeArray {
+impl<const WORDS: usize> core::ops::BitAndAssign for IrisCodeArray<WORDS> {
     #[inline]
     fn bitand_assign(&mut self, rhs: Self) {
         for i in 0..Self::IRIS_CODE_SIZE_U64 {
@@ -77,18 +260,37 @@ impl std::ops::BitAndAssign for IrisCodeArray {
         }
     }
 }
-impl std::ops::BitAnd for IrisCodeArray {
+impl<const WORDS: usize> core::ops::BitAnd for IrisCodeArray<WORDS> {
     type Output = Self;
     #[inline]
     fn bitand(self, rhs: Self) -> Self::Output {
-        let mut res = IrisCodeArray::ZERO;
+        let mut res = Self::ZERO;
         for i in 0..Self::IRIS_CODE_SIZE_U64 {
             res.0[i] = self.0[i] & rhs.0[i];
         }
         res
     }
 }
-impl std::ops::BitXorAssign for IrisCodeArray {
+impl<const WORDS: usize> core::ops::BitOrAssign for IrisCodeArray<WORDS> {
+    #[inline]
+    fn bitor_assign(&mut self, rhs: Self) {
+        for i in 0..Self::IRIS_CODE_SIZE_U64 {
+            self.0[i] |= rhs.0[i];
+        }
+    }
+}
+impl<const WORDS: usize> core::ops::BitOr for IrisCodeArray<WORDS> {
+    type Output = Self;
+    #[inline]
+    fn bitor(self, rhs: Self) -> Self::Output {
+        let mut res = Self::ZERO;
+        for i in 0..Self::IRIS_CODE_SIZE_U64 {
+            res.0[i] = self.0[i] | rhs.0[i];
+        }
+        res
+    }
+}
+impl<const WORDS: usize> core::ops::BitXorAssign for IrisCodeArray<WORDS> {
     #[inline]
     fn bitxor_assign(&mut self, rhs: Self) {
         for i in 0..Self::IRIS_CODE_SIZE_U64 {
@@ -96,103 +298,632 @@ impl std::ops::BitXorAssign for IrisCodeArray {
         }
     }
 }
-impl std::ops::BitXor for IrisCodeArray {
+impl<const WORDS: usize> core::ops::BitXor for IrisCodeArray<WORDS> {
     type Output = Self;
     #[inline]
     fn bitxor(self, rhs: Self) -> Self::Output {
-        let mut res = IrisCodeArray::ZERO;
+        let mut res = Self::ZERO;
         for i in 0..Self::IRIS_CODE_SIZE_U64 {
             res.0[i] = self.0[i] ^ rhs.0[i];
         }
         res
     }
 }
+impl<const WORDS: usize> core::ops::Not for IrisCodeArray<WORDS> {
+    type Output = Self;
+    #[inline]
+    fn not(self) -> Self::Output {
+        let mut res = Self::ZERO;
+        for i in 0..Self::IRIS_CODE_SIZE_U64 {
+            res.0[i] = !self.0[i];
+        }
+        res
+    }
+}
+/// Bits set in `self` but not in `rhs`.
+impl<const WORDS: usize> core::ops::Sub for IrisCodeArray<WORDS> {
+    type Output = Self;
+    #[inline]
+    #[allow(clippy::suspicious_arithmetic_impl)]
+    fn sub(self, rhs: Self) -> Self::Output {
+        let mut res = Self::ZERO;
+        for i in 0..Self::IRIS_CODE_SIZE_U64 {
+            res.0[i] = self.0[i] & !rhs.0[i];
+        }
+        res
+    }
+}
+
+impl<const WORDS: usize> TryFrom<&[u8]> for IrisCodeArray<WORDS> {
+    type Error = IrisError;
 
-#[derive(Clone, Debug)]
-pub struct IrisCode {
-    pub code: IrisCodeArray,
-    pub mask: IrisCodeArray,
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        Self::from_bytes(bytes)
+    }
 }
-impl Default for IrisCode {
-    fn default() -> Self {
-        Self {
-            code: IrisCodeArray::ZERO,
-            mask: IrisCodeArray::ONES,
+
+// hex of the raw bytes as in IrisCodeArray::as_raw_slice, two digits per byte
+impl<const WORDS: usize> fmt::LowerHex for IrisCodeArray<WORDS> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            f.write_str("0x")?;
+        }
+        for byte in self.as_raw_slice() {
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+impl<const WORDS: usize> fmt::Display for IrisCodeArray<WORDS> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::LowerHex::fmt(self, f)
+    }
+}
+
+/// Parses the output of [`fmt::LowerHex`], with or without `0x` prefix.
+impl<const WORDS: usize> FromStr for IrisCodeArray<WORDS> {
+    type Err = IrisError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let digits = s.strip_prefix("0x").unwrap_or(s);
+        if digits.len() != 2 * Self::IRIS_CODE_SIZE_BYTES {
+            return Err(IrisError::InvalidLength {
+                expected: 2 * Self::IRIS_CODE_SIZE_BYTES,
+                actual: digits.len(),
+            });
+        }
+        let bytes = (0..digits.len())
+            .step_by(2)
+            .map(|i| {
+                digits
+                    .get(i..i + 2)
+                    .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                    .ok_or_else(|| IrisError::InvalidEncoding(format!("invalid hex {s:?}")))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Self::from_bytes(&bytes)
+    }
+}
+
+// serialized as raw bytes rather than a list of words, see IrisCodeArray::as_raw_slice
+impl<const WORDS: usize> Serialize for IrisCodeArray<WORDS> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(self.as_raw_slice())
+    }
+}
+
+impl<'de, const WORDS: usize> Deserialize<'de> for IrisCodeArray<WORDS> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_bytes(BytesVisitor::<WORDS>)
+    }
+}
+
+struct BytesVisitor<const WORDS: usize>;
+
+impl<'de, const WORDS: usize> Visitor<'de> for BytesVisitor<WORDS> {
+    type Value = IrisCodeArray<WORDS>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} bytes", IrisCodeArray::<WORDS>::IRIS_CODE_SIZE_BYTES)
+    }
+
+    fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<Self::Value, E> {
+        IrisCodeArray::from_bytes(bytes).map_err(E::custom)
+    }
+
+    // formats without a bytes type, e.g. JSON, hand them over as a sequence
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut bytes = Vec::with_capacity(IrisCodeArray::<WORDS>::IRIS_CODE_SIZE_BYTES);
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
         }
+        IrisCodeArray::from_bytes(&bytes).map_err(de::Error::custom)
     }
 }
 
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct IrisCode<const WORDS: usize = DEFAULT_IRIS_CODE_WORDS> {
+    pub code: IrisCodeArray<WORDS>,
+    pub mask: IrisCodeArray<WORDS>,
+}
+impl<const WORDS: usize> Default for IrisCode<WORDS> {
+    fn default() -> Self {
+        Self::new(IrisCodeArray::ZERO, IrisCodeArray::ONES)
+    }
+}
+
+// fixed-size merged arrays need the width spelled out, so they only exist for the default one
 impl IrisCode {
-    pub const IRIS_CODE_SIZE: usize = IrisCodeArray::IRIS_CODE_SIZE;
+    pub fn as_merged_array(&self) -> [u64; 2 * DEFAULT_IRIS_CODE_WORDS] {
+        let mut res = [0; 2 * DEFAULT_IRIS_CODE_WORDS];
+        res[0..DEFAULT_IRIS_CODE_WORDS].copy_from_slice(&self.code.0);
+        res[DEFAULT_IRIS_CODE_WORDS..].copy_from_slice(&self.mask.0);
+        res
+    }
 
-    pub fn as_merged_array(
-        &self,
-    ) -> [u64; IrisCodeArray::IRIS_CODE_SIZE_U64 + IrisCodeArray::IRIS_CODE_SIZE_U64] {
-        let mut res = [0; IrisCodeArray::IRIS_CODE_SIZE_U64 + IrisCodeArray::IRIS_CODE_SIZE_U64];
-        res[0..IrisCodeArray::IRIS_CODE_SIZE_U64].copy_from_slice(&self.code.0);
-        res[IrisCodeArray::IRIS_CODE_SIZE_U64..].copy_from_slice(&self.mask.0);
+    pub fn from_merged_array(merged: &[u64; 2 * DEFAULT_IRIS_CODE_WORDS]) -> Self {
+        let mut res = Self::default();
+        res.code
+            .0
+            .copy_from_slice(&merged[0..DEFAULT_IRIS_CODE_WORDS]);
+        res.mask
+            .0
+            .copy_from_slice(&merged[DEFAULT_IRIS_CODE_WORDS..]);
         res
     }
 
+    /// Minimum of [`IrisCode::get_distance`] over shifts of `other` by up to `max_shift` columns
+    /// either way, which tolerates head tilt between captures. NaN if the masks don't overlap in
+    /// any of them.
+    pub fn get_distance_rotated(&self, other: &Self, max_shift: usize) -> f64 {
+        let max_shift = max_shift as isize;
+        (-max_shift..=max_shift)
+            .filter_map(|shift| self.get_distance_checked(&other.rotated(shift)))
+            .reduce(f64::min)
+            .unwrap_or(f64::NAN)
+    }
+
+    /// Like [`IrisCode::get_distance`], with every bit counting by its weight, so more
+    /// discriminative regions of the iris weigh more.
+    #[cfg(feature = "std")]
+    pub fn get_weighted_distance(&self, other: &Self, weights: &BitWeights) -> f64 {
+        let combined_mask = self.mask & other.mask;
+        let combined_code = (self.code ^ other.code) & combined_mask;
+        weights.sum_over(&combined_code) / weights.sum_over(&combined_mask)
+    }
+
+    /// Code and mask with their columns rolled by `shift` positions under [`DEFAULT_LAYOUT`].
+    pub(crate) fn rotated(&self, shift: isize) -> Self {
+        Self {
+            code: self.code.rotated(&DEFAULT_LAYOUT, shift),
+            mask: self.mask.rotated(&DEFAULT_LAYOUT, shift),
+        }
+    }
+}
+
+impl<const WORDS: usize> IrisCode<WORDS> {
+    pub const IRIS_CODE_SIZE: usize = IrisCodeArray::<WORDS>::IRIS_CODE_SIZE;
+
+    pub const fn new(code: IrisCodeArray<WORDS>, mask: IrisCodeArray<WORDS>) -> Self {
+        Self { code, mask }
+    }
+
+    /// Splits code and mask words merged as in [`IrisCode::as_merged_array`].
+    pub fn from_merged_slice(merged: &[u64]) -> Result<Self, IrisError> {
+        if merged.len() != 2 * WORDS {
+            return Err(IrisError::InvalidLength {
+                expected: 2 * WORDS,
+                actual: merged.len(),
+            });
+        }
+        let mut res = Self::default();
+        res.code.0.copy_from_slice(&merged[..WORDS]);
+        res.mask.0.copy_from_slice(&merged[WORDS..]);
+        Ok(res)
+    }
+
+    /// Parses raw little-endian code and mask words.
+    pub fn from_bytes(code: &[u8], mask: &[u8]) -> Result<Self, IrisError> {
+        Ok(Self {
+            code: IrisCodeArray::from_bytes(code)?,
+            mask: IrisCodeArray::from_bytes(mask)?,
+        })
+    }
+
+    /// Like [`IrisCode::from_bytes`], but also checks the mask invariants of open-iris templates:
+    /// at least one usable bit, and bits duplicated pairwise as the encoder writes them.
+    pub fn try_from_raw(code: &[u8], mask: &[u8]) -> Result<Self, IrisError> {
+        let res = Self::from_bytes(code, mask)?;
+        res.validate(&ValidationRules {
+            min_mask_bits: 1,
+            pairwise_mask: true,
+            ..ValidationRules::default()
+        })?;
+        Ok(res)
+    }
+
+    /// Writes the compact binary encoding: the format version, the code width in bits as a
+    /// little-endian u32, then the raw code and mask bytes.
+    #[cfg(feature = "std")]
+    pub fn write_to(&self, mut writer: impl Write) -> io::Result<()> {
+        writer.write_all(&[TEMPLATE_FORMAT_VERSION])?;
+        writer.write_all(&(Self::IRIS_CODE_SIZE as u32).to_le_bytes())?;
+        writer.write_all(self.code.as_raw_slice())?;
+        writer.write_all(self.mask.as_raw_slice())
+    }
+
+    /// Reads a template written by [`IrisCode::write_to`], rejecting other versions and widths.
+    #[cfg(feature = "std")]
+    pub fn read_from(mut reader: impl Read) -> io::Result<Self> {
+        let mut header = [0; 5];
+        reader.read_exact(&mut header)?;
+        if header[0] != TEMPLATE_FORMAT_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported template format version {}", header[0]),
+            ));
+        }
+        let bits = u32::from_le_bytes(header[1..].try_into().unwrap()) as usize;
+        if bits != Self::IRIS_CODE_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "template has {bits} bits, expected {}",
+                    Self::IRIS_CODE_SIZE
+                ),
+            ));
+        }
+        let mut res = Self::default();
+        reader.read_exact(res.code.as_raw_mut_slice())?;
+        reader.read_exact(res.mask.as_raw_mut_slice())?;
+        Ok(res)
+    }
+
+    /// Decodes the `iris_codes` and `mask_codes` fields of a serialized open-iris template, see
+    /// [`IrisCodeArray::from_base64`].
+    pub fn from_base64(code: &str, mask: &str) -> Result<Self, IrisError> {
+        Ok(Self {
+            code: IrisCodeArray::from_base64(code)?,
+            mask: IrisCodeArray::from_base64(mask)?,
+        })
+    }
+
+    /// Code and mask in the format of [`IrisCode::from_base64`].
+    pub fn to_base64(&self) -> (String, String) {
+        (self.code.to_base64(), self.mask.to_base64())
+    }
+
+    #[cfg(feature = "rand")]
     pub fn random_rng<R: Rng>(rng: &mut R) -> Self {
-        let mut code = IrisCode {
+        Self::random_with_params(rng, DEFAULT_MASK_DROPOUT, true)
+            .expect("DEFAULT_MASK_DROPOUT is a valid probability")
+    }
+
+    /// Random code with about `mask_dropout` of the mask bits removed. With `pairwise`, bits are
+    /// removed in adjacent pairs, like the mask duplicated in the last dimension by open-iris.
+    #[cfg(feature = "rand")]
+    pub fn random_with_params<R: Rng>(
+        rng: &mut R,
+        mask_dropout: f64,
+        pairwise: bool,
+    ) -> Result<Self, IrisError> {
+        if !(0.0..=1.0).contains(&mask_dropout) {
+            return Err(IrisError::InvalidProbability(mask_dropout));
+        }
+        let mut code = Self {
             code: IrisCodeArray::random_rng(rng),
             mask: IrisCodeArray::ONES,
         };
 
-        // remove about 10% of the mask bits
-        // masks are duplicated in the last dimension, so we always need to set the bits
-        // pairwise <https://github.com/worldcoin/iris/blob/e43e32748fd6800aa1ee11b0e79261d5ed62d776/src/iris/nodes/encoder/iris_encoder.py#L46>
-        for _ in 0..Self::IRIS_CODE_SIZE / 10 / 2 {
-            let i = rng.gen_range(0..Self::IRIS_CODE_SIZE / 2);
-            code.mask.set_bit(2 * i, false);
-            code.mask.set_bit(2 * i + 1, false);
+        // positions are drawn with replacement, so slightly fewer bits end up removed
+        let removed = (Self::IRIS_CODE_SIZE as f64 * mask_dropout) as usize;
+        if pairwise {
+            // masks are duplicated in the last dimension, so we always need to set the bits
+            // pairwise <https://github.com/worldcoin/iris/blob/e43e32748fd6800aa1ee11b0e79261d5ed62d776/src/iris/nodes/encoder/iris_encoder.py#L46>
+            for _ in 0..removed / 2 {
+                let i = rng.gen_range(0..Self::IRIS_CODE_SIZE / 2);
+                code.mask.set_bit(2 * i, false);
+                code.mask.set_bit(2 * i + 1, false);
+            }
+        } else {
+            for _ in 0..removed {
+                let i = rng.gen_range(0..Self::IRIS_CODE_SIZE);
+                code.mask.set_bit(i, false);
+            }
         }
 
-        code
+        Ok(code)
     }
 
+    /// Hamming distance over the common mask and the size of that mask, i.e. numerator and
+    /// denominator of [`IrisCode::get_distance`]. Thresholds can be compared exactly against it by
+    /// cross-multiplying.
+    pub fn get_distance_fraction(&self, other: &Self) -> (u32, u32) {
+        simd::masked_hamming(&self.code.0, &self.mask.0, &other.code.0, &other.mask.0)
+    }
+
+    /// Full outcome of the masked comparison, keeping the overlap that [`IrisCode::get_distance`]
+    /// folds into the ratio.
+    pub fn get_masked_distance(&self, other: &Self) -> MaskedDistance {
+        let (hamming, overlap) = self.get_distance_fraction(other);
+        MaskedDistance::new(hamming, overlap)
+    }
+
+    /// Like [`IrisCode::get_distance_fraction`], given `mask.count_ones()` of both templates, e.g.
+    /// cached with stored ones. When either mask is full the overlap is the other count, so only
+    /// the differing code bits have to be counted.
+    pub fn get_distance_fraction_cached(
+        &self,
+        other: &Self,
+        mask_ones: u32,
+        other_mask_ones: u32,
+    ) -> (u32, u32) {
+        let full = Self::IRIS_CODE_SIZE as u32;
+        if other_mask_ones == full {
+            let code_distance = ((self.code ^ other.code) & self.mask).count_ones();
+            (code_distance as u32, mask_ones)
+        } else if mask_ones == full {
+            let code_distance = ((self.code ^ other.code) & other.mask).count_ones();
+            (code_distance as u32, other_mask_ones)
+        } else {
+            self.get_distance_fraction(other)
+        }
+    }
+
+    /// Fractional Hamming distance over the bits both masks mark valid, NaN if there are none.
     pub fn get_distance(&self, other: &Self) -> f64 {
-        let combined_mask = self.mask & other.mask;
-        let combined_mask_len = combined_mask.count_ones();
+        let (code_distance, combined_mask_len) = self.get_distance_fraction(other);
+        code_distance as f64 / combined_mask_len as f64
+    }
 
-        let combined_code = (self.code ^ other.code) & combined_mask;
-        let code_distance = combined_code.count_ones();
+    /// Like [`IrisCode::get_distance`], but stops counting once the distance is certain to exceed
+    /// `bound`. The result is then some value above `bound` instead of the exact distance. 1.0
+    /// instead of NaN when the masks don't overlap, like the graph distances.
+    pub fn get_distance_with_bound(&self, other: &Self, bound: f64) -> f64 {
+        // at least four chunks, so that narrow codes can stop early too
+        let chunk_words = WORDS.div_ceil(4).clamp(1, 8);
+        let mut code_distance = 0;
+        let mut combined_mask_len = 0;
+        for start in (0..WORDS).step_by(chunk_words) {
+            let end = (start + chunk_words).min(WORDS);
+            let (chunk_distance, chunk_mask_len) = simd::masked_hamming(
+                &self.code.0[start..end],
+                &self.mask.0[start..end],
+                &other.code.0[start..end],
+                &other.mask.0[start..end],
+            );
+            code_distance += chunk_distance;
+            combined_mask_len += chunk_mask_len;
+            // at most every remaining bit can still join the common mask
+            let max_mask_len = combined_mask_len as usize + (WORDS - end) * 64;
+            if code_distance as f64 > bound * max_mask_len as f64 {
+                return code_distance as f64 / max_mask_len as f64;
+            }
+        }
+        if combined_mask_len == 0 {
+            return 1.0;
+        }
         code_distance as f64 / combined_mask_len as f64
     }
 
+    /// Like [`IrisCode::get_distance`], but `None` instead of NaN when the masks don't overlap.
+    pub fn get_distance_checked(&self, other: &Self) -> Option<f64> {
+        let (code_distance, combined_mask_len) = self.get_distance_fraction(other);
+        (combined_mask_len > 0).then(|| code_distance as f64 / combined_mask_len as f64)
+    }
+
+    /// Like [`IrisCode::get_distance`], but 1.0 (never a match) when the masks have fewer than
+    /// `min_overlap` bits in common, as the distance over so few bits is mostly noise.
+    pub fn get_distance_with_min_overlap(&self, other: &Self, min_overlap: u32) -> f64 {
+        let (code_distance, combined_mask_len) = self.get_distance_fraction(other);
+        if combined_mask_len < min_overlap {
+            return 1.0;
+        }
+        code_distance as f64 / combined_mask_len as f64
+    }
+
+    /// Jaccard (Tanimoto) distance between the set code bits within the common mask. 1.0 when
+    /// the masks don't overlap or neither code has a set bit within the overlap, so occluded or
+    /// blank templates never match.
+    pub fn get_jaccard_distance(&self, other: &Self) -> f64 {
+        let combined_mask = self.mask & other.mask;
+        let intersection = (self.code & other.code & combined_mask).count_ones();
+        let union = intersection + ((self.code ^ other.code) & combined_mask).count_ones();
+        if union == 0 {
+            return 1.0;
+        }
+        1.0 - intersection as f64 / union as f64
+    }
+
+    /// Fraction of differing code bits, ignoring both masks.
+    pub fn hamming_distance(&self, other: &Self) -> f64 {
+        (self.code ^ other.code).count_ones() as f64 / Self::IRIS_CODE_SIZE as f64
+    }
+
     pub fn is_close(&self, other: &Self) -> bool {
-        self.get_distance(other) < MATCH_THRESHOLD_RATIO
+        self.get_distance_with_bound(other, MATCH_THRESHOLD_RATIO) < MATCH_THRESHOLD_RATIO
+    }
+
+    #[cfg(feature = "rand")]
+    pub fn get_similar_iris<R: Rng>(
+        &self,
+        rng: &mut R,
+        flip_probability: f64,
+    ) -> Result<Self, IrisError> {
+        self.get_similar_iris_with(rng, &NoiseParams::uniform(flip_probability))
+    }
+
+    /// Consensus of several captures of one eye: every code bit takes the majority of the samples
+    /// and the mask is the intersection of theirs. Bits without a majority are fragile, so they
+    /// are masked out as well. Fails for an empty slice.
+    pub fn fuse(samples: &[Self]) -> Result<Self, IrisError> {
+        let Some(first) = samples.first() else {
+            return Err(IrisError::InvalidLength {
+                expected: 1,
+                actual: 0,
+            });
+        };
+        let mut res = Self::new(IrisCodeArray::ZERO, first.mask);
+        for sample in &samples[1..] {
+            res.mask &= sample.mask;
+        }
+        for i in 0..Self::IRIS_CODE_SIZE {
+            let ones = samples
+                .iter()
+                .filter(|sample| sample.code.get_bit(i))
+                .count();
+            if 2 * ones > samples.len() {
+                res.code.set_bit(i, true);
+            } else if 2 * ones == samples.len() {
+                res.mask.set_bit(i, false);
+            }
+        }
+        Ok(res)
     }
 
-    pub fn get_similar_iris<R: Rng>(&self, rng: &mut R) -> IrisCode {
+    pub fn quality(&self) -> TemplateQuality {
+        let usable_bits = self.mask.count_ones();
+        let ones = (self.code & self.mask).count_ones();
+        TemplateQuality {
+            usable_bits,
+            coverage: usable_bits as f64 / Self::IRIS_CODE_SIZE as f64,
+            balance: ones as f64 / usable_bits.max(1) as f64,
+        }
+    }
+
+    /// Rejects templates that break `rules`, e.g. a capture that is too occluded to compare.
+    pub fn validate(&self, rules: &ValidationRules) -> Result<(), IrisError> {
+        let usable = self.mask.count_ones();
+        if usable < rules.min_mask_bits {
+            return Err(IrisError::InsufficientMask {
+                usable,
+                required: rules.min_mask_bits,
+            });
+        }
+        if rules.min_quality > 0.0 {
+            let score = self.quality().score();
+            if score < rules.min_quality {
+                return Err(IrisError::LowQuality {
+                    score,
+                    required: rules.min_quality,
+                });
+            }
+        }
+        if rules.pairwise_mask {
+            for (i, word) in self.mask.0.iter().enumerate() {
+                // even bits where the next bit differs
+                let unpaired = (word ^ (word >> 1)) & 0x5555_5555_5555_5555;
+                if unpaired != 0 {
+                    return Err(IrisError::UnpairedMask {
+                        bit: i * 64 + unpaired.trailing_zeros() as usize,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Copy with code and mask bits flipped independently at the rates of `params`.
+    #[cfg(feature = "rand")]
+    pub fn get_similar_iris_with<R: Rng>(
+        &self,
+        rng: &mut R,
+        params: &NoiseParams,
+    ) -> Result<Self, IrisError> {
         let mut res = self.clone();
-        // flip a few bits in mask and code (like 5%)
-        let dist = Bernoulli::new(0.05).unwrap();
-        for i in 0..IrisCode::IRIS_CODE_SIZE {
-            if dist.sample(rng) {
-                res.code.flip_bit(i);
+        let code_flips = Bernoulli::new(params.code_flip_probability)
+            .map_err(|_| IrisError::InvalidProbability(params.code_flip_probability))?;
+        let mask_flips = Bernoulli::new(params.mask_flip_probability)
+            .map_err(|_| IrisError::InvalidProbability(params.mask_flip_probability))?;
+        // a pairwise flip covers both bits of a duplicated pair
+        let width = if params.pairwise { 2 } else { 1 };
+        for i in (0..Self::IRIS_CODE_SIZE).step_by(width) {
+            if code_flips.sample(rng) {
+                (i..i + width).for_each(|bit| res.code.flip_bit(bit));
             }
-            if dist.sample(rng) {
-                res.mask.flip_bit(i);
+            if mask_flips.sample(rng) {
+                (i..i + width).for_each(|bit| res.mask.flip_bit(bit));
             }
         }
 
-        res
+        Ok(res)
     }
 }
 
-pub struct Bits<'a> {
-    code: &'a IrisCodeArray,
+/// Differing bits over the common mask, the size of that mask and their ratio, NaN without
+/// overlap.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct MaskedDistance {
+    pub hamming: u32,
+    pub overlap: u32,
+    pub ratio: f64,
+}
+
+impl MaskedDistance {
+    pub fn new(hamming: u32, overlap: u32) -> Self {
+        Self {
+            hamming,
+            overlap,
+            ratio: hamming as f64 / overlap as f64,
+        }
+    }
+
+    /// Whether the ratio is below `threshold` over at least `min_overlap` common bits.
+    pub fn is_match(&self, threshold: f64, min_overlap: u32) -> bool {
+        self.overlap > 0 && self.overlap >= min_overlap && self.ratio < threshold
+    }
+}
+
+/// Requirements checked by [`IrisCode::validate`], none by default.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ValidationRules {
+    /// Fewest usable mask bits, as distances over a mostly occluded template are mostly noise.
+    pub min_mask_bits: usize,
+    /// Adjacent mask bits must be equal, as the open-iris encoder duplicates them.
+    pub pairwise_mask: bool,
+    /// Lowest [`TemplateQuality::score`].
+    pub min_quality: f64,
+}
+
+/// Statistics of how much a template can be trusted, see [`IrisCode::quality`].
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct TemplateQuality {
+    /// Usable mask bits.
+    pub usable_bits: usize,
+    /// Share of the code covered by the mask.
+    pub coverage: f64,
+    /// Share of ones among the usable code bits, about 0.5 for a healthy capture.
+    pub balance: f64,
+}
+
+impl TemplateQuality {
+    /// Coverage scaled down by the imbalance of the code bits, in [0, 1]. Templates with little
+    /// coverage are the main source of false matches, skewed ones usually come from a bad capture.
+    pub fn score(&self) -> f64 {
+        // distance of the balance from 0.5, scaled to [0, 1]
+        let skew = (2.0 * self.balance - 1.0).max(1.0 - 2.0 * self.balance);
+        self.coverage * (1.0 - skew)
+    }
+}
+
+/// Intra-class noise of [`IrisCode::get_similar_iris_with`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NoiseParams {
+    pub code_flip_probability: f64,
+    pub mask_flip_probability: f64,
+    /// Flip adjacent bit pairs together, like the duplicated last dimension of open-iris codes.
+    pub pairwise: bool,
+}
+
+impl NoiseParams {
+    /// The same rate for code and mask bits, flipped one by one.
+    pub fn uniform(flip_probability: f64) -> Self {
+        Self {
+            code_flip_probability: flip_probability,
+            mask_flip_probability: flip_probability,
+            pairwise: false,
+        }
+    }
+}
+
+impl Default for NoiseParams {
+    fn default() -> Self {
+        Self::uniform(DEFAULT_FLIP_PROBABILITY)
+    }
+}
+
+pub struct Bits<'a, const WORDS: usize = DEFAULT_IRIS_CODE_WORDS> {
+    code: &'a IrisCodeArray<WORDS>,
     current: u64,
     index: usize,
 }
 
-impl Iterator for Bits<'_> {
+impl<const WORDS: usize> Iterator for Bits<'_, WORDS> {
     type Item = bool;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.index >= IrisCodeArray::IRIS_CODE_SIZE {
+        if self.index >= IrisCodeArray::<WORDS>::IRIS_CODE_SIZE {
             None
         } else {
             if self.index % 64 == 0 {
@@ -207,10 +938,275 @@ impl Iterator for Bits<'_> {
 
     fn size_hint(&self) -> (usize, Option<usize>) {
         (
-            IrisCodeArray::IRIS_CODE_SIZE - self.index,
-            Some(IrisCodeArray::IRIS_CODE_SIZE - self.index),
+            IrisCodeArray::<WORDS>::IRIS_CODE_SIZE - self.index,
+            Some(IrisCodeArray::<WORDS>::IRIS_CODE_SIZE - self.index),
         )
     }
 }
 
-impl ExactSizeIterator for Bits<'_> {}
+impl<const WORDS: usize> ExactSizeIterator for Bits<'_, WORDS> {}
+
+/// Iterator over the indices of set bits, see [`IrisCodeArray::ones`].
+pub struct Ones<'a, const WORDS: usize = DEFAULT_IRIS_CODE_WORDS> {
+    code: &'a IrisCodeArray<WORDS>,
+    current: u64,
+    word: usize,
+}
+
+impl<const WORDS: usize> Iterator for Ones<'_, WORDS> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.current == 0 {
+            self.word += 1;
+            if self.word >= WORDS {
+                return None;
+            }
+            self.current = self.code.0[self.word];
+        }
+        let bit = self.current.trailing_zeros() as usize;
+        // clear the lowest set bit
+        self.current &= self.current - 1;
+        Some(self.word * 64 + bit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    // splitmix64 words, so the codes are the same on every run without needing `rand`
+    fn array<const WORDS: usize>(seed: u64) -> IrisCodeArray<WORDS> {
+        let mut state = seed;
+        IrisCodeArray::new(core::array::from_fn(|_| {
+            state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^ (z >> 31)
+        }))
+    }
+
+    // a random code with about three quarters of the mask set
+    fn code<const WORDS: usize>(seed: u64) -> IrisCode<WORDS> {
+        IrisCode::new(array(3 * seed), array(3 * seed + 1) | array(3 * seed + 2))
+    }
+
+    // np.packbits of a flattened open-iris code with bits 0, 9 and 127 set, base64 encoded
+    const OPEN_IRIS_BASE64: &str = "gEAAAAAAAAAAAAAAAAAAAQ==";
+
+    #[test]
+    fn base64_matches_open_iris() {
+        let array = IrisCodeArray::<2>::ZERO
+            .with_bit(0, true)
+            .with_bit(9, true)
+            .with_bit(127, true);
+        assert_eq!(array.to_base64(), OPEN_IRIS_BASE64);
+        assert_eq!(
+            IrisCodeArray::<2>::from_base64(OPEN_IRIS_BASE64).unwrap(),
+            array
+        );
+        assert!(matches!(
+            IrisCodeArray::<2>::from_base64("gEAA"),
+            Err(IrisError::InvalidLength {
+                expected: 16,
+                actual: 3
+            })
+        ));
+    }
+
+    #[test]
+    fn distance_with_bound_is_exact_up_to_the_bound() {
+        for seed in 0..50 {
+            let (a, b) = (code::<DEFAULT_IRIS_CODE_WORDS>(seed), code(seed + 1000));
+            let exact = a.get_distance(&b);
+            for bound in [
+                0.0,
+                exact / 2.0,
+                exact - 1e-9,
+                exact,
+                exact + 1e-9,
+                0.375,
+                1.0,
+            ] {
+                let bounded = a.get_distance_with_bound(&b, bound);
+                if exact <= bound {
+                    assert_eq!(bounded, exact, "seed {seed}, bound {bound}");
+                } else {
+                    assert!(bounded > bound, "seed {seed}, bound {bound}: {bounded}");
+                    assert!(bounded <= exact, "seed {seed}, bound {bound}: {bounded}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn distance_with_bound_stops_early() {
+        // four one-word chunks, the first of which already decides the bound
+        let a = IrisCode::<4>::new(IrisCodeArray::ZERO, IrisCodeArray::ONES);
+        let b = IrisCode::<4>::new(IrisCodeArray::new([u64::MAX, 0, 0, 0]), IrisCodeArray::ONES);
+        assert_eq!(a.get_distance_with_bound(&b, 0.25), 0.25);
+        assert_eq!(a.get_distance_with_bound(&b, 0.2), 0.25);
+        let b = IrisCode::<4>::new(IrisCodeArray::new([u64::MAX; 4]), IrisCodeArray::ONES);
+        // all that is known after the first chunk is 64 differing bits out of at most 256
+        assert_eq!(a.get_distance_with_bound(&b, 0.2), 0.25);
+        assert_eq!(a.get_distance_with_bound(&b, 1.0), 1.0);
+    }
+
+    #[test]
+    fn distance_with_bound_without_overlap() {
+        let a = IrisCode::<4>::new(IrisCodeArray::ZERO, IrisCodeArray::new([u64::MAX, 0, 0, 0]));
+        let b = IrisCode::<4>::new(IrisCodeArray::ONES, IrisCodeArray::new([0, 0, 0, u64::MAX]));
+        assert!(a.get_distance(&b).is_nan());
+        assert_eq!(a.get_distance_with_bound(&b, 0.375), 1.0);
+        assert!(!a.is_close(&b));
+    }
+
+    #[test]
+    fn set_range_matches_single_bits() {
+        let ranges = [
+            (0, 0),
+            (0, 1),
+            (3, 64),
+            (0, 64),
+            (63, 65),
+            (64, 128),
+            (10, 150),
+            (0, 192),
+        ];
+        for (start, end) in ranges {
+            for val in [false, true] {
+                let initial = array::<3>(start as u64);
+                let mut expected = initial;
+                (start..end).for_each(|i| expected.set_bit(i, val));
+                let mut ranged = initial;
+                ranged.set_range(start, end, val);
+                assert_eq!(ranged, expected, "{start}..{end} to {val}");
+            }
+        }
+        let mut filled = array::<3>(0);
+        filled.fill();
+        assert_eq!(filled, IrisCodeArray::ONES);
+        filled.clear_all();
+        assert_eq!(filled, IrisCodeArray::ZERO);
+    }
+
+    #[test]
+    #[should_panic]
+    fn set_range_rejects_ranges_past_the_end() {
+        IrisCodeArray::<2>::ZERO.set_range(100, 129, true);
+    }
+
+    #[test]
+    fn ones_lists_the_set_bits() {
+        for seed in 0..10 {
+            let array = array::<3>(seed) & array(seed + 100);
+            let expected: Vec<_> = array
+                .bits()
+                .enumerate()
+                .filter_map(|(i, bit)| bit.then_some(i))
+                .collect();
+            assert_eq!(array.ones().collect::<Vec<_>>(), expected);
+            assert_eq!(expected.len(), array.count_ones());
+        }
+        // runs of empty words and a set top bit
+        let sparse = IrisCodeArray::<4>::ZERO
+            .with_bit(1, true)
+            .with_bit(255, true);
+        assert_eq!(sparse.ones().collect::<Vec<_>>(), [1, 255]);
+        assert_eq!(IrisCodeArray::<4>::ZERO.ones().next(), None);
+        assert_eq!(IrisCodeArray::<1>::ONES.ones().count(), 64);
+    }
+
+    #[test]
+    fn rotated_distance_undoes_head_tilt() {
+        for seed in 0..10 {
+            let a = code::<DEFAULT_IRIS_CODE_WORDS>(seed);
+            let b = code(seed + 1000);
+            for shift in -3..=3 {
+                assert_eq!(a.get_distance_rotated(&a.rotated(shift), 3), 0.0);
+            }
+            let expected = (-2..=2)
+                .map(|shift| a.get_distance(&b.rotated(shift)))
+                .reduce(f64::min)
+                .unwrap();
+            assert_eq!(a.get_distance_rotated(&b, 2), expected);
+            assert_eq!(a.get_distance_rotated(&b, 0), a.get_distance(&b));
+        }
+        let blank = IrisCode::new(IrisCodeArray::ZERO, IrisCodeArray::ZERO);
+        assert!(code::<DEFAULT_IRIS_CODE_WORDS>(0)
+            .get_distance_rotated(&blank, 2)
+            .is_nan());
+    }
+
+    #[test]
+    fn fuse_takes_the_majority() {
+        let full = IrisCodeArray::<1>::ONES;
+        let samples = [
+            IrisCode::new(IrisCodeArray::new([0b110]), full),
+            IrisCode::new(IrisCodeArray::new([0b100]), full.with_bit(5, false)),
+            IrisCode::new(IrisCodeArray::new([0b011]), full),
+        ];
+        let fused = IrisCode::fuse(&samples).unwrap();
+        assert_eq!(fused.code, IrisCodeArray::new([0b110]));
+        assert_eq!(fused.mask, full.with_bit(5, false));
+        // bits without a majority are masked out
+        let fused = IrisCode::fuse(&samples[..2]).unwrap();
+        assert_eq!(fused.code, IrisCodeArray::new([0b100]));
+        assert_eq!(fused.mask, full.with_bit(1, false).with_bit(5, false));
+        assert_eq!(IrisCode::fuse(&samples[..1]).unwrap(), samples[0]);
+        assert!(IrisCode::<1>::fuse(&[]).is_err());
+    }
+
+    #[test]
+    fn validate_checks_every_rule() {
+        // a balanced code under a mask of 96 paired bits
+        let code = IrisCode::<2>::new(
+            IrisCodeArray::new([0xaaaa_aaaa_aaaa_aaaa; 2]),
+            IrisCodeArray::new([u64::MAX, 0xffff_ffff]),
+        );
+        assert!(code.validate(&ValidationRules::default()).is_ok());
+        let rules = ValidationRules {
+            min_mask_bits: 96,
+            pairwise_mask: true,
+            min_quality: 0.75,
+        };
+        assert!(code.validate(&rules).is_ok());
+
+        let rules = ValidationRules {
+            min_mask_bits: 97,
+            ..ValidationRules::default()
+        };
+        assert!(matches!(
+            code.validate(&rules),
+            Err(IrisError::InsufficientMask {
+                usable: 96,
+                required: 97
+            })
+        ));
+
+        let unpaired = IrisCode::new(code.code, code.mask.with_bit(95, false));
+        let rules = ValidationRules {
+            pairwise_mask: true,
+            ..ValidationRules::default()
+        };
+        assert!(matches!(
+            unpaired.validate(&rules),
+            Err(IrisError::UnpairedMask { bit: 94 })
+        ));
+
+        // a code of ones is as skewed as it gets
+        let skewed = IrisCode::new(IrisCodeArray::ONES, code.mask);
+        assert_eq!(skewed.quality().score(), 0.0);
+        let rules = ValidationRules {
+            min_quality: 0.1,
+            ..ValidationRules::default()
+        };
+        assert!(matches!(
+            skewed.validate(&rules),
+            Err(IrisError::LowQuality { .. })
+        ));
+    }
+}
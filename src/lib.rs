@@ -0,0 +1,59 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(all(feature = "toy-codes", feature = "full-codes"))]
+compile_error!("the features `toy-codes` and `full-codes` select different code widths");
+
+#[cfg(feature = "async")]
+pub mod asynchronous;
+#[cfg(feature = "std")]
+pub mod dataset;
+#[cfg(feature = "std")]
+pub mod distance;
+pub mod error;
+#[cfg(feature = "std")]
+pub mod index;
+pub mod iris;
+pub mod mask;
+#[cfg(feature = "rand")]
+pub mod noise;
+#[cfg(feature = "std")]
+pub mod observer;
+#[cfg(feature = "std")]
+pub mod pair;
+#[cfg(feature = "std")]
+pub mod payload;
+#[cfg(feature = "std")]
+pub mod sharded;
+mod simd;
+#[cfg(feature = "std")]
+pub mod snapshot;
+pub mod template;
+
+#[cfg(feature = "async")]
+pub use asynchronous::AsyncIrisHnsw;
+#[cfg(feature = "std")]
+pub use distance::{BitWeights, DistanceKind};
+pub use error::IrisError;
+#[cfg(feature = "std")]
+pub use index::{
+    DuplicateSet, Id, IdRemap, IndexStats, IrisHnsw, IrisHnswBuilder, Metadata, SearchHit,
+    UniquenessResult, RESERVED_METADATA_PREFIX,
+};
+pub use iris::{
+    IrisCode, IrisCodeArray, MaskedDistance, ValidationRules, DEFAULT_IRIS_CODE_WORDS,
+    MATCH_THRESHOLD_RATIO,
+};
+pub use mask::CompressedMask;
+#[cfg(feature = "std")]
+pub use observer::IndexObserver;
+#[cfg(feature = "std")]
+pub use pair::{IrisPair, MatchRule, PairIndex};
+#[cfg(feature = "std")]
+pub use payload::PayloadIndex;
+#[cfg(feature = "std")]
+pub use sharded::ShardedIrisIndex;
+#[cfg(feature = "std")]
+pub use snapshot::IndexSnapshot;
+pub use template::{IrisTemplate2D, TemplateLayout};
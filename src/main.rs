@@ -1,117 +1,162 @@
-mod iris;
-
-use std::{
-    collections::HashSet,
-    sync::{
-        atomic::{AtomicUsize, Ordering},
-        LazyLock, Mutex,
-    },
-};
-
-use anndists::dist::Distance;
-use hnsw_rs::hnsw::Hnsw;
-use indicatif::{ProgressBar, ProgressStyle};
-use iris::{IrisCode, IrisCodeArray};
-use rand::{seq::index::sample, thread_rng};
-use rayon::iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
-
-// Dataset parameters
-const N_POINTS: usize = 100_000;
-const RANDOM_QUERIES: usize = 10_000;
-
-// HNSW parameters
-const MAX_NB_CONNECTION: usize = 128;
-const EF_C: usize = 128;
-const KNBN: usize = 1;
-
-static EVAL_COUNTER: LazyLock<AtomicUsize> = LazyLock::new(|| AtomicUsize::new(0));
-
-fn to_array(code: &[u64]) -> [u64; IrisCodeArray::IRIS_CODE_SIZE_U64] {
-    bytemuck::try_cast_slice(code).unwrap().try_into().unwrap()
-}
-struct HD;
-impl Distance<u64> for HD {
-    fn eval(&self, va: &[u64], vb: &[u64]) -> f32 {
-        EVAL_COUNTER.fetch_add(1, Ordering::Relaxed);
-        let iris_code1 = IrisCodeArray(to_array(&va[0..IrisCodeArray::IRIS_CODE_SIZE_U64]));
-        let mask_code1 = IrisCodeArray(to_array(&va[IrisCodeArray::IRIS_CODE_SIZE_U64..]));
-        let iris_code2 = IrisCodeArray(to_array(&vb[0..IrisCodeArray::IRIS_CODE_SIZE_U64]));
-        let mask_code2 = IrisCodeArray(to_array(&vb[IrisCodeArray::IRIS_CODE_SIZE_U64..]));
-
-        let code1 = IrisCode {
-            code: iris_code1,
-            mask: mask_code1,
-        };
-        let code2 = IrisCode {
-            code: iris_code2,
-            mask: mask_code2,
-        };
-
-        code1.get_distance(&code2) as f32
-    }
-}
-
-fn main() {
-    let mut rng = thread_rng();
-    let nb_layer: usize = 16.min((N_POINTS as f32).ln().trunc() as usize);
-    let random_query_indices: HashSet<usize> = sample(&mut rng, N_POINTS, RANDOM_QUERIES)
-        .into_iter()
-        .collect();
-
-    let mut hnsw = Hnsw::<u64, HD>::new(MAX_NB_CONNECTION, N_POINTS, nb_layer, EF_C, HD {});
-
-    // Fill the DB
-    let bar = ProgressBar::new(N_POINTS as u64).with_style(
-        ProgressStyle::with_template(
-            "Insert: {elapsed_precise} {wide_bar} {pos}/{len} {percent_precise}%",
-        )
-        .unwrap(),
-    );
-    let random_queries = Mutex::new(vec![]);
-    (0..N_POINTS).into_par_iter().for_each(|idx| {
-        let mut rng = thread_rng();
-        let code = IrisCode::random_rng(&mut rng);
-        if random_query_indices.contains(&idx) {
-            random_queries.lock().unwrap().push((code.clone(), idx));
+#[cfg(feature = "hdf5")]
+mod annbench;
+mod checkpointer;
+mod cli;
+#[cfg(feature = "parquet")]
+mod columnar;
+mod commands;
+mod config;
+mod numpy;
+mod pipeline;
+mod progress;
+mod remote;
+mod service;
+mod store;
+mod wal;
+
+use clap::Parser;
+use cli::{Cli, Command, ExportFormat, ImportFormat};
+use tracing_subscriber::fmt::format::FmtSpan;
+
+fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    // span close events carry the busy/idle time of each phase
+    tracing_subscriber::fmt()
+        .with_max_level(cli.log_level())
+        .with_span_events(FmtSpan::CLOSE)
+        .with_writer(std::io::stderr)
+        .init();
+
+    match cli.command {
+        Command::Build {
+            experiment,
+            index_dir,
+            checkpoint_every,
+            checkpoint_minutes,
+            resume,
+            dataset,
+            queries,
+        } => commands::build::run(
+            &experiment,
+            &index_dir,
+            dataset.as_deref().zip(queries.as_deref()),
+            checkpoint_every,
+            checkpoint_minutes,
+            resume,
+        ),
+        Command::Generate {
+            experiment,
+            output,
+            queries,
+        } => commands::generate::run(&experiment, &output, queries.as_deref()),
+        Command::Import { format } => match format {
+            ImportFormat::Ndjson(args) => commands::import::ndjson(&args),
+            ImportFormat::Csv(args) => commands::import::csv(&args),
+            #[cfg(feature = "parquet")]
+            ImportFormat::Parquet(args) => commands::import::parquet(&args),
+            #[cfg(feature = "postgres")]
+            ImportFormat::Postgres(args) => commands::import::postgres(&args),
+        },
+        Command::Export { format } => match format {
+            ExportFormat::Ndjson(args) => commands::export::ndjson(&args),
+            #[cfg(feature = "parquet")]
+            ExportFormat::Parquet(args) => commands::export::parquet(&args),
+            ExportFormat::Npz(args) => commands::export::npz(&args),
+            ExportFormat::Npy(args) => commands::export::npy(&args),
+        },
+        Command::Ingest {
+            experiment,
+            format,
+            index_dir,
+        } => commands::ingest::run(&experiment, format, index_dir.as_deref()),
+        #[cfg(feature = "kafka")]
+        Command::Kafka(args) => commands::kafka::run(&args),
+        Command::Migrate { index_dir, dataset } => {
+            commands::migrate::run(index_dir.as_deref(), &dataset)
         }
-        hnsw.insert_slice((&code.as_merged_array(), idx));
-        bar.inc(1);
-    });
-
-    bar.finish();
-
-    hnsw.set_searching_mode(true);
-    EVAL_COUNTER.store(0, Ordering::Relaxed);
-
-    // Search the DB
-    let random_queries_vec = random_queries.lock().unwrap().clone();
-    let bar = ProgressBar::new(random_queries_vec.len() as u64).with_style(
-        ProgressStyle::with_template(
-            "Search: {elapsed_precise} {wide_bar} {pos}/{len} {percent_precise}%",
-        )
-        .unwrap(),
-    );
-    let correct = AtomicUsize::new(0);
-    random_queries_vec.par_iter().for_each(|(code, idx)| {
-        let mut rng = thread_rng();
-        let query = code.get_similar_iris(&mut rng);
-        let knn_neighbours = hnsw.search(&query.as_merged_array(), KNBN, EF_C);
-
-        if *idx == knn_neighbours[0].d_id {
-            correct.fetch_add(1, Ordering::Relaxed);
-        }
-        bar.inc(1);
-    });
-
-    bar.finish();
-
-    println!(
-        "ØEvals: {}",
-        EVAL_COUNTER.load(Ordering::Relaxed) / random_queries_vec.len()
-    );
-
-    println!(
-        "Recall: {:.4}%",
-        (correct.load(Ordering::Relaxed) as f32) / (random_queries_vec.len() as f32) * 100.0
-    );
+        Command::Search {
+            experiment,
+            index_dir,
+            groundtruth,
+        } => commands::search::run(&experiment, &index_dir, groundtruth.as_deref()),
+        Command::Groundtruth {
+            experiment,
+            index_dir,
+            k,
+            threshold,
+            output,
+        } => commands::groundtruth::run(&experiment, &index_dir, k, threshold, &output),
+        Command::Serve {
+            experiment,
+            index_dir,
+            wal,
+            listen,
+        } => commands::serve::run(&experiment, index_dir.as_deref(), wal.as_deref(), listen),
+        #[cfg(feature = "grpc")]
+        Command::ServeGrpc {
+            experiment,
+            index_dir,
+            wal,
+            listen,
+        } => commands::grpc::run(&experiment, index_dir.as_deref(), wal.as_deref(), listen),
+        #[cfg(feature = "hdf5")]
+        Command::AnnBenchmark {
+            experiment,
+            dataset,
+            results,
+            count,
+        } => commands::ann::run(&experiment, &dataset, &results, count),
+        Command::Dedup {
+            experiment,
+            input,
+            candidates,
+            pairs_csv,
+        } => commands::dedup::run(&experiment, &input, candidates, pairs_csv.as_deref()),
+        Command::Eval {
+            experiment,
+            input,
+            labels,
+            identities,
+            samples_per_identity,
+            threshold,
+            report,
+            aging,
+        } => commands::eval::run(
+            &experiment,
+            input.as_deref().zip(labels.as_deref()),
+            identities,
+            samples_per_identity,
+            threshold,
+            report.as_deref(),
+            &aging,
+        ),
+        Command::Bench {
+            experiment,
+            groundtruth,
+        } => commands::bench::run(&experiment, groundtruth.as_deref()),
+        Command::Autotune {
+            experiment,
+            target_recall,
+            tune_max_nb_connection,
+            max_ef_search,
+        } => commands::autotune::run(
+            &experiment,
+            target_recall,
+            &tune_max_nb_connection,
+            max_ef_search,
+        ),
+        Command::Sweep {
+            experiment,
+            sweep_max_nb_connection,
+            sweep_ef_construction,
+            sweep_ef_search,
+            sweep_csv,
+        } => commands::sweep::run(
+            &experiment,
+            &sweep_max_nb_connection,
+            &sweep_ef_construction,
+            &sweep_ef_search,
+            &sweep_csv,
+        ),
+    }
 }
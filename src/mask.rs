@@ -0,0 +1,182 @@
+use alloc::{format, vec, vec::Vec};
+use core::marker::PhantomData;
+
+use crate::{
+    error::IrisError,
+    iris::{IrisCodeArray, DEFAULT_IRIS_CODE_WORDS},
+};
+
+// only the even bits are encoded, each odd bit duplicates the one before it
+const FLAG_PAIRED: u8 = 1;
+
+/// Run-length encoded mask. Masks are mostly ones in long runs and usually pairwise-duplicated,
+/// in which case only every other bit is encoded, so a typical one shrinks to a few dozen bytes.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CompressedMask<const WORDS: usize = DEFAULT_IRIS_CODE_WORDS> {
+    // flags, then the lengths of alternating runs of ones and zeros as LEB128, ones first
+    bytes: Vec<u8>,
+    words: PhantomData<[u64; WORDS]>,
+}
+
+impl<const WORDS: usize> CompressedMask<WORDS> {
+    /// Longest possible encoding: the flags, a leading empty run of ones and a byte for every
+    /// other run, none of which can be shorter than its LEB128 length.
+    pub const MAX_LEN: usize = IrisCodeArray::<WORDS>::IRIS_CODE_SIZE + 2;
+
+    pub fn compress(mask: &IrisCodeArray<WORDS>) -> Self {
+        let paired = mask
+            .0
+            .iter()
+            .all(|word| (word ^ (word >> 1)) & 0x5555_5555_5555_5555 == 0);
+        let step = if paired { 2 } else { 1 };
+        let mut bytes = vec![if paired { FLAG_PAIRED } else { 0 }];
+        let mut value = true;
+        let mut run = 0;
+        for i in (0..IrisCodeArray::<WORDS>::IRIS_CODE_SIZE).step_by(step) {
+            let bit = mask.get_bit(i);
+            if bit != value {
+                push_varint(&mut bytes, run);
+                value = bit;
+                run = 0;
+            }
+            run += 1;
+        }
+        push_varint(&mut bytes, run);
+        Self {
+            bytes,
+            words: PhantomData,
+        }
+    }
+
+    pub fn decompress(&self) -> IrisCodeArray<WORDS> {
+        decode(&self.bytes).expect("compressed masks are checked when they are made")
+    }
+
+    /// Parses the encoding returned by [`CompressedMask::as_bytes`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, IrisError> {
+        decode::<WORDS>(bytes)?;
+        Ok(Self {
+            bytes: bytes.to_vec(),
+            words: PhantomData,
+        })
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+fn decode<const WORDS: usize>(bytes: &[u8]) -> Result<IrisCodeArray<WORDS>, IrisError> {
+    let invalid = |reason: &str| IrisError::InvalidEncoding(format!("compressed mask {reason}"));
+    let (&flags, mut runs) = bytes.split_first().ok_or_else(|| invalid("is empty"))?;
+    if flags & !FLAG_PAIRED != 0 {
+        return Err(invalid("has unknown flags"));
+    }
+    let step = if flags & FLAG_PAIRED != 0 { 2 } else { 1 };
+    let len = IrisCodeArray::<WORDS>::IRIS_CODE_SIZE / step;
+    let mut mask = IrisCodeArray::ZERO;
+    let mut pos = 0;
+    let mut value = true;
+    while !runs.is_empty() {
+        let run = read_varint(&mut runs).ok_or_else(|| invalid("ends within a run"))?;
+        if run > len - pos {
+            return Err(invalid("runs past the end"));
+        }
+        if value {
+            mask.set_range(pos * step, (pos + run) * step, true);
+        }
+        pos += run;
+        value = !value;
+    }
+    if pos != len {
+        return Err(invalid("ends early"));
+    }
+    Ok(mask)
+}
+
+fn push_varint(bytes: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn read_varint(bytes: &mut &[u8]) -> Option<usize> {
+    let mut value = 0;
+    for shift in (0..usize::BITS).step_by(7) {
+        let (&byte, rest) = bytes.split_first()?;
+        *bytes = rest;
+        value |= usize::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Mask = IrisCodeArray<2>;
+
+    fn round_trip(mask: Mask) -> CompressedMask<2> {
+        let compressed = CompressedMask::compress(&mask);
+        assert_eq!(compressed.decompress(), mask);
+        let parsed = CompressedMask::<2>::from_bytes(compressed.as_bytes()).unwrap();
+        assert_eq!(parsed, compressed);
+        compressed
+    }
+
+    #[test]
+    fn compress_round_trips() {
+        assert_eq!(round_trip(Mask::ONES).as_bytes(), [FLAG_PAIRED, 64]);
+        assert_eq!(round_trip(Mask::ZERO).as_bytes(), [FLAG_PAIRED, 0, 64]);
+        let mut occluded = Mask::ONES;
+        occluded.set_range(20, 50, false);
+        assert_eq!(round_trip(occluded).as_bytes(), [FLAG_PAIRED, 10, 15, 39]);
+        // a single odd bit breaks the pairing
+        let unpaired = Mask::ONES.with_bit(77, false);
+        assert_eq!(round_trip(unpaired).as_bytes(), [0, 77, 1, 50]);
+        // runs of 128 and more take two varint bytes
+        let mut wide = IrisCodeArray::<4>::ZERO;
+        wide.set_range(0, 255, true);
+        let compressed = CompressedMask::compress(&wide);
+        assert_eq!(compressed.as_bytes(), [0, 0xff, 0x01, 1]);
+        assert_eq!(compressed.decompress(), wide);
+    }
+
+    #[test]
+    fn from_bytes_rejects_malformed_input() {
+        for bytes in [
+            &[][..],
+            // unknown flag
+            &[4, 128, 1],
+            // runs past the end
+            &[0, 0x81, 0x01],
+            &[0, 100, 29],
+            // ends early
+            &[0, 100],
+            &[FLAG_PAIRED, 63],
+            // ends within a run
+            &[0, 0x80],
+        ] {
+            assert!(
+                matches!(
+                    CompressedMask::<2>::from_bytes(bytes),
+                    Err(IrisError::InvalidEncoding(_))
+                ),
+                "{bytes:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn alternating_bits_take_the_longest_encoding() {
+        let alternating = Mask::new([0xaaaa_aaaa_aaaa_aaaa; 2]);
+        let compressed = round_trip(alternating);
+        assert_eq!(compressed.as_bytes().len(), CompressedMask::<2>::MAX_LEN);
+        assert_eq!(compressed.as_bytes()[..4], [0, 0, 1, 1]);
+    }
+}
@@ -0,0 +1,262 @@
+use rand::{
+    distributions::{Bernoulli, Distribution},
+    Rng,
+};
+
+use crate::{
+    error::IrisError,
+    iris::{IrisCode, NoiseParams, DEFAULT_IRIS_CODE_WORDS},
+    template::{IrisTemplate2D, TemplateLayout, DEFAULT_LAYOUT},
+};
+
+/// Intra-class variation between two captures of the same eye. On top of the independent bit
+/// flips of [`NoiseParams`], which underestimate real-world difficulty, it models the structured
+/// differences of real captures: the eye rotated by a few columns, the iris pattern shifted by a
+/// radial band as the pupil dilates, and an eyelid or eyelashes masking a contiguous region.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NoiseModel {
+    pub layout: TemplateLayout,
+    pub flips: NoiseParams,
+    /// Most columns a capture is rotated by, either way.
+    pub max_rotation: usize,
+    /// Probability of the rows moving by one band, inwards or outwards.
+    pub radial_shift_probability: f64,
+    /// Probability of an occlusion band, masking a run of columns from the outermost row inwards.
+    pub occlusion_probability: f64,
+    /// Widest occlusion band in columns.
+    pub max_occlusion_columns: usize,
+}
+
+impl Default for NoiseModel {
+    /// Only the default bit flips, under [`DEFAULT_LAYOUT`].
+    fn default() -> Self {
+        Self::from_flips(NoiseParams::default())
+    }
+}
+
+impl NoiseModel {
+    /// Independent bit flips without structured noise, under [`DEFAULT_LAYOUT`].
+    pub fn from_flips(flips: NoiseParams) -> Self {
+        Self {
+            layout: DEFAULT_LAYOUT,
+            flips,
+            max_rotation: 0,
+            radial_shift_probability: 0.0,
+            occlusion_probability: 0.0,
+            max_occlusion_columns: 0,
+        }
+    }
+
+    /// Fails on probabilities outside [0, 1] or a layout that doesn't fit codes of `WORDS`.
+    pub fn validate<const WORDS: usize>(&self) -> Result<(), IrisError> {
+        for p in [
+            self.flips.code_flip_probability,
+            self.flips.mask_flip_probability,
+            self.radial_shift_probability,
+            self.occlusion_probability,
+        ] {
+            if !(0.0..=1.0).contains(&p) {
+                return Err(IrisError::InvalidProbability(p));
+            }
+        }
+        IrisTemplate2D::<WORDS>::new(Default::default(), self.layout)?;
+        Ok(())
+    }
+
+    /// Another capture of the eye of `code`. Disabled kinds of noise don't draw from `rng`, so a
+    /// model with only bit flips reproduces [`IrisCode::get_similar_iris_with`].
+    pub fn apply<const WORDS: usize, R: Rng>(
+        &self,
+        code: &IrisCode<WORDS>,
+        rng: &mut R,
+    ) -> Result<IrisCode<WORDS>, IrisError> {
+        self.validate::<WORDS>()?;
+        let mut bits = IrisTemplate2D::new(code.code, self.layout)?;
+        let mut mask = IrisTemplate2D::new(code.mask, self.layout)?;
+
+        if self.max_rotation > 0 {
+            let max = self.max_rotation as isize;
+            let shift = rng.gen_range(-max..=max);
+            bits.rotate_columns(shift);
+            mask.rotate_columns(shift);
+        }
+        if self.radial_shift_probability > 0.0 && rng.gen_bool(self.radial_shift_probability) {
+            let outwards = rng.gen_bool(0.5);
+            shift_rows(&mut bits, outwards);
+            shift_rows(&mut mask, outwards);
+        }
+        if self.occlusion_probability > 0.0
+            && self.max_occlusion_columns > 0
+            && rng.gen_bool(self.occlusion_probability)
+        {
+            self.occlude(&mut mask, rng);
+        }
+
+        let res = IrisCode {
+            code: bits.into_flat(),
+            mask: mask.into_flat(),
+        };
+        res.get_similar_iris_with(rng, &self.flips)
+    }
+
+    // eyelids cover the outer rows of an angular sector, reaching further in at its centre, which
+    // is approximated by a rectangle of random width and depth
+    fn occlude<const WORDS: usize, R: Rng>(&self, mask: &mut IrisTemplate2D<WORDS>, rng: &mut R) {
+        let layout = self.layout;
+        let width = rng.gen_range(1..=self.max_occlusion_columns.min(layout.columns));
+        let depth = rng.gen_range(1..=layout.rows);
+        let start = rng.gen_range(0..layout.columns);
+        for row in layout.rows - depth..layout.rows {
+            for column in (start..start + width).map(|column| column % layout.columns) {
+                for filter in 0..layout.filters {
+                    mask.set(row, column, filter, false);
+                }
+            }
+        }
+    }
+}
+
+/// Two captures with whether they come from the same eye.
+#[derive(Clone, Debug)]
+pub struct LabeledPair<const WORDS: usize = DEFAULT_IRIS_CODE_WORDS> {
+    pub a: IrisCode<WORDS>,
+    pub b: IrisCode<WORDS>,
+    pub genuine: bool,
+}
+
+/// Endless source of labeled comparisons over a set of enrolled codes, each taken as a distinct
+/// eye. Genuine pairs are two captures of one code under the noise model, impostor pairs are
+/// captures of two different codes.
+pub struct PairGenerator<'a, R, const WORDS: usize = DEFAULT_IRIS_CODE_WORDS> {
+    codes: &'a [IrisCode<WORDS>],
+    model: NoiseModel,
+    rng: R,
+    genuine_probability: f64,
+}
+
+impl<'a, R: Rng, const WORDS: usize> PairGenerator<'a, R, WORDS> {
+    /// Yields genuine and impostor pairs equally often. Needs at least two codes.
+    pub fn new(codes: &'a [IrisCode<WORDS>], model: NoiseModel, rng: R) -> Result<Self, IrisError> {
+        if codes.len() < 2 {
+            return Err(IrisError::InvalidLength {
+                expected: 2,
+                actual: codes.len(),
+            });
+        }
+        model.validate::<WORDS>()?;
+        Ok(Self {
+            codes,
+            model,
+            rng,
+            genuine_probability: 0.5,
+        })
+    }
+
+    /// Share of genuine pairs, which are far rarer than impostor pairs in a real gallery.
+    pub fn genuine_probability(mut self, p: f64) -> Result<Self, IrisError> {
+        if !(0.0..=1.0).contains(&p) {
+            return Err(IrisError::InvalidProbability(p));
+        }
+        self.genuine_probability = p;
+        Ok(self)
+    }
+
+    fn capture(&mut self, i: usize) -> IrisCode<WORDS> {
+        self.model
+            .apply(&self.codes[i], &mut self.rng)
+            .expect("the noise model is validated on construction")
+    }
+}
+
+impl<R: Rng, const WORDS: usize> Iterator for PairGenerator<'_, R, WORDS> {
+    type Item = LabeledPair<WORDS>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let genuine = self.rng.gen_bool(self.genuine_probability);
+        let i = self.rng.gen_range(0..self.codes.len());
+        let j = if genuine {
+            i
+        } else {
+            // uniform over the other codes
+            let j = self.rng.gen_range(0..self.codes.len() - 1);
+            j + usize::from(j >= i)
+        };
+        Some(LabeledPair {
+            a: self.capture(i),
+            b: self.capture(j),
+            genuine,
+        })
+    }
+}
+
+/// Slow drift of an eye over the years: code bits that flip for good and mask bits that are lost,
+/// e.g. to a drooping eyelid, both accumulating from step to step.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AgingModel {
+    /// Probability of a code bit flipping per step.
+    pub flip_rate: f64,
+    /// Probability of a mask bit being lost per step.
+    pub erosion_rate: f64,
+}
+
+impl AgingModel {
+    /// Endless sequence of the template of `code` after one, two, ... steps.
+    pub fn trajectory<R: Rng, const WORDS: usize>(
+        &self,
+        code: &IrisCode<WORDS>,
+        rng: R,
+    ) -> Result<Trajectory<R, WORDS>, IrisError> {
+        Ok(Trajectory {
+            state: code.clone(),
+            flips: Bernoulli::new(self.flip_rate)
+                .map_err(|_| IrisError::InvalidProbability(self.flip_rate))?,
+            erosion: Bernoulli::new(self.erosion_rate)
+                .map_err(|_| IrisError::InvalidProbability(self.erosion_rate))?,
+            rng,
+        })
+    }
+}
+
+/// Iterator returned by [`AgingModel::trajectory`].
+pub struct Trajectory<R, const WORDS: usize = DEFAULT_IRIS_CODE_WORDS> {
+    state: IrisCode<WORDS>,
+    flips: Bernoulli,
+    erosion: Bernoulli,
+    rng: R,
+}
+
+impl<R: Rng, const WORDS: usize> Iterator for Trajectory<R, WORDS> {
+    type Item = IrisCode<WORDS>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for i in 0..IrisCode::<WORDS>::IRIS_CODE_SIZE {
+            if self.flips.sample(&mut self.rng) {
+                self.state.code.flip_bit(i);
+            }
+            if self.erosion.sample(&mut self.rng) {
+                self.state.mask.set_bit(i, false);
+            }
+        }
+        Some(self.state.clone())
+    }
+}
+
+/// Moves every row one band outwards or inwards. The row that is vacated has no counterpart in
+/// the original capture, so it ends up zeroed, which masks it out when applied to the mask.
+fn shift_rows<const WORDS: usize>(template: &mut IrisTemplate2D<WORDS>, outwards: bool) {
+    let layout = template.layout();
+    let source = *template;
+    for row in 0..layout.rows {
+        let from = if outwards {
+            row.checked_sub(1)
+        } else {
+            Some(row + 1).filter(|&from| from < layout.rows)
+        };
+        for column in 0..layout.columns {
+            for filter in 0..layout.filters {
+                let val = from.is_some_and(|from| source.get(from, column, filter));
+                template.set(row, column, filter, val);
+            }
+        }
+    }
+}
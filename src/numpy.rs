@@ -0,0 +1,152 @@
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::Context;
+use hnsw_hamming::IrisCodeArray;
+use rayon::{
+    iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator},
+    slice::ParallelSliceMut,
+};
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+use crate::store::TemplateRecord;
+
+const NPY_MAGIC: &[u8] = b"\x93NUMPY\x01\x00";
+// the header including magic and length is padded to a multiple of this, see the npy format spec
+const NPY_ALIGNMENT: usize = 64;
+
+/// A C-ordered array in the `.npy` format.
+pub struct NpyArray {
+    pub name: &'static str,
+    /// numpy type string, e.g. `|u1` or `<f4`.
+    pub descr: &'static str,
+    pub shape: Vec<usize>,
+    pub data: Vec<u8>,
+}
+
+impl NpyArray {
+    fn write_to(&self, mut writer: impl Write) -> std::io::Result<()> {
+        let shape = match self.shape.as_slice() {
+            [len] => format!("({len},)"),
+            dims => format!(
+                "({})",
+                dims.iter()
+                    .map(usize::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        };
+        let mut header = format!(
+            "{{'descr': '{}', 'fortran_order': False, 'shape': {shape}, }}",
+            self.descr
+        );
+        // 2 bytes of header length and the closing newline
+        let unpadded = NPY_MAGIC.len() + 2 + header.len() + 1;
+        header.push_str(&" ".repeat(unpadded.next_multiple_of(NPY_ALIGNMENT) - unpadded));
+        header.push('\n');
+        writer.write_all(NPY_MAGIC)?;
+        writer.write_all(&(header.len() as u16).to_le_bytes())?;
+        writer.write_all(header.as_bytes())?;
+        writer.write_all(&self.data)
+    }
+
+    // bytes written by write_to, at most
+    fn size_bound(&self) -> usize {
+        self.data.len() + 2 * NPY_ALIGNMENT
+    }
+}
+
+// bits most significant first, so that `np.unpackbits(a, axis=1)[:, i]` is bit i
+fn packed_bits(arrays: impl Iterator<Item = IrisCodeArray>) -> Vec<u8> {
+    arrays
+        .flat_map(|array| {
+            array
+                .as_raw_slice()
+                .iter()
+                .map(|byte| byte.reverse_bits())
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// `ids` (u64), `codes` and `masks` (packed bits, one row per template) of `records`, and with
+/// `distances` their matrix of fractional distances (f32, NaN without common bits).
+pub fn template_arrays(records: &[TemplateRecord], distances: bool) -> Vec<NpyArray> {
+    let n = records.len();
+    let bytes = <IrisCodeArray>::IRIS_CODE_SIZE_BYTES;
+    let mut arrays = vec![
+        NpyArray {
+            name: "ids",
+            descr: "<u8",
+            shape: vec![n],
+            data: records
+                .iter()
+                .flat_map(|record| (record.id as u64).to_le_bytes())
+                .collect(),
+        },
+        NpyArray {
+            name: "codes",
+            descr: "|u1",
+            shape: vec![n, bytes],
+            data: packed_bits(records.iter().map(|record| record.code.code)),
+        },
+        NpyArray {
+            name: "masks",
+            descr: "|u1",
+            shape: vec![n, bytes],
+            data: packed_bits(records.iter().map(|record| record.code.mask)),
+        },
+    ];
+    if distances {
+        let mut data = vec![0; n * n * 4];
+        if n > 0 {
+            data.par_chunks_mut(n * 4)
+                .zip(records.par_iter())
+                .for_each(|(row, a)| {
+                    for (cell, b) in row.chunks_exact_mut(4).zip(records) {
+                        let distance = a.code.get_distance(&b.code) as f32;
+                        cell.copy_from_slice(&distance.to_le_bytes());
+                    }
+                });
+        }
+        arrays.push(NpyArray {
+            name: "distances",
+            descr: "<f4",
+            shape: vec![n, n],
+            data,
+        });
+    }
+    arrays
+}
+
+/// One `<name>.npy` per array in `dir`.
+pub fn write_npy_dir(dir: &Path, arrays: &[NpyArray]) -> anyhow::Result<()> {
+    fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+    for array in arrays {
+        let path = dir.join(format!("{}.npy", array.name));
+        let mut writer = BufWriter::new(
+            File::create(&path).with_context(|| format!("creating {}", path.display()))?,
+        );
+        array.write_to(&mut writer)?;
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+/// The arrays as an uncompressed `.npz`, as `np.savez` writes it.
+pub fn write_npz(path: &Path, arrays: &[NpyArray]) -> anyhow::Result<()> {
+    let file = File::create(path).with_context(|| format!("creating {}", path.display()))?;
+    let mut zip = ZipWriter::new(BufWriter::new(file));
+    for array in arrays {
+        let options = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Stored)
+            .large_file(array.size_bound() >= u32::MAX as usize);
+        zip.start_file(format!("{}.npy", array.name), options)?;
+        array.write_to(&mut zip)?;
+    }
+    zip.finish()?.flush()?;
+    Ok(())
+}
@@ -0,0 +1,16 @@
+use crate::{
+    index::{Id, SearchHit},
+    iris::IrisCode,
+};
+
+/// Lifecycle hooks of an [`IrisHnsw`](crate::IrisHnsw), for metrics, progress or auditing.
+/// Hooks run synchronously on the thread doing the work, so they should be cheap.
+pub trait IndexObserver: Send + Sync {
+    /// `id` was published, after `evals` distance evaluations to link it into the graph.
+    fn on_insert(&self, _id: Id, _evals: usize) {}
+
+    fn on_search_start(&self, _query: &IrisCode) {}
+
+    /// A search returned `hits` after `evals` distance evaluations.
+    fn on_search_done(&self, _hits: &[SearchHit], _evals: usize) {}
+}
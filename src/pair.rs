@@ -0,0 +1,165 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::IrisError,
+    index::{Id, IrisHnsw, IrisHnswBuilder, SearchHit},
+    iris::{IrisCode, DEFAULT_IRIS_CODE_WORDS, MATCH_THRESHOLD_RATIO},
+};
+
+/// Both eyes of one person, the unit deployments decide uniqueness on.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IrisPair<const WORDS: usize = DEFAULT_IRIS_CODE_WORDS> {
+    pub left: IrisCode<WORDS>,
+    pub right: IrisCode<WORDS>,
+}
+
+/// How the decisions of the two eyes combine into one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MatchRule {
+    /// A match if either eye matches, the strict choice for uniqueness.
+    #[default]
+    Either,
+    /// A match only if both eyes match the same person.
+    Both,
+}
+
+impl MatchRule {
+    pub fn combine(self, left: bool, right: bool) -> bool {
+        match self {
+            MatchRule::Either => left || right,
+            MatchRule::Both => left && right,
+        }
+    }
+}
+
+impl<const WORDS: usize> IrisPair<WORDS> {
+    /// Distances of the left and right eyes, see [`IrisCode::get_distance`].
+    pub fn get_distances(&self, other: &Self) -> (f64, f64) {
+        (
+            self.left.get_distance(&other.left),
+            self.right.get_distance(&other.right),
+        )
+    }
+
+    /// Whether the pairs belong to the same person under `rule`, each eye being compared against
+    /// `threshold`.
+    pub fn is_match(&self, other: &Self, rule: MatchRule, threshold: f64) -> bool {
+        let (left, right) = self.get_distances(other);
+        rule.combine(left < threshold, right < threshold)
+    }
+
+    /// [`IrisPair::is_match`] at [`MATCH_THRESHOLD_RATIO`].
+    pub fn is_close(&self, other: &Self, rule: MatchRule) -> bool {
+        self.is_match(other, rule, MATCH_THRESHOLD_RATIO)
+    }
+}
+
+/// A match of a pair query, with the distance of each eye where that eye was found.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PairHit {
+    pub id: Id,
+    pub left: Option<f32>,
+    pub right: Option<f32>,
+}
+
+/// One graph per eye, both keyed by the id of the person.
+pub struct PairIndex {
+    left: IrisHnsw,
+    right: IrisHnsw,
+    rule: MatchRule,
+}
+
+impl PairIndex {
+    /// Two empty graphs, each built from `builder`.
+    pub fn new(builder: &IrisHnswBuilder, rule: MatchRule) -> Self {
+        Self::from_indexes(builder.clone().build(), builder.clone().build(), rule)
+    }
+
+    /// Wraps per-eye graphs built elsewhere, whose entries must share ids.
+    pub fn from_indexes(left: IrisHnsw, right: IrisHnsw, rule: MatchRule) -> Self {
+        Self { left, right, rule }
+    }
+
+    pub fn left(&self) -> &IrisHnsw {
+        &self.left
+    }
+
+    pub fn right(&self) -> &IrisHnsw {
+        &self.right
+    }
+
+    pub fn rule(&self) -> MatchRule {
+        self.rule
+    }
+
+    /// Fails without inserting either eye if one of them doesn't pass validation.
+    pub fn insert(&self, pair: &IrisPair, id: Id) -> Result<(), IrisError> {
+        self.left.validate(&pair.left)?;
+        self.right.validate(&pair.right)?;
+        self.left.insert(&pair.left, id)?;
+        self.right.insert(&pair.right, id)
+    }
+
+    pub fn remove(&self, id: Id) -> bool {
+        let left = self.left.remove(id);
+        let right = self.right.remove(id);
+        left || right
+    }
+
+    pub fn get(&self, id: Id) -> Option<IrisPair> {
+        Some(IrisPair {
+            left: self.left.get(id)?,
+            right: self.right.get(id)?,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.left.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.left.is_empty()
+    }
+
+    /// Everyone matching `pair` under the index's rule, by id. Each eye is searched with
+    /// [`IrisHnsw::search_within`] at the match threshold of its graph.
+    pub fn matches(&self, pair: &IrisPair) -> Vec<PairHit> {
+        let (left, right) = rayon::join(
+            || {
+                self.left
+                    .search_within(&pair.left, self.left.match_threshold())
+            },
+            || {
+                self.right
+                    .search_within(&pair.right, self.right.match_threshold())
+            },
+        );
+        let mut hits: BTreeMap<Id, PairHit> = BTreeMap::new();
+        let mut add = |found: Vec<SearchHit>, is_left: bool| {
+            for hit in found {
+                let entry = hits.entry(hit.id).or_insert(PairHit {
+                    id: hit.id,
+                    left: None,
+                    right: None,
+                });
+                if is_left {
+                    entry.left = Some(hit.distance);
+                } else {
+                    entry.right = Some(hit.distance);
+                }
+            }
+        };
+        add(left, true);
+        add(right, false);
+        hits.into_values()
+            .filter(|hit| self.rule.combine(hit.left.is_some(), hit.right.is_some()))
+            .collect()
+    }
+
+    /// Whether `pair` matches nobody in the index.
+    pub fn is_unique(&self, pair: &IrisPair) -> bool {
+        self.matches(pair).is_empty()
+    }
+}
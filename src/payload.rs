@@ -0,0 +1,206 @@
+use std::{marker::PhantomData, path::Path};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    error::IrisError,
+    index::{Id, IrisHnsw, Metadata, SearchHit},
+    iris::IrisCode,
+};
+
+// metadata key the payload of an entry is stored under, as JSON, under RESERVED_METADATA_PREFIX
+// so it can't collide with user metadata
+const PAYLOAD_KEY: &str = "hnsw_iris.payload";
+
+/// An [`IrisHnsw`] whose entries each carry a caller-defined payload. The payload is stored in
+/// the entry's [`Metadata`], so it is inserted, removed and dumped in one step with the entry,
+/// and the index is only exposed for reading, so payloads can't drift out of sync with it.
+pub struct PayloadIndex<T> {
+    index: IrisHnsw,
+    payloads: PhantomData<fn() -> T>,
+}
+
+impl<T: Serialize + DeserializeOwned> PayloadIndex<T> {
+    pub fn new(index: IrisHnsw) -> Self {
+        Self {
+            index,
+            payloads: PhantomData,
+        }
+    }
+
+    pub fn insert(&self, code: &IrisCode, id: Id, payload: T) -> Result<(), IrisError> {
+        self.index
+            .insert_with_reserved_metadata(code, id, payload_metadata(&payload)?)
+    }
+
+    /// Replaces the template of `id`, keeping its payload, see [`IrisHnsw::update`].
+    pub fn update(&self, id: Id, code: &IrisCode) -> Result<bool, IrisError> {
+        self.index.update(id, code)
+    }
+
+    /// Replaces the payload of `id`, returning false if it isn't in the index.
+    pub fn set_payload(&self, id: Id, payload: T) -> Result<bool, IrisError> {
+        Ok(self
+            .index
+            .set_reserved_metadata(id, payload_metadata(&payload)?))
+    }
+
+    /// Removes `id`, returning its payload, or `None` if it isn't in the index. The entry is
+    /// removed even if its payload doesn't decode.
+    pub fn remove(&self, id: Id) -> Result<Option<T>, IrisError> {
+        let Some(metadata) = self.index.metadata(id) else {
+            return Ok(None);
+        };
+        if !self.index.remove(id) {
+            return Ok(None);
+        }
+        decode_payload(&metadata).map(Some)
+    }
+
+    /// Payload of `id`, `None` if it isn't in the index. Fails if the stored payload doesn't
+    /// decode as `T`, e.g. after a change to `T` that isn't backward compatible.
+    pub fn payload(&self, id: Id) -> Result<Option<T>, IrisError> {
+        self.index
+            .metadata(id)
+            .map(|metadata| decode_payload(&metadata))
+            .transpose()
+    }
+
+    pub fn contains(&self, id: Id) -> bool {
+        self.index.contains(id)
+    }
+
+    /// Template stored for `id`, see [`IrisHnsw::get`].
+    pub fn get(&self, id: Id) -> Option<IrisCode> {
+        self.index.get(id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// Nearest `k` entries together with their payloads.
+    pub fn search(&self, query: &IrisCode, k: usize) -> Result<Vec<(SearchHit, T)>, IrisError> {
+        self.search_with_ef(query, k, self.index.ef_search())
+    }
+
+    /// Like [`PayloadIndex::search`], with a beam of `ef`. Entries removed while searching are
+    /// left out, a payload that doesn't decode fails the search.
+    pub fn search_with_ef(
+        &self,
+        query: &IrisCode,
+        k: usize,
+        ef: usize,
+    ) -> Result<Vec<(SearchHit, T)>, IrisError> {
+        self.index
+            .search_with_ef(query, k, ef)
+            .into_iter()
+            .filter_map(|hit| {
+                Some(
+                    self.payload(hit.id)
+                        .transpose()?
+                        .map(|payload| (hit, payload)),
+                )
+            })
+            .collect()
+    }
+
+    /// Reloads an index written by [`PayloadIndex::dump`].
+    pub fn load(dir: &Path, basename: &str) -> anyhow::Result<Self> {
+        Ok(Self::new(IrisHnsw::load(dir, basename)?))
+    }
+
+    /// Writes the index files, payloads included, into `dir`, returning the basename actually
+    /// used.
+    pub fn dump(&self, dir: &Path, basename: &str) -> anyhow::Result<String> {
+        self.index.dump(dir, basename)
+    }
+}
+
+fn payload_metadata<T: Serialize>(payload: &T) -> Result<Metadata, IrisError> {
+    let json = serde_json::to_string(payload)
+        .map_err(|err| IrisError::InvalidEncoding(format!("payload: {err}")))?;
+    Ok(Metadata::from([(PAYLOAD_KEY.to_string(), json)]))
+}
+
+fn decode_payload<T: DeserializeOwned>(metadata: &Metadata) -> Result<T, IrisError> {
+    let json = metadata
+        .get(PAYLOAD_KEY)
+        .ok_or_else(|| IrisError::InvalidEncoding("payload: missing".to_string()))?;
+    serde_json::from_str(json).map_err(|err| IrisError::InvalidEncoding(format!("payload: {err}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    fn index() -> IrisHnsw {
+        IrisHnsw::builder()
+            .max_nb_connection(16)
+            .ef_construction(64)
+            .capacity(100)
+            .build()
+    }
+
+    fn codes(len: usize) -> Vec<IrisCode> {
+        let mut rng = StdRng::seed_from_u64(7);
+        (0..len).map(|_| IrisCode::random_rng(&mut rng)).collect()
+    }
+
+    #[test]
+    fn payloads_follow_their_entries() {
+        let codes = codes(10);
+        let index = PayloadIndex::new(index());
+        for (id, code) in codes.iter().enumerate() {
+            index.insert(code, id, format!("subject {id}")).unwrap();
+        }
+        assert_eq!(index.payload(3).unwrap().as_deref(), Some("subject 3"));
+        assert!(index.set_payload(3, "renamed".to_string()).unwrap());
+        let hits = index.search(&codes[3], 1).unwrap();
+        assert_eq!(hits[0].0.id, 3);
+        assert_eq!(hits[0].1, "renamed");
+        assert_eq!(index.remove(3).unwrap().as_deref(), Some("renamed"));
+        assert_eq!(index.remove(3).unwrap(), None);
+        assert_eq!(index.payload(3).unwrap(), None);
+    }
+
+    #[test]
+    fn payload_key_is_reserved() {
+        let metadata = Metadata::from([(PAYLOAD_KEY.to_string(), "1".to_string())]);
+        let index = index();
+        assert_eq!(
+            index.insert_with_metadata(&codes(1)[0], 0, metadata.clone()),
+            Err(IrisError::ReservedMetadataKey(PAYLOAD_KEY.to_string()))
+        );
+        index.insert(&codes(1)[0], 0).unwrap();
+        assert_eq!(
+            index.set_metadata(0, metadata),
+            Err(IrisError::ReservedMetadataKey(PAYLOAD_KEY.to_string()))
+        );
+    }
+
+    #[test]
+    fn undecodable_payloads_are_errors() {
+        let codes = codes(2);
+        let index = index();
+        let payload = |json: &str| Metadata::from([(PAYLOAD_KEY.to_string(), json.to_string())]);
+        index
+            .insert_with_reserved_metadata(&codes[0], 0, payload("1"))
+            .unwrap();
+        index
+            .insert_with_reserved_metadata(&codes[1], 1, payload("\"one\""))
+            .unwrap();
+        let index = PayloadIndex::<u32>::new(index);
+        assert_eq!(index.payload(0).unwrap(), Some(1));
+        assert!(index.payload(1).is_err());
+        assert!(index.search(&codes[0], 2).is_err());
+        assert!(index.remove(1).is_err());
+        assert!(!index.contains(1));
+    }
+}
@@ -0,0 +1,464 @@
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use hnsw_hamming::{
+    dataset::MappedDataset, distance::thread_eval_count, noise::AgingModel, IrisCode, IrisError,
+    IrisHnsw,
+};
+use indicatif::{ProgressBar, ProgressStyle};
+use rand::{seq::index::sample, thread_rng, Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use rayon::{
+    iter::{
+        IndexedParallelIterator, IntoParallelIterator, IntoParallelRefIterator, ParallelIterator,
+    },
+    ThreadPool, ThreadPoolBuilder,
+};
+use serde::Serialize;
+use tracing::{debug, info, info_span, warn};
+
+use crate::{
+    config::{Config, DatasetConfig, HnswConfig, NoiseConfig},
+    progress::{NoProgress, Progress},
+    remote,
+    store::{self, GroundTruth},
+};
+
+// Distinct RNG domains, so the dataset and the query noise don't share random streams
+const SAMPLE_DOMAIN: u64 = 0;
+const DATASET_DOMAIN: u64 = 1;
+const NOISE_DOMAIN: u64 = 2;
+const AGING_DOMAIN: u64 = 3;
+const AGED_CAPTURE_DOMAIN: u64 = 4;
+
+#[derive(Serialize)]
+pub struct QueryResult {
+    pub query_idx: usize,
+    pub returned_id: Option<usize>,
+    pub distance: Option<f32>,
+    pub evals: usize,
+    pub latency_us: u64,
+    /// All returned ids, nearest first.
+    #[serde(skip)]
+    pub neighbours: Vec<usize>,
+}
+
+pub struct SearchStats {
+    pub avg_evals: usize,
+    pub recall: f32,
+    pub search_time: Duration,
+    pub queries: Vec<QueryResult>,
+    /// Set by [`score_against`].
+    pub truth: Option<TruthRecall>,
+}
+
+/// Accuracy of a run against the exact neighbours written by `groundtruth`.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct TruthRecall {
+    pub k: usize,
+    pub threshold: f64,
+    /// Share of the exact `k` nearest among the first `k` results, in percent.
+    pub recall_at_k: f32,
+    /// Share of the exact matches below `threshold` that were returned at all, in percent.
+    pub threshold_recall: f32,
+}
+
+#[derive(Serialize)]
+struct RunSummary<'a> {
+    dataset: &'a DatasetConfig,
+    noise: &'a NoiseConfig,
+    hnsw: HnswConfig,
+    recall: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    truth: Option<TruthRecall>,
+    avg_evals: usize,
+    build_time_secs: Option<f64>,
+    search_time_secs: f64,
+}
+
+pub fn progress(config: &Config, label: &str, len: usize) -> Box<dyn Progress> {
+    if !config.output.progress {
+        return Box::new(NoProgress);
+    }
+    Box::new(
+        ProgressBar::new(len as u64).with_style(
+            ProgressStyle::with_template(&format!(
+                "{label}: {{elapsed_precise}} {{wide_bar}} {{pos}}/{{len}} {{percent_precise}}%"
+            ))
+            .unwrap(),
+        ),
+    )
+}
+
+/// Per-item RNG, so generated data doesn't depend on how rayon schedules the work.
+fn item_rng(seed: u64, domain: u64, idx: usize) -> ChaCha8Rng {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed ^ domain.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+    rng.set_stream(idx as u64);
+    rng
+}
+
+pub fn resolve_seed(config: &Config) -> u64 {
+    config.dataset.seed.unwrap_or_else(|| thread_rng().gen())
+}
+
+/// Random codes plus the subset of them that is perturbed into queries.
+pub struct Dataset {
+    pub seed: u64,
+    pub codes: Vec<IrisCode>,
+    pub queries: Vec<(IrisCode, usize)>,
+}
+
+pub fn generate_dataset(config: &Config) -> Dataset {
+    generate_dataset_with_seed(config, resolve_seed(config))
+}
+
+pub fn generate_dataset_with_seed(config: &Config, seed: u64) -> Dataset {
+    let _span = info_span!("generate", n_points = config.dataset.n_points, seed).entered();
+
+    let codes: Vec<IrisCode> = (0..config.dataset.n_points)
+        .into_par_iter()
+        .map(|idx| random_code(config, &mut item_rng(seed, DATASET_DOMAIN, idx)))
+        .collect();
+    let mut query_indices = sample(
+        &mut item_rng(seed, SAMPLE_DOMAIN, 0),
+        config.dataset.n_points,
+        config.dataset.random_queries,
+    )
+    .into_vec();
+    query_indices.sort_unstable();
+    let queries = query_indices
+        .into_iter()
+        .map(|idx| (codes[idx].clone(), idx))
+        .collect();
+
+    Dataset {
+        seed,
+        codes,
+        queries,
+    }
+}
+
+/// Synthetic labeled samples: `samples` noisy captures of each of `identities` random irises,
+/// returned as (code, identity) with ids given by position.
+pub fn generate_labeled_dataset(
+    config: &Config,
+    identities: usize,
+    samples: usize,
+) -> Vec<(IrisCode, usize)> {
+    let seed = resolve_seed(config);
+    let _span = info_span!("generate", identities, samples, seed).entered();
+
+    (0..identities * samples)
+        .into_par_iter()
+        .map(|idx| {
+            let identity = idx / samples;
+            let base = random_code(config, &mut item_rng(seed, DATASET_DOMAIN, identity));
+            let sample = config
+                .noise
+                .model()
+                .apply(&base, &mut item_rng(seed, NOISE_DOMAIN, idx))
+                .expect("noise probabilities are validated with the config");
+            (sample, identity)
+        })
+        .collect()
+}
+
+/// Distance of every sample to fresh captures of its eye after each of `steps` aging steps,
+/// indexed by step and then by sample.
+pub fn aging_distances(
+    config: &Config,
+    samples: &[(IrisCode, usize)],
+    model: &AgingModel,
+    steps: usize,
+) -> anyhow::Result<Vec<Vec<f64>>> {
+    let seed = resolve_seed(config);
+    let _span = info_span!("aging", samples = samples.len(), steps, seed).entered();
+    let noise = config.noise.model();
+
+    let per_sample: Vec<Vec<f64>> = samples
+        .par_iter()
+        .enumerate()
+        .map(|(idx, (code, _))| -> Result<Vec<f64>, IrisError> {
+            let mut capture_rng = item_rng(seed, AGED_CAPTURE_DOMAIN, idx);
+            model
+                .trajectory(code, item_rng(seed, AGING_DOMAIN, idx))?
+                .take(steps)
+                .map(|aged| {
+                    let capture = noise.apply(&aged, &mut capture_rng)?;
+                    Ok(code.get_distance(&capture))
+                })
+                .collect()
+        })
+        .collect::<Result<_, IrisError>>()
+        .context("invalid aging or noise parameters")?;
+    Ok((0..steps)
+        .map(|step| per_sample.iter().map(|distances| distances[step]).collect())
+        .collect())
+}
+
+fn random_code(config: &Config, rng: &mut impl Rng) -> IrisCode {
+    IrisCode::random_with_params(rng, config.dataset.mask_dropout, true)
+        .expect("mask_dropout is validated with the config")
+}
+
+fn thread_pool(threads: Option<usize>) -> Option<Arc<ThreadPool>> {
+    threads.map(|threads| {
+        Arc::new(
+            ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .expect("creating thread pool"),
+        )
+    })
+}
+
+/// Applies the search-time settings of `config`, which aren't part of a persisted index.
+pub fn configure_index(config: &Config, index: &mut IrisHnsw) {
+    index.set_ef_search(config.ef_search());
+    index.set_build_pool(thread_pool(config.threads.build));
+    index.set_search_pool(thread_pool(config.threads.search));
+}
+
+/// Reloads the index `basename` from `dir`, mapped if the config asks for it, and configures it.
+pub fn load_index(config: &Config, dir: &Path, basename: &str) -> anyhow::Result<IrisHnsw> {
+    let mut index = if config.hnsw.mmap {
+        IrisHnsw::load_mapped(dir, basename)?
+    } else {
+        IrisHnsw::load(dir, basename)?
+    };
+    configure_index(config, &mut index);
+    Ok(index)
+}
+
+/// Creates an empty index; without a `capacity` it is sized for open-ended growth.
+pub fn new_index(config: &Config, capacity: Option<usize>) -> IrisHnsw {
+    debug!(?capacity, hnsw = ?config.hnsw, "creating index");
+    let mut builder = IrisHnsw::builder()
+        .max_nb_connection(config.hnsw.max_nb_connection)
+        .ef_construction(config.hnsw.ef_construction)
+        .compress_masks(config.hnsw.compress_masks);
+    if let Some(capacity) = capacity {
+        builder = builder.capacity(capacity).nb_layer(config.nb_layer());
+    } else if let Some(nb_layer) = config.hnsw.nb_layer {
+        builder = builder.nb_layer(nb_layer);
+    }
+    let mut index = builder.build();
+    configure_index(config, &mut index);
+    index
+}
+
+/// Inserts `codes`, using `first_id` plus their position as id.
+pub fn insert_codes(index: &IrisHnsw, codes: &[IrisCode], first_id: usize, bar: &dyn Progress) {
+    let items: Vec<_> = codes.iter().cloned().zip(first_id..).collect();
+    index
+        .insert_batch(&items, |_| bar.inc(1))
+        .expect("pipeline indexes don't set validation rules");
+}
+
+/// Inserts the `records` of a mapped dataset under their ids, see [`IrisHnsw::insert_mapped`].
+pub fn insert_mapped(
+    index: &IrisHnsw,
+    dataset: &Arc<MappedDataset>,
+    records: Range<usize>,
+    bar: &dyn Progress,
+) {
+    index
+        .insert_mapped_batch(dataset, records, |_| bar.inc(1))
+        .expect("pipeline indexes don't set validation rules");
+}
+
+/// Fills a fresh index with the dataset codes, using their position as id.
+pub fn build_index(config: &Config, dataset: &Dataset) -> IrisHnsw {
+    let _span = info_span!("build", n_points = dataset.codes.len()).entered();
+
+    let mut index = new_index(config, Some(dataset.codes.len()));
+    let bar = progress(config, "Insert", dataset.codes.len());
+    insert_codes(&index, &dataset.codes, 0, bar.as_ref());
+    bar.finish();
+
+    index.set_searching_mode(true);
+    index
+}
+
+/// The query searched for the query set entry `idx` with template `code`, which is `code`
+/// perturbed by the configured noise.
+pub fn noisy_query(config: &Config, seed: u64, code: &IrisCode, idx: usize) -> IrisCode {
+    let mut rng = item_rng(seed, NOISE_DOMAIN, idx);
+    config
+        .noise
+        .model()
+        .apply(code, &mut rng)
+        .expect("noise probabilities are validated with the config")
+}
+
+/// Perturbs every query and checks whether its source code is the nearest neighbour.
+pub fn run_queries(
+    index: &IrisHnsw,
+    queries: &[(IrisCode, usize)],
+    seed: u64,
+    config: &Config,
+) -> SearchStats {
+    let _span = info_span!("search", queries = queries.len(), seed).entered();
+    let start = Instant::now();
+
+    let bar = progress(config, "Search", queries.len());
+    let results: Vec<QueryResult> = index.in_search_pool(|| {
+        queries
+            .par_iter()
+            .map(|(code, idx)| {
+                let query = noisy_query(config, seed, code, *idx);
+                let evals_before = thread_eval_count();
+                let query_start = Instant::now();
+                let knn_neighbours = match config.hnsw.rerank_oversample {
+                    Some(oversample) => index.search_reranked(&query, config.hnsw.knbn, oversample),
+                    None => index.search(&query, config.hnsw.knbn),
+                };
+                let latency = query_start.elapsed();
+                bar.inc(1);
+
+                let nearest = knn_neighbours.first();
+                QueryResult {
+                    query_idx: *idx,
+                    returned_id: nearest.map(|n| n.id),
+                    distance: nearest.map(|n| n.distance),
+                    evals: thread_eval_count() - evals_before,
+                    latency_us: latency.as_micros() as u64,
+                    neighbours: knn_neighbours.iter().map(|n| n.id).collect(),
+                }
+            })
+            .collect()
+    });
+
+    bar.finish();
+
+    let correct = results
+        .iter()
+        .filter(|r| r.returned_id == Some(r.query_idx))
+        .count();
+    let total_evals: usize = results.iter().map(|r| r.evals).sum();
+    SearchStats {
+        avg_evals: total_evals / queries.len(),
+        recall: (correct as f32) / (queries.len() as f32) * 100.0,
+        search_time: start.elapsed(),
+        queries: results,
+        truth: None,
+    }
+}
+
+/// Reads a file written by `groundtruth`, local or at a URL.
+pub fn load_groundtruth(config: &Config, path: &Path) -> anyhow::Result<GroundTruth> {
+    let truth = store::read_groundtruth(&remote::fetch_file(path)?)
+        .with_context(|| format!("reading ground truth from {}", path.display()))?;
+    if config.hnsw.knbn < truth.k {
+        warn!(
+            knbn = config.hnsw.knbn,
+            k = truth.k,
+            "fewer neighbours are retrieved than the ground truth holds, recall@k is capped"
+        );
+    }
+    Ok(truth)
+}
+
+/// Scores the results in `stats` against `truth`, which must cover every query. The queries have
+/// to be perturbed with the seed of `truth` for the exact neighbours to apply.
+pub fn score_against(stats: &mut SearchStats, truth: &GroundTruth) -> anyhow::Result<()> {
+    let exact: HashMap<_, _> = truth
+        .queries
+        .iter()
+        .map(|query| (query.query_idx, query))
+        .collect();
+    let (mut nearest_found, mut nearest_total) = (0, 0);
+    let (mut matches_found, mut matches_total) = (0, 0);
+    for result in &stats.queries {
+        let exact = exact
+            .get(&result.query_idx)
+            .with_context(|| format!("ground truth has no query {}", result.query_idx))?;
+        let returned: HashSet<_> = result.neighbours.iter().collect();
+        let first_k: HashSet<_> = result.neighbours.iter().take(truth.k).collect();
+        let nearest = &exact.neighbours[..truth.k.min(exact.neighbours.len())];
+        nearest_found += nearest.iter().filter(|id| first_k.contains(id)).count();
+        nearest_total += nearest.len();
+        // matches are the nearest neighbours, so they lead the list
+        let matches = &exact.neighbours[..exact.matches];
+        matches_found += matches.iter().filter(|id| returned.contains(id)).count();
+        matches_total += matches.len();
+    }
+    stats.truth = Some(TruthRecall {
+        k: truth.k,
+        threshold: truth.threshold,
+        recall_at_k: percent(nearest_found, nearest_total),
+        threshold_recall: percent(matches_found, matches_total),
+    });
+    Ok(())
+}
+
+fn percent(part: usize, whole: usize) -> f32 {
+    if whole == 0 {
+        return 100.0;
+    }
+    part as f32 / whole as f32 * 100.0
+}
+
+/// Prints the run summary and writes the configured JSON summary and per-query CSV.
+pub fn report(
+    config: &Config,
+    stats: &SearchStats,
+    build_time: Option<Duration>,
+) -> anyhow::Result<()> {
+    if let Some(build_time) = build_time {
+        info!("Build time: {:.2}s", build_time.as_secs_f64());
+    }
+    info!("Code size: {} bits", config.dataset.code_bits);
+    info!("Search time: {:.2}s", stats.search_time.as_secs_f64());
+    info!("ØEvals: {}", stats.avg_evals);
+    info!("Recall: {:.4}%", stats.recall);
+    if let Some(truth) = &stats.truth {
+        info!("Recall@{}: {:.4}%", truth.k, truth.recall_at_k);
+        info!(
+            "Threshold recall (< {}): {:.4}%",
+            truth.threshold, truth.threshold_recall
+        );
+    }
+
+    if let Some(path) = &config.output.results {
+        let summary = RunSummary {
+            dataset: &config.dataset,
+            noise: &config.noise,
+            hnsw: HnswConfig {
+                ef_search: Some(config.ef_search()),
+                nb_layer: Some(config.nb_layer()),
+                ..config.hnsw.clone()
+            },
+            recall: stats.recall,
+            truth: stats.truth,
+            avg_evals: stats.avg_evals,
+            build_time_secs: build_time.map(|t| t.as_secs_f64()),
+            search_time_secs: stats.search_time.as_secs_f64(),
+        };
+        std::fs::write(path, serde_json::to_string_pretty(&summary)?)
+            .with_context(|| format!("writing results to {}", path.display()))?;
+        debug!(path = %path.display(), "wrote run summary");
+    }
+
+    if let Some(path) = &config.output.queries_csv {
+        let mut writer =
+            csv::Writer::from_path(path).with_context(|| format!("creating {}", path.display()))?;
+        for result in &stats.queries {
+            writer.serialize(result)?;
+        }
+        writer.flush()?;
+        debug!(path = %path.display(), "wrote per-query results");
+    }
+    #[cfg(feature = "parquet")]
+    if let Some(path) = &config.output.queries_parquet {
+        crate::columnar::write_query_results(path, &stats.queries)?;
+        debug!(path = %path.display(), "wrote per-query results");
+    }
+    Ok(())
+}
@@ -0,0 +1,24 @@
+use indicatif::ProgressBar;
+
+/// Sink for progress updates of long-running phases, so callers decide whether anything is drawn.
+pub trait Progress: Sync {
+    fn inc(&self, delta: u64);
+    fn finish(&self) {}
+}
+
+/// Discards all updates, e.g. for headless runs under nohup or CI.
+pub struct NoProgress;
+
+impl Progress for NoProgress {
+    fn inc(&self, _delta: u64) {}
+}
+
+impl Progress for ProgressBar {
+    fn inc(&self, delta: u64) {
+        ProgressBar::inc(self, delta);
+    }
+
+    fn finish(&self) {
+        ProgressBar::finish(self);
+    }
+}
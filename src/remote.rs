@@ -0,0 +1,195 @@
+use std::path::{Path, PathBuf};
+
+#[cfg(not(feature = "object-store"))]
+use anyhow::bail;
+
+// Dataset files and index directories can be given as object store URLs, e.g.
+// `s3://bucket/prefix`, `gs://`, `az://` or `https://`. They are mirrored in a local cache
+// directory, which the rest of the tool works on as if it had been given that directory.
+
+/// Whether `path` is a URL rather than a local path.
+pub fn is_remote(path: &Path) -> bool {
+    path.to_str()
+        .and_then(|path| path.split_once("://"))
+        .is_some_and(|(scheme, _)| scheme != "file")
+}
+
+/// Local copy of the file at `path`, downloaded if it is remote.
+pub fn fetch_file(path: &Path) -> anyhow::Result<PathBuf> {
+    if !is_remote(path) {
+        return Ok(path.to_path_buf());
+    }
+    imp::fetch_file(path)
+}
+
+/// Local copy of the directory at `path`, with every object under the prefix downloaded if it is
+/// remote.
+pub fn fetch_dir(path: &Path) -> anyhow::Result<PathBuf> {
+    if !is_remote(path) {
+        return Ok(path.to_path_buf());
+    }
+    imp::fetch_dir(path)
+}
+
+/// Local directory to write the directory at `path` into before [`publish_dir`]. For a remote
+/// `path` this is its mirror in the cache, so files left by an interrupted run, such as build
+/// checkpoints, are found again by the next one.
+pub fn staging_dir(path: &Path) -> anyhow::Result<PathBuf> {
+    if !is_remote(path) {
+        return Ok(path.to_path_buf());
+    }
+    imp::cache_path(path)
+}
+
+/// Uploads every file in `local` under the prefix `path`, if it is remote.
+pub fn publish_dir(local: &Path, path: &Path) -> anyhow::Result<()> {
+    if !is_remote(path) {
+        return Ok(());
+    }
+    imp::publish_dir(local, path)
+}
+
+#[cfg(not(feature = "object-store"))]
+mod imp {
+    use super::*;
+
+    fn unsupported<T>(path: &Path) -> anyhow::Result<T> {
+        bail!(
+            "{} is a URL, which needs a build with the `object-store` feature",
+            path.display()
+        )
+    }
+
+    pub fn fetch_file(path: &Path) -> anyhow::Result<PathBuf> {
+        unsupported(path)
+    }
+
+    pub fn fetch_dir(path: &Path) -> anyhow::Result<PathBuf> {
+        unsupported(path)
+    }
+
+    pub fn cache_path(path: &Path) -> anyhow::Result<PathBuf> {
+        unsupported(path)
+    }
+
+    pub fn publish_dir(_local: &Path, path: &Path) -> anyhow::Result<()> {
+        unsupported(path)
+    }
+}
+
+#[cfg(feature = "object-store")]
+mod imp {
+    use std::{
+        fs::{self, File},
+        io::{Read, Write},
+    };
+
+    use anyhow::Context;
+    use futures::{StreamExt, TryStreamExt};
+    use object_store::{path::Path as ObjectPath, ObjectStore, WriteMultipart};
+    use tokio::runtime::Runtime;
+    use tracing::info;
+    use url::Url;
+
+    use super::*;
+
+    // size of the parts uploads are split into
+    const PART_BYTES: usize = 8 << 20;
+
+    // credentials and regions come from the usual variables, e.g. AWS_ACCESS_KEY_ID, which
+    // object_store knows by their lowercase names
+    fn open(path: &Path) -> anyhow::Result<(Box<dyn ObjectStore>, ObjectPath, Url)> {
+        let url = Url::parse(&path.to_string_lossy())
+            .with_context(|| format!("parsing {}", path.display()))?;
+        let options = std::env::vars().map(|(key, value)| (key.to_ascii_lowercase(), value));
+        let (store, prefix) = object_store::parse_url_opts(&url, options)
+            .with_context(|| format!("opening {url}"))?;
+        Ok((store, prefix, url))
+    }
+
+    pub fn cache_path(path: &Path) -> anyhow::Result<PathBuf> {
+        let (_, prefix, url) = open(path)?;
+        let mut local = std::env::temp_dir().join("hnsw-hamming").join(url.scheme());
+        local.push(url.host_str().unwrap_or_default());
+        local.extend(prefix.parts().map(|part| part.as_ref().to_string()));
+        Ok(local)
+    }
+
+    async fn download(
+        store: &dyn ObjectStore,
+        location: &ObjectPath,
+        local: &Path,
+    ) -> anyhow::Result<()> {
+        if let Some(parent) = local.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file =
+            File::create(local).with_context(|| format!("creating {}", local.display()))?;
+        let mut stream = store.get(location).await?.into_stream();
+        while let Some(chunk) = stream.next().await {
+            file.write_all(&chunk?)?;
+        }
+        Ok(())
+    }
+
+    pub fn fetch_file(path: &Path) -> anyhow::Result<PathBuf> {
+        let (store, location, _) = open(path)?;
+        let local = cache_path(path)?;
+        Runtime::new()?.block_on(download(store.as_ref(), &location, &local))?;
+        info!(remote = %path.display(), local = %local.display(), "downloaded file");
+        Ok(local)
+    }
+
+    pub fn fetch_dir(path: &Path) -> anyhow::Result<PathBuf> {
+        let (store, prefix, _) = open(path)?;
+        let local = cache_path(path)?;
+        Runtime::new()?.block_on(async {
+            let objects: Vec<_> = store.list(Some(&prefix)).try_collect().await?;
+            anyhow::ensure!(
+                !objects.is_empty(),
+                "nothing found under {}",
+                path.display()
+            );
+            for object in objects {
+                let relative: PathBuf = object
+                    .location
+                    .prefix_match(&prefix)
+                    .context("listed object outside the prefix")?
+                    .map(|part| part.as_ref().to_string())
+                    .collect();
+                download(store.as_ref(), &object.location, &local.join(relative)).await?;
+            }
+            Ok::<_, anyhow::Error>(())
+        })?;
+        info!(remote = %path.display(), local = %local.display(), "downloaded directory");
+        Ok(local)
+    }
+
+    pub fn publish_dir(local: &Path, path: &Path) -> anyhow::Result<()> {
+        let (store, prefix, _) = open(path)?;
+        Runtime::new()?.block_on(async {
+            for entry in fs::read_dir(local)? {
+                let entry = entry?;
+                if !entry.file_type()?.is_file() {
+                    continue;
+                }
+                let location = prefix.child(entry.file_name().to_string_lossy().as_ref());
+                let mut upload = WriteMultipart::new(store.put_multipart(&location).await?);
+                let mut file = File::open(entry.path())?;
+                let mut buf = vec![0; PART_BYTES];
+                loop {
+                    let read = file.read(&mut buf)?;
+                    if read == 0 {
+                        break;
+                    }
+                    upload.wait_for_capacity(4).await?;
+                    upload.write(&buf[..read]);
+                }
+                upload.finish().await?;
+            }
+            Ok::<_, anyhow::Error>(())
+        })?;
+        info!(local = %local.display(), remote = %path.display(), "uploaded directory");
+        Ok(())
+    }
+}
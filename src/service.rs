@@ -0,0 +1,121 @@
+use std::{
+    fmt, io,
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use hnsw_hamming::{IrisCode, IrisError, IrisHnsw, SearchHit, UniquenessResult};
+use tracing::info;
+
+use crate::{config::Config, pipeline, remote, store::INDEX_BASENAME, wal::Wal};
+
+/// In-memory index shared by the network frontends.
+pub struct IndexService {
+    index: IrisHnsw,
+    next_id: AtomicUsize,
+    config: Config,
+    wal: Option<Wal>,
+}
+
+/// Why an insert was refused.
+#[derive(Debug)]
+pub enum InsertError {
+    /// The template failed validation.
+    Invalid(IrisError),
+    /// The insert couldn't be logged, so it wasn't applied either.
+    Wal(io::Error),
+}
+
+impl fmt::Display for InsertError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Invalid(err) => err.fmt(f),
+            Self::Wal(err) => write!(f, "logging the insert failed: {err}"),
+        }
+    }
+}
+
+impl From<IrisError> for InsertError {
+    fn from(err: IrisError) -> Self {
+        Self::Invalid(err)
+    }
+}
+
+impl IndexService {
+    /// Starts from the index in `index_dir` if given, otherwise from an empty one. With `wal`,
+    /// inserts logged there that the index misses are replayed, and new ones are logged before
+    /// they are applied.
+    pub fn load(
+        config: Config,
+        index_dir: Option<&Path>,
+        wal: Option<&Path>,
+    ) -> anyhow::Result<Self> {
+        let index = match index_dir {
+            Some(dir) => pipeline::load_index(&config, &remote::fetch_dir(dir)?, INDEX_BASENAME)?,
+            None => pipeline::new_index(&config, None),
+        };
+        let mut next_id = index.nb_points();
+        let wal = match wal {
+            Some(path) => {
+                let (wal, entries) = Wal::open(path)?;
+                next_id = entries
+                    .iter()
+                    .map(|(id, _)| id + 1)
+                    .fold(next_id, usize::max);
+                let missing: Vec<_> = entries
+                    .into_iter()
+                    .filter(|(id, _)| !index.contains(*id))
+                    .map(|(id, code)| (code, id))
+                    .collect();
+                index.insert_batch(&missing, |_| {})?;
+                info!(path = %path.display(), replayed = missing.len(), "replayed write-ahead log");
+                Some(wal)
+            }
+            None => None,
+        };
+        Ok(Self {
+            next_id: AtomicUsize::new(next_id),
+            index,
+            config,
+            wal,
+        })
+    }
+
+    /// Rejected templates don't use up an id.
+    pub fn insert(&self, code: &IrisCode) -> Result<usize, InsertError> {
+        self.index.validate(code)?;
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        if let Some(wal) = &self.wal {
+            wal.append(&[(id, code)]).map_err(InsertError::Wal)?;
+        }
+        self.index.insert(code, id)?;
+        Ok(id)
+    }
+
+    pub fn insert_batch(&self, codes: &[IrisCode]) -> Result<Vec<usize>, InsertError> {
+        codes
+            .iter()
+            .try_for_each(|code| self.index.validate(code))?;
+        let first_id = self.next_id.fetch_add(codes.len(), Ordering::Relaxed);
+        if let Some(wal) = &self.wal {
+            let entries: Vec<_> = (first_id..).zip(codes).collect();
+            wal.append(&entries).map_err(InsertError::Wal)?;
+        }
+        let items: Vec<_> = codes.iter().cloned().zip(first_id..).collect();
+        self.index.insert_batch(&items, |_| {})?;
+        Ok((first_id..first_id + codes.len()).collect())
+    }
+
+    /// Searches with the configured k and ef where not overridden.
+    pub fn search(&self, code: &IrisCode, k: Option<usize>, ef: Option<usize>) -> Vec<SearchHit> {
+        let k = k.unwrap_or(self.config.hnsw.knbn);
+        match ef {
+            Some(ef) => self.index.search_with_ef(code, k, ef),
+            None => self.index.search(code, k),
+        }
+    }
+
+    pub fn is_unique(&self, code: &IrisCode) -> UniquenessResult {
+        self.index.is_unique(code)
+    }
+}
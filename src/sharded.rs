@@ -0,0 +1,149 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
+
+use crate::{
+    error::IrisError,
+    index::{Id, IrisHnsw, IrisHnswBuilder, SearchHit, UniquenessResult},
+    iris::IrisCode,
+};
+
+/// Independent graphs that together act as one index. Entries are placed by `id % shards`, so an
+/// id always lives in the same shard, and searches fan out to every shard in parallel.
+pub struct ShardedIrisIndex {
+    shards: Vec<IrisHnsw>,
+}
+
+impl ShardedIrisIndex {
+    /// `nb_shards` empty graphs, each built from `builder`.
+    pub fn new(nb_shards: usize, builder: &IrisHnswBuilder) -> Self {
+        assert!(nb_shards > 0, "a sharded index needs at least one shard");
+        Self::from_shards((0..nb_shards).map(|_| builder.clone().build()).collect())
+    }
+
+    /// Wraps shards built elsewhere, e.g. in parallel. Their entries must already be placed by
+    /// `id % shards.len()`.
+    pub fn from_shards(shards: Vec<IrisHnsw>) -> Self {
+        assert!(
+            !shards.is_empty(),
+            "a sharded index needs at least one shard"
+        );
+        Self { shards }
+    }
+
+    pub fn shards(&self) -> &[IrisHnsw] {
+        &self.shards
+    }
+
+    fn shard(&self, id: Id) -> &IrisHnsw {
+        &self.shards[id % self.shards.len()]
+    }
+
+    pub fn insert(&self, code: &IrisCode, id: Id) -> Result<(), IrisError> {
+        self.shard(id).insert(code, id)
+    }
+
+    /// Inserts all `items` in parallel, see [`IrisHnsw::insert_batch`].
+    pub fn insert_batch(
+        &self,
+        items: &[(IrisCode, Id)],
+        progress: impl Fn(usize) + Sync,
+    ) -> Result<(), IrisError> {
+        // validated before any shard starts, so a bad item can't leave a partial batch behind
+        items
+            .iter()
+            .try_for_each(|(code, id)| self.shard(*id).validate(code))?;
+        let mut per_shard = vec![vec![]; self.shards.len()];
+        for (code, id) in items {
+            per_shard[id % self.shards.len()].push((code.clone(), *id));
+        }
+        let done = AtomicUsize::new(0);
+        self.shards
+            .par_iter()
+            .zip(per_shard)
+            .try_for_each(|(shard, items)| {
+                shard.insert_batch(&items, |_| {
+                    progress(done.fetch_add(1, Ordering::Relaxed) + 1);
+                })
+            })
+    }
+
+    pub fn update(&self, id: Id, code: &IrisCode) -> Result<bool, IrisError> {
+        self.shard(id).update(id, code)
+    }
+
+    pub fn remove(&self, id: Id) -> bool {
+        self.shard(id).remove(id)
+    }
+
+    pub fn get(&self, id: Id) -> Option<IrisCode> {
+        self.shard(id).get(id)
+    }
+
+    /// Searches every shard with its default ef_search and keeps the `k` nearest overall.
+    pub fn search(&self, query: &IrisCode, k: usize) -> Vec<SearchHit> {
+        let per_shard: Vec<_> = self
+            .shards
+            .par_iter()
+            .map(|shard| shard.search(query, k))
+            .collect();
+        merge_nearest(per_shard, k)
+    }
+
+    pub fn search_with_ef(&self, query: &IrisCode, k: usize, ef: usize) -> Vec<SearchHit> {
+        let per_shard: Vec<_> = self
+            .shards
+            .par_iter()
+            .map(|shard| shard.search_with_ef(query, k, ef))
+            .collect();
+        merge_nearest(per_shard, k)
+    }
+
+    pub fn search_within(&self, query: &IrisCode, threshold: f64) -> Vec<SearchHit> {
+        let per_shard: Vec<_> = self
+            .shards
+            .par_iter()
+            .map(|shard| shard.search_within(query, threshold))
+            .collect();
+        merge_nearest(per_shard, usize::MAX)
+    }
+
+    pub fn is_unique(&self, code: &IrisCode) -> UniquenessResult {
+        let closest = self
+            .shards
+            .par_iter()
+            .filter_map(|shard| shard.is_unique(code).closest)
+            .min_by(|a, b| a.distance.total_cmp(&b.distance));
+        UniquenessResult {
+            unique: closest.as_ref().is_none_or(|hit| !hit.is_match),
+            closest,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(IrisHnsw::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(IrisHnsw::is_empty)
+    }
+
+    pub fn set_ef_search(&mut self, ef_search: usize) {
+        for shard in &mut self.shards {
+            shard.set_ef_search(ef_search);
+        }
+    }
+
+    pub fn set_searching_mode(&mut self, flag: bool) {
+        for shard in &mut self.shards {
+            shard.set_searching_mode(flag);
+        }
+    }
+}
+
+fn merge_nearest(per_shard: Vec<Vec<SearchHit>>, k: usize) -> Vec<SearchHit> {
+    let mut neighbours: Vec<_> = per_shard.into_iter().flatten().collect();
+    neighbours.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+    neighbours.truncate(k);
+    neighbours
+}
@@ -0,0 +1,394 @@
+// popcount kernels behind the distance functions, picked at runtime from the CPU features
+// feature detection needs std, so no_std builds always use the scalar loops
+
+/// Popcounts of `(code_a ^ code_b) & mask_a & mask_b` and `mask_a & mask_b`, i.e. the Hamming
+/// distance over the common mask and the size of that mask. Panics unless all slices have the
+/// same length.
+#[inline]
+pub fn masked_hamming(
+    code_a: &[u64],
+    mask_a: &[u64],
+    code_b: &[u64],
+    mask_b: &[u64],
+) -> (u32, u32) {
+    // the kernels load all four slices at the offsets of `code_a`
+    assert!(
+        code_a.len() == mask_a.len()
+            && code_a.len() == code_b.len()
+            && code_a.len() == mask_b.len(),
+        "masked_hamming on slices of different lengths"
+    );
+    #[cfg(feature = "std")]
+    {
+        // SAFETY: `detect` only picks kernels whose CPU features are present, and the lengths
+        // were checked
+        unsafe { (kernels().masked_hamming)(code_a, mask_a, code_b, mask_b) }
+    }
+    #[cfg(not(feature = "std"))]
+    {
+        masked_hamming_scalar(code_a, mask_a, code_b, mask_b)
+    }
+}
+
+/// Number of set bits in `words`.
+#[inline]
+pub fn count_ones(words: &[u64]) -> u32 {
+    #[cfg(feature = "std")]
+    {
+        // SAFETY: as above
+        unsafe { (kernels().count_ones)(words) }
+    }
+    #[cfg(not(feature = "std"))]
+    {
+        count_ones_scalar(words)
+    }
+}
+
+#[cfg(feature = "std")]
+struct Kernels {
+    masked_hamming: unsafe fn(&[u64], &[u64], &[u64], &[u64]) -> (u32, u32),
+    count_ones: unsafe fn(&[u64]) -> u32,
+}
+
+// detected once, the distance functions run far too often to check the CPU on every call
+#[cfg(feature = "std")]
+fn kernels() -> &'static Kernels {
+    static KERNELS: std::sync::OnceLock<Kernels> = std::sync::OnceLock::new();
+    KERNELS.get_or_init(detect)
+}
+
+#[cfg(feature = "std")]
+fn detect() -> Kernels {
+    #[cfg(target_arch = "x86_64")]
+    {
+        if std::is_x86_feature_detected!("avx512f")
+            && std::is_x86_feature_detected!("avx512vpopcntdq")
+        {
+            return Kernels {
+                masked_hamming: x86::masked_hamming_avx512,
+                count_ones: x86::count_ones_avx512,
+            };
+        }
+        if std::is_x86_feature_detected!("avx2") {
+            return Kernels {
+                masked_hamming: x86::masked_hamming_avx2,
+                count_ones: x86::count_ones_avx2,
+            };
+        }
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        if std::arch::is_aarch64_feature_detected!("neon") {
+            return Kernels {
+                masked_hamming: neon::masked_hamming_neon,
+                count_ones: neon::count_ones_neon,
+            };
+        }
+    }
+    Kernels {
+        masked_hamming: masked_hamming_scalar,
+        count_ones: count_ones_scalar,
+    }
+}
+
+#[inline(always)]
+fn masked_hamming_scalar(
+    code_a: &[u64],
+    mask_a: &[u64],
+    code_b: &[u64],
+    mask_b: &[u64],
+) -> (u32, u32) {
+    let mut distance = 0;
+    let mut overlap = 0;
+    for ((ca, ma), (cb, mb)) in code_a.iter().zip(mask_a).zip(code_b.iter().zip(mask_b)) {
+        let mask = ma & mb;
+        overlap += mask.count_ones();
+        distance += ((ca ^ cb) & mask).count_ones();
+    }
+    (distance, overlap)
+}
+
+#[inline(always)]
+fn count_ones_scalar(words: &[u64]) -> u32 {
+    words.iter().map(|word| word.count_ones()).sum()
+}
+
+#[cfg(all(target_arch = "x86_64", feature = "std"))]
+mod x86 {
+    use std::arch::x86_64::*;
+
+    use super::{count_ones_scalar, masked_hamming_scalar};
+
+    const LANES: usize = 8;
+
+    #[target_feature(enable = "avx512f,avx512vpopcntdq")]
+    pub(super) unsafe fn masked_hamming_avx512(
+        code_a: &[u64],
+        mask_a: &[u64],
+        code_b: &[u64],
+        mask_b: &[u64],
+    ) -> (u32, u32) {
+        let body = code_a.len() / LANES * LANES;
+        let mut distance = _mm512_setzero_si512();
+        let mut overlap = _mm512_setzero_si512();
+        for i in (0..body).step_by(LANES) {
+            let ca = _mm512_loadu_si512(code_a.as_ptr().add(i).cast());
+            let ma = _mm512_loadu_si512(mask_a.as_ptr().add(i).cast());
+            let cb = _mm512_loadu_si512(code_b.as_ptr().add(i).cast());
+            let mb = _mm512_loadu_si512(mask_b.as_ptr().add(i).cast());
+            let mask = _mm512_and_si512(ma, mb);
+            let diff = _mm512_and_si512(_mm512_xor_si512(ca, cb), mask);
+            overlap = _mm512_add_epi64(overlap, _mm512_popcnt_epi64(mask));
+            distance = _mm512_add_epi64(distance, _mm512_popcnt_epi64(diff));
+        }
+        let (tail_distance, tail_overlap) = masked_hamming_scalar(
+            &code_a[body..],
+            &mask_a[body..],
+            &code_b[body..],
+            &mask_b[body..],
+        );
+        (
+            _mm512_reduce_add_epi64(distance) as u32 + tail_distance,
+            _mm512_reduce_add_epi64(overlap) as u32 + tail_overlap,
+        )
+    }
+
+    #[target_feature(enable = "avx512f,avx512vpopcntdq")]
+    pub(super) unsafe fn count_ones_avx512(words: &[u64]) -> u32 {
+        let body = words.len() / LANES * LANES;
+        let mut sum = _mm512_setzero_si512();
+        for i in (0..body).step_by(LANES) {
+            let v = _mm512_loadu_si512(words.as_ptr().add(i).cast());
+            sum = _mm512_add_epi64(sum, _mm512_popcnt_epi64(v));
+        }
+        _mm512_reduce_add_epi64(sum) as u32 + count_ones_scalar(&words[body..])
+    }
+
+    const AVX2_LANES: usize = 4;
+
+    // per-nibble counts looked up with a byte shuffle, then summed into one count per u64 lane
+    #[target_feature(enable = "avx2")]
+    #[inline]
+    unsafe fn popcount_avx2(v: __m256i) -> __m256i {
+        let lookup = _mm256_setr_epi8(
+            0, 1, 1, 2, 1, 2, 2, 3, 1, 2, 2, 3, 2, 3, 3, 4, 0, 1, 1, 2, 1, 2, 2, 3, 1, 2, 2, 3, 2,
+            3, 3, 4,
+        );
+        let low_nibbles = _mm256_set1_epi8(0x0f);
+        let low = _mm256_and_si256(v, low_nibbles);
+        let high = _mm256_and_si256(_mm256_srli_epi16(v, 4), low_nibbles);
+        let counts = _mm256_add_epi8(
+            _mm256_shuffle_epi8(lookup, low),
+            _mm256_shuffle_epi8(lookup, high),
+        );
+        _mm256_sad_epu8(counts, _mm256_setzero_si256())
+    }
+
+    #[target_feature(enable = "avx2")]
+    #[inline]
+    unsafe fn sum_lanes_avx2(v: __m256i) -> u32 {
+        let mut lanes = [0u64; AVX2_LANES];
+        _mm256_storeu_si256(lanes.as_mut_ptr().cast(), v);
+        lanes.iter().sum::<u64>() as u32
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn masked_hamming_avx2(
+        code_a: &[u64],
+        mask_a: &[u64],
+        code_b: &[u64],
+        mask_b: &[u64],
+    ) -> (u32, u32) {
+        let body = code_a.len() / AVX2_LANES * AVX2_LANES;
+        let mut distance = _mm256_setzero_si256();
+        let mut overlap = _mm256_setzero_si256();
+        for i in (0..body).step_by(AVX2_LANES) {
+            let ca = _mm256_loadu_si256(code_a.as_ptr().add(i).cast());
+            let ma = _mm256_loadu_si256(mask_a.as_ptr().add(i).cast());
+            let cb = _mm256_loadu_si256(code_b.as_ptr().add(i).cast());
+            let mb = _mm256_loadu_si256(mask_b.as_ptr().add(i).cast());
+            let mask = _mm256_and_si256(ma, mb);
+            let diff = _mm256_and_si256(_mm256_xor_si256(ca, cb), mask);
+            overlap = _mm256_add_epi64(overlap, popcount_avx2(mask));
+            distance = _mm256_add_epi64(distance, popcount_avx2(diff));
+        }
+        let (tail_distance, tail_overlap) = masked_hamming_scalar(
+            &code_a[body..],
+            &mask_a[body..],
+            &code_b[body..],
+            &mask_b[body..],
+        );
+        (
+            sum_lanes_avx2(distance) + tail_distance,
+            sum_lanes_avx2(overlap) + tail_overlap,
+        )
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn count_ones_avx2(words: &[u64]) -> u32 {
+        let body = words.len() / AVX2_LANES * AVX2_LANES;
+        let mut sum = _mm256_setzero_si256();
+        for i in (0..body).step_by(AVX2_LANES) {
+            let v = _mm256_loadu_si256(words.as_ptr().add(i).cast());
+            sum = _mm256_add_epi64(sum, popcount_avx2(v));
+        }
+        sum_lanes_avx2(sum) + count_ones_scalar(&words[body..])
+    }
+}
+
+#[cfg(all(target_arch = "aarch64", feature = "std"))]
+mod neon {
+    use std::arch::aarch64::*;
+
+    use super::{count_ones_scalar, masked_hamming_scalar};
+
+    const LANES: usize = 2;
+
+    // per-byte counts widened pairwise up to one count per u64 lane
+    #[target_feature(enable = "neon")]
+    #[inline]
+    unsafe fn popcount(v: uint64x2_t) -> uint64x2_t {
+        vpaddlq_u32(vpaddlq_u16(vpaddlq_u8(vcntq_u8(vreinterpretq_u8_u64(v)))))
+    }
+
+    #[target_feature(enable = "neon")]
+    pub(super) unsafe fn masked_hamming_neon(
+        code_a: &[u64],
+        mask_a: &[u64],
+        code_b: &[u64],
+        mask_b: &[u64],
+    ) -> (u32, u32) {
+        let body = code_a.len() / LANES * LANES;
+        let mut distance = vdupq_n_u64(0);
+        let mut overlap = vdupq_n_u64(0);
+        for i in (0..body).step_by(LANES) {
+            let ca = vld1q_u64(code_a.as_ptr().add(i));
+            let ma = vld1q_u64(mask_a.as_ptr().add(i));
+            let cb = vld1q_u64(code_b.as_ptr().add(i));
+            let mb = vld1q_u64(mask_b.as_ptr().add(i));
+            let mask = vandq_u64(ma, mb);
+            let diff = vandq_u64(veorq_u64(ca, cb), mask);
+            overlap = vaddq_u64(overlap, popcount(mask));
+            distance = vaddq_u64(distance, popcount(diff));
+        }
+        let (tail_distance, tail_overlap) = masked_hamming_scalar(
+            &code_a[body..],
+            &mask_a[body..],
+            &code_b[body..],
+            &mask_b[body..],
+        );
+        (
+            vaddvq_u64(distance) as u32 + tail_distance,
+            vaddvq_u64(overlap) as u32 + tail_overlap,
+        )
+    }
+
+    #[target_feature(enable = "neon")]
+    pub(super) unsafe fn count_ones_neon(words: &[u64]) -> u32 {
+        let body = words.len() / LANES * LANES;
+        let mut sum = vdupq_n_u64(0);
+        for i in (0..body).step_by(LANES) {
+            sum = vaddq_u64(sum, popcount(vld1q_u64(words.as_ptr().add(i))));
+        }
+        vaddvq_u64(sum) as u32 + count_ones_scalar(&words[body..])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{vec, vec::Vec};
+
+    use super::*;
+
+    // splitmix64, so the words are the same on every run without needing `rand`
+    fn words(len: usize, seed: u64) -> Vec<u64> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+                let mut z = state;
+                z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+                z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+                z ^ (z >> 31)
+            })
+            .collect()
+    }
+
+    type MaskedHamming = unsafe fn(&[u64], &[u64], &[u64], &[u64]) -> (u32, u32);
+    type CountOnes = unsafe fn(&[u64]) -> u32;
+
+    // every kernel this CPU can run, the scalar ones included
+    #[allow(unused_mut)]
+    fn available() -> Vec<(&'static str, MaskedHamming, CountOnes)> {
+        let mut kernels: Vec<(&str, MaskedHamming, CountOnes)> =
+            vec![("scalar", masked_hamming_scalar, count_ones_scalar)];
+        #[cfg(all(target_arch = "x86_64", feature = "std"))]
+        {
+            if std::is_x86_feature_detected!("avx512f")
+                && std::is_x86_feature_detected!("avx512vpopcntdq")
+            {
+                kernels.push(("avx512", x86::masked_hamming_avx512, x86::count_ones_avx512));
+            }
+            if std::is_x86_feature_detected!("avx2") {
+                kernels.push(("avx2", x86::masked_hamming_avx2, x86::count_ones_avx2));
+            }
+        }
+        #[cfg(all(target_arch = "aarch64", feature = "std"))]
+        {
+            if std::arch::is_aarch64_feature_detected!("neon") {
+                kernels.push(("neon", neon::masked_hamming_neon, neon::count_ones_neon));
+            }
+        }
+        kernels
+    }
+
+    #[test]
+    fn kernels_match_scalar() {
+        // lengths around and between the 2, 4 and 8 lanes of the kernels
+        for len in 0..=21 {
+            let [code_a, mask_a, code_b, mask_b] = [1, 2, 3, 4].map(|seed| words(len, seed));
+            let expected = masked_hamming_scalar(&code_a, &mask_a, &code_b, &mask_b);
+            let ones = count_ones_scalar(&code_a);
+            for (name, masked_hamming, count_ones) in available() {
+                // SAFETY: only kernels whose CPU features are present are listed
+                unsafe {
+                    assert_eq!(
+                        masked_hamming(&code_a, &mask_a, &code_b, &mask_b),
+                        expected,
+                        "{name} at {len} words"
+                    );
+                    assert_eq!(count_ones(&code_a), ones, "{name} at {len} words");
+                }
+            }
+            assert_eq!(
+                super::masked_hamming(&code_a, &mask_a, &code_b, &mask_b),
+                expected
+            );
+            assert_eq!(super::count_ones(&code_a), ones);
+        }
+    }
+
+    #[test]
+    fn kernels_count_full_words() {
+        let ones = vec![u64::MAX; 13];
+        let zeros = vec![0; 13];
+        for (name, masked_hamming, count_ones) in available() {
+            // SAFETY: as above
+            unsafe {
+                assert_eq!(
+                    masked_hamming(&ones, &ones, &zeros, &ones),
+                    (13 * 64, 13 * 64),
+                    "{name}"
+                );
+                assert_eq!(count_ones(&ones), 13 * 64, "{name}");
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "different lengths")]
+    fn masked_hamming_rejects_short_masks() {
+        let words = words(8, 0);
+        masked_hamming(&words, &words[..7], &words, &words);
+    }
+}
@@ -0,0 +1,104 @@
+use std::sync::Arc;
+
+use crate::{
+    index::{IrisHnsw, SearchHit},
+    iris::IrisCode,
+};
+
+/// Read-only view of an [`IrisHnsw`] as of the moment it was taken. It shares the graph with the
+/// primary, which keeps accepting inserts, updates and removals; none of them show up in the
+/// snapshot's results. Taking one is O(1), searches skip the points added since while traversing.
+#[derive(Clone)]
+pub struct IndexSnapshot {
+    index: Arc<IrisHnsw>,
+    epoch: u64,
+    len: usize,
+}
+
+impl IndexSnapshot {
+    pub(crate) fn new(index: Arc<IrisHnsw>, epoch: u64, len: usize) -> Self {
+        Self { index, epoch, len }
+    }
+
+    pub fn search(&self, query: &IrisCode, k: usize) -> Vec<SearchHit> {
+        self.search_with_ef(query, k, self.index.ef_search())
+    }
+
+    pub fn search_with_ef(&self, query: &IrisCode, k: usize, ef: usize) -> Vec<SearchHit> {
+        if self.len == 0 {
+            return vec![];
+        }
+        self.index.search_at(query, k, ef, self.epoch)
+    }
+
+    /// Number of entries that were live when the snapshot was taken.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    fn codes(len: usize) -> Vec<IrisCode> {
+        let mut rng = StdRng::seed_from_u64(11);
+        (0..len).map(|_| IrisCode::random_rng(&mut rng)).collect()
+    }
+
+    fn ids(hits: Vec<SearchHit>) -> Vec<usize> {
+        hits.into_iter().map(|hit| hit.id).collect()
+    }
+
+    #[test]
+    fn snapshots_ignore_later_changes() {
+        let codes = codes(13);
+        let index = Arc::new(
+            IrisHnsw::builder()
+                .max_nb_connection(16)
+                .ef_construction(64)
+                .capacity(100)
+                .build(),
+        );
+        for (id, code) in codes[..10].iter().enumerate() {
+            index.insert(code, id).unwrap();
+        }
+        let snapshot = index.snapshot();
+        assert!(index.remove(3));
+        assert!(index.update(4, &codes[11]).unwrap());
+        index.insert(&codes[10], 10).unwrap();
+
+        assert_eq!(snapshot.len(), 10);
+        assert_eq!(ids(snapshot.search(&codes[3], 1)), [3]);
+        assert_eq!(ids(snapshot.search(&codes[4], 1)), [4]);
+        assert_ne!(ids(snapshot.search(&codes[10], 1)), [10]);
+        assert_ne!(ids(snapshot.search(&codes[11], 1)), [4]);
+        assert!(snapshot.search(&codes[3], 0).is_empty());
+
+        assert_ne!(ids(index.search(&codes[3], 1)), [3]);
+        assert_eq!(ids(index.search(&codes[11], 1)), [4]);
+        assert_eq!(ids(index.search(&codes[10], 1)), [10]);
+    }
+
+    #[test]
+    fn snapshots_outlive_removals() {
+        let codes = codes(2);
+        let index = Arc::new(IrisHnsw::builder().capacity(100).build());
+        let empty = index.snapshot();
+        index.insert(&codes[0], 0).unwrap();
+        let snapshot = index.snapshot();
+        assert!(index.remove(0));
+
+        assert!(empty.is_empty());
+        assert!(empty.search(&codes[0], 1).is_empty());
+        assert!(index.search(&codes[0], 1).is_empty());
+        assert_eq!(ids(snapshot.search(&codes[0], 1)), [0]);
+        assert_eq!(snapshot.search(&codes[1], 1)[0].id, 0);
+    }
+}
@@ -0,0 +1,370 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, ensure, Context};
+use csv::StringRecord;
+use hnsw_hamming::{
+    dataset::{DatasetReader, DatasetWriter},
+    IrisCode, IrisHnsw, Metadata,
+};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+pub const INDEX_BASENAME: &str = "hnsw";
+pub const QUERIES_FILE: &str = "queries.bin";
+pub const CONFIG_FILE: &str = "config.toml";
+
+const CHECKPOINT_DIR: &str = "checkpoint";
+const CHECKPOINT_PARTIAL_DIR: &str = "checkpoint.partial";
+const CHECKPOINT_STATE_FILE: &str = "state.toml";
+
+/// Writes `records` as a dataset with ids, see `DatasetWriter`.
+pub fn write_records(path: &Path, records: &[(IrisCode, usize)]) -> io::Result<()> {
+    let mut writer = DatasetWriter::new(File::create(path)?, records.len() as u64, true)?;
+    for (code, idx) in records {
+        writer.write(code, Some(*idx))?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+/// Reads a dataset, see `DatasetReader`.
+pub fn read_records(path: &Path) -> io::Result<Vec<(IrisCode, usize)>> {
+    DatasetReader::new(File::open(path)?)?.collect()
+}
+
+/// A template with its id and metadata, as exchanged with other tools.
+pub struct TemplateRecord {
+    pub id: usize,
+    pub code: IrisCode,
+    pub meta: Metadata,
+}
+
+// one line of an NDJSON file, with code and mask encoded as by open-iris
+#[derive(Serialize, Deserialize)]
+struct NdjsonLine {
+    id: usize,
+    code_b64: String,
+    mask_b64: String,
+    #[serde(default, skip_serializing_if = "Metadata::is_empty")]
+    meta: Metadata,
+}
+
+/// Reads one JSON object per line, skipping blank lines.
+pub fn read_ndjson(reader: impl BufRead) -> anyhow::Result<Vec<TemplateRecord>> {
+    let mut records = vec![];
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        records.push(parse_ndjson_line(&line).with_context(|| format!("line {}", i + 1))?);
+    }
+    Ok(records)
+}
+
+pub fn parse_ndjson_line(line: &str) -> anyhow::Result<TemplateRecord> {
+    let line: NdjsonLine = serde_json::from_str(line)?;
+    Ok(TemplateRecord {
+        id: line.id,
+        code: IrisCode::from_base64(&line.code_b64, &line.mask_b64)?,
+        meta: line.meta,
+    })
+}
+
+/// Reads `id`, `code` and `mask` columns, the templates hex encoded as by `IrisCodeArray`'s
+/// `LowerHex`; any other non-empty column goes into the metadata. Rows are parsed as they are
+/// read, and every bad one is logged with its line before the whole file is rejected.
+pub fn read_csv(reader: impl Read) -> anyhow::Result<Vec<TemplateRecord>> {
+    let mut reader = csv::Reader::from_reader(reader);
+    let headers = reader.headers()?.clone();
+    let column = |name: &str| {
+        headers
+            .iter()
+            .position(|header| header == name)
+            .with_context(|| format!("missing column {name:?}"))
+    };
+    let columns = [column("id")?, column("code")?, column("mask")?];
+
+    let mut records = vec![];
+    let mut invalid = 0;
+    for row in reader.records() {
+        let (line, parsed) = match row {
+            Ok(row) => (
+                row.position().map(|position| position.line()),
+                parse_csv_row(&headers, columns, &row),
+            ),
+            Err(err) => (
+                err.position().map(|position| position.line()),
+                Err(err.into()),
+            ),
+        };
+        match parsed {
+            Ok(record) => records.push(record),
+            Err(err) => {
+                invalid += 1;
+                warn!(line, "invalid row: {err:#}");
+            }
+        }
+    }
+    if invalid > 0 {
+        bail!("{invalid} invalid rows");
+    }
+    Ok(records)
+}
+
+fn parse_csv_row(
+    headers: &StringRecord,
+    [id, code, mask]: [usize; 3],
+    row: &StringRecord,
+) -> anyhow::Result<TemplateRecord> {
+    let meta = headers
+        .iter()
+        .zip(row)
+        .enumerate()
+        .filter(|&(i, (_, value))| ![id, code, mask].contains(&i) && !value.is_empty())
+        .map(|(_, (header, value))| (header.to_string(), value.to_string()))
+        .collect();
+    Ok(TemplateRecord {
+        id: row[id]
+            .parse()
+            .with_context(|| format!("invalid id {:?}", &row[id]))?,
+        code: IrisCode {
+            code: row[code].parse().context("invalid code")?,
+            mask: row[mask].parse().context("invalid mask")?,
+        },
+        meta,
+    })
+}
+
+pub fn write_ndjson(mut writer: impl Write, records: &[TemplateRecord]) -> anyhow::Result<()> {
+    for record in records {
+        let (code_b64, mask_b64) = record.code.to_base64();
+        let line = NdjsonLine {
+            id: record.id,
+            code_b64,
+            mask_b64,
+            meta: record.meta.clone(),
+        };
+        serde_json::to_writer(&mut writer, &line)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CheckpointState {
+    pub seed: u64,
+    pub n_points: usize,
+    pub inserted: usize,
+    pub basename: String,
+}
+
+/// Dumps a partially built index next to its state. The state file is written last, so any
+/// checkpoint directory containing one is complete.
+pub fn write_checkpoint(
+    index_dir: &Path,
+    index: &IrisHnsw,
+    seed: u64,
+    n_points: usize,
+    inserted: usize,
+) -> anyhow::Result<()> {
+    let partial = index_dir.join(CHECKPOINT_PARTIAL_DIR);
+    let dir = index_dir.join(CHECKPOINT_DIR);
+    if partial.join(CHECKPOINT_STATE_FILE).exists() {
+        // a complete partial checkpoint is the newest one, possibly the one resumed from, so it
+        // takes the regular one's place before a new one is started
+        if dir.exists() {
+            fs::remove_dir_all(&dir)?;
+        }
+        fs::rename(&partial, &dir)?;
+    } else if partial.exists() {
+        fs::remove_dir_all(&partial)?;
+    }
+    fs::create_dir_all(&partial)?;
+    let basename = index.dump(&partial, INDEX_BASENAME)?;
+    let state = CheckpointState {
+        seed,
+        n_points,
+        inserted,
+        basename,
+    };
+    fs::write(
+        partial.join(CHECKPOINT_STATE_FILE),
+        toml::to_string(&state)?,
+    )?;
+
+    if dir.exists() {
+        fs::remove_dir_all(&dir)?;
+    }
+    fs::rename(&partial, &dir)?;
+    Ok(())
+}
+
+/// Returns the directory and state of the newest complete checkpoint, if any.
+pub fn find_checkpoint(index_dir: &Path) -> anyhow::Result<Option<(PathBuf, CheckpointState)>> {
+    // a complete partial checkpoint is newer than the regular one it was about to replace
+    for name in [CHECKPOINT_PARTIAL_DIR, CHECKPOINT_DIR] {
+        let dir = index_dir.join(name);
+        let state_path = dir.join(CHECKPOINT_STATE_FILE);
+        if state_path.exists() {
+            let state = toml::from_str(&fs::read_to_string(&state_path)?)
+                .with_context(|| format!("parsing {}", state_path.display()))?;
+            return Ok(Some((dir, state)));
+        }
+    }
+    Ok(None)
+}
+
+pub fn remove_checkpoints(index_dir: &Path) -> io::Result<()> {
+    for name in [CHECKPOINT_PARTIAL_DIR, CHECKPOINT_DIR] {
+        let dir = index_dir.join(name);
+        if dir.exists() {
+            fs::remove_dir_all(dir)?;
+        }
+    }
+    Ok(())
+}
+
+/// Exact neighbours of a query set, written by `groundtruth`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GroundTruth {
+    /// Seed of the noise the queries were perturbed with.
+    pub seed: u64,
+    pub k: usize,
+    pub threshold: f64,
+    pub queries: Vec<QueryTruth>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct QueryTruth {
+    pub query_idx: usize,
+    /// The `k` nearest ids, followed by any further ones below `threshold`, nearest first.
+    pub neighbours: Vec<usize>,
+    pub distances: Vec<f32>,
+    /// Number of `neighbours` below `threshold`.
+    pub matches: usize,
+}
+
+pub fn write_groundtruth(path: &Path, truth: &GroundTruth) -> anyhow::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    serde_json::to_writer(&mut writer, truth)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Reads a file written by [`write_groundtruth`], rejecting queries whose lists don't add up.
+pub fn read_groundtruth(path: &Path) -> anyhow::Result<GroundTruth> {
+    let reader = BufReader::new(File::open(path)?);
+    let truth: GroundTruth =
+        serde_json::from_reader(reader).with_context(|| format!("parsing {}", path.display()))?;
+    for query in &truth.queries {
+        ensure!(
+            query.distances.len() == query.neighbours.len(),
+            "query {} has {} neighbours but {} distances",
+            query.query_idx,
+            query.neighbours.len(),
+            query.distances.len()
+        );
+        ensure!(
+            query.matches <= query.neighbours.len(),
+            "query {} has {} matches but only {} neighbours",
+            query.query_idx,
+            query.matches,
+            query.neighbours.len()
+        );
+    }
+    Ok(truth)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    fn codes(len: usize) -> Vec<IrisCode> {
+        let mut rng = StdRng::seed_from_u64(0);
+        (0..len).map(|_| IrisCode::random_rng(&mut rng)).collect()
+    }
+
+    #[test]
+    fn ndjson_round_trips() {
+        let records: Vec<_> = codes(3)
+            .into_iter()
+            .enumerate()
+            .map(|(i, code)| TemplateRecord {
+                id: 10 * i,
+                code,
+                meta: Metadata::from([("eye".to_string(), format!("{}", i % 2))]),
+            })
+            .collect();
+        let mut bytes = vec![];
+        write_ndjson(&mut bytes, &records).unwrap();
+        // blank lines are skipped
+        bytes.extend_from_slice(b"\n  \n");
+
+        let read = read_ndjson(bytes.as_slice()).unwrap();
+        assert_eq!(read.len(), records.len());
+        for (read, record) in read.iter().zip(&records) {
+            assert_eq!(read.id, record.id);
+            assert_eq!(read.code, record.code);
+            assert_eq!(read.meta, record.meta);
+        }
+    }
+
+    #[test]
+    fn ndjson_errors_name_the_line() {
+        let (code, mask) = codes(1)[0].to_base64();
+        let valid = format!(r#"{{"id":1,"code_b64":"{code}","mask_b64":"{mask}"}}"#);
+        let record = parse_ndjson_line(&valid).unwrap();
+        assert_eq!(record.id, 1);
+        assert!(record.meta.is_empty());
+
+        let input =
+            format!("{valid}\n\n{{\"id\":2,\"code_b64\":\"AAAA\",\"mask_b64\":\"{mask}\"}}\n");
+        let err = read_ndjson(input.as_bytes()).err().unwrap();
+        assert_eq!(err.to_string(), "line 3");
+        assert!(read_ndjson(&b"{\"id\":1}\n"[..]).is_err());
+    }
+
+    #[test]
+    fn csv_parses_templates_and_metadata() {
+        let codes = codes(2);
+        let input = format!(
+            "id,code,eye,mask,site\n7,{:#x},left,{:x},\n9,{:x},,{:#x},berlin\n",
+            codes[0].code, codes[0].mask, codes[1].code, codes[1].mask
+        );
+        let records = read_csv(input.as_bytes()).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!((records[0].id, &records[0].code), (7, &codes[0]));
+        assert_eq!((records[1].id, &records[1].code), (9, &codes[1]));
+        // empty columns are left out of the metadata
+        assert_eq!(
+            records[0].meta,
+            Metadata::from([("eye".to_string(), "left".to_string())])
+        );
+        assert_eq!(
+            records[1].meta,
+            Metadata::from([("site".to_string(), "berlin".to_string())])
+        );
+    }
+
+    #[test]
+    fn csv_rejects_bad_rows_and_headers() {
+        let code = &codes(1)[0];
+        let (hex_code, hex_mask) = (format!("{:x}", code.code), format!("{:x}", code.mask));
+        let input = format!(
+            "id,code,mask\n1,{hex_code},{hex_mask}\nx,{hex_code},{hex_mask}\n2,{hex_code},00\n"
+        );
+        let err = read_csv(input.as_bytes()).err().unwrap();
+        assert_eq!(err.to_string(), "2 invalid rows");
+
+        let err = read_csv("id,code\n".as_bytes()).err().unwrap();
+        assert_eq!(err.to_string(), "missing column \"mask\"");
+    }
+}
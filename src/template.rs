@@ -0,0 +1,193 @@
+use crate::{
+    error::IrisError,
+    iris::{IrisCodeArray, DEFAULT_IRIS_CODE_WORDS},
+};
+
+/// Arrangement of the bits in a flat code: `rows` radial bands of `columns` angular positions,
+/// with `filters` bits per cell. Bits are stored row-major with the filter bits of a cell next to
+/// each other, so shifting whole columns keeps the filter responses of a position together.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TemplateLayout {
+    pub rows: usize,
+    pub columns: usize,
+    pub filters: usize,
+}
+
+/// Layout of the default code width, i.e. 2 x 16 x 4 for 128-bit toy codes.
+#[cfg(not(feature = "full-codes"))]
+pub const DEFAULT_LAYOUT: TemplateLayout = TemplateLayout::new(2, 16, 4);
+/// Layout of the default code width, i.e. 16 x 200 x 4 for 12,800-bit production codes.
+#[cfg(feature = "full-codes")]
+pub const DEFAULT_LAYOUT: TemplateLayout = TemplateLayout::new(16, 200, 4);
+
+impl Default for TemplateLayout {
+    fn default() -> Self {
+        DEFAULT_LAYOUT
+    }
+}
+
+impl TemplateLayout {
+    pub const fn new(rows: usize, columns: usize, filters: usize) -> Self {
+        Self {
+            rows,
+            columns,
+            filters,
+        }
+    }
+
+    pub const fn bits(&self) -> usize {
+        self.rows * self.columns * self.filters
+    }
+
+    /// Position of a bit in the flat code.
+    #[inline]
+    pub const fn bit_index(&self, row: usize, column: usize, filter: usize) -> usize {
+        debug_assert!(row < self.rows && column < self.columns && filter < self.filters);
+        (row * self.columns + column) * self.filters + filter
+    }
+
+    /// Inverse of [`TemplateLayout::bit_index`], as `(row, column, filter)`.
+    #[inline]
+    pub const fn position(&self, index: usize) -> (usize, usize, usize) {
+        let cell = index / self.filters;
+        (
+            cell / self.columns,
+            cell % self.columns,
+            index % self.filters,
+        )
+    }
+}
+
+/// A flat code viewed through its angular/radial layout.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IrisTemplate2D<const WORDS: usize = DEFAULT_IRIS_CODE_WORDS> {
+    code: IrisCodeArray<WORDS>,
+    layout: TemplateLayout,
+}
+
+impl IrisTemplate2D {
+    /// Views a code of the default width through [`DEFAULT_LAYOUT`].
+    pub fn from_flat(code: IrisCodeArray) -> Self {
+        Self {
+            code,
+            layout: DEFAULT_LAYOUT,
+        }
+    }
+}
+
+impl<const WORDS: usize> IrisTemplate2D<WORDS> {
+    /// Fails if the layout doesn't cover exactly the bits of the code.
+    pub fn new(code: IrisCodeArray<WORDS>, layout: TemplateLayout) -> Result<Self, IrisError> {
+        if layout.bits() != IrisCodeArray::<WORDS>::IRIS_CODE_SIZE {
+            return Err(IrisError::LayoutMismatch {
+                layout_bits: layout.bits(),
+                code_bits: IrisCodeArray::<WORDS>::IRIS_CODE_SIZE,
+            });
+        }
+        Ok(Self { code, layout })
+    }
+
+    pub fn layout(&self) -> TemplateLayout {
+        self.layout
+    }
+
+    pub fn rows(&self) -> usize {
+        self.layout.rows
+    }
+
+    pub fn columns(&self) -> usize {
+        self.layout.columns
+    }
+
+    pub fn filters(&self) -> usize {
+        self.layout.filters
+    }
+
+    #[inline]
+    pub fn get(&self, row: usize, column: usize, filter: usize) -> bool {
+        self.code
+            .get_bit(self.layout.bit_index(row, column, filter))
+    }
+
+    #[inline]
+    pub fn set(&mut self, row: usize, column: usize, filter: usize, val: bool) {
+        self.code
+            .set_bit(self.layout.bit_index(row, column, filter), val);
+    }
+
+    /// Rolls every row by `shift` columns, wrapping around the angular dimension. Positive shifts
+    /// move bits towards higher columns. The filter bits of a cell move together, so real and
+    /// imaginary responses stay paired.
+    pub fn rotate_columns(&mut self, shift: isize) {
+        self.code = self.code.rotated(&self.layout, shift);
+    }
+
+    /// Copy of the template rotated as in [`IrisTemplate2D::rotate_columns`].
+    pub fn rotated_columns(&self, shift: isize) -> Self {
+        let mut res = *self;
+        res.rotate_columns(shift);
+        res
+    }
+
+    pub fn as_flat(&self) -> &IrisCodeArray<WORDS> {
+        &self.code
+    }
+
+    pub fn into_flat(self) -> IrisCodeArray<WORDS> {
+        self.code
+    }
+}
+
+impl From<IrisCodeArray> for IrisTemplate2D {
+    fn from(code: IrisCodeArray) -> Self {
+        Self::from_flat(code)
+    }
+}
+
+impl<const WORDS: usize> From<IrisTemplate2D<WORDS>> for IrisCodeArray<WORDS> {
+    fn from(template: IrisTemplate2D<WORDS>) -> Self {
+        template.into_flat()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LAYOUT: TemplateLayout = TemplateLayout::new(2, 8, 4);
+
+    fn template() -> IrisTemplate2D<1> {
+        IrisTemplate2D::new(IrisCodeArray::new([0x0123_4567_89ab_cdef]), LAYOUT).unwrap()
+    }
+
+    #[test]
+    fn rotation_moves_whole_cells_within_rows() {
+        let template = template();
+        for shift in [-9, -3, -1, 0, 1, 5, 8, 11] {
+            let rotated = template.rotated_columns(shift);
+            for row in 0..LAYOUT.rows {
+                for column in 0..LAYOUT.columns {
+                    let target = (column as isize + shift).rem_euclid(8) as usize;
+                    for filter in 0..LAYOUT.filters {
+                        assert_eq!(
+                            rotated.get(row, target, filter),
+                            template.get(row, column, filter),
+                            "shift {shift} at ({row}, {column}, {filter})"
+                        );
+                    }
+                }
+            }
+            assert_eq!(rotated.rotated_columns(-shift), template);
+        }
+        assert_eq!(template.rotated_columns(8), template);
+    }
+
+    #[test]
+    fn layout_positions_round_trip() {
+        for index in 0..LAYOUT.bits() {
+            let (row, column, filter) = LAYOUT.position(index);
+            assert_eq!(LAYOUT.bit_index(row, column, filter), index);
+        }
+        assert!(IrisTemplate2D::new(IrisCodeArray::<2>::ZERO, LAYOUT).is_err());
+    }
+}
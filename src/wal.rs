@@ -0,0 +1,183 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufReader, Read, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+use anyhow::{ensure, Context};
+use hnsw_hamming::{IrisCode, IrisCodeArray};
+use tracing::warn;
+
+const WAL_MAGIC: [u8; 4] = *b"IRWL";
+const WAL_FORMAT_VERSION: u8 = 1;
+// magic, version, three reserved bytes, code bits u32 LE
+const HEADER_LEN: usize = 12;
+
+// id u64 LE, code and mask raw, FNV-1a of the preceding bytes u64 LE
+fn record_len() -> usize {
+    8 + 2 * <IrisCodeArray>::IRIS_CODE_SIZE_BYTES + 8
+}
+
+fn checksum(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+fn header() -> [u8; HEADER_LEN] {
+    let mut bytes = [0; HEADER_LEN];
+    bytes[..4].copy_from_slice(&WAL_MAGIC);
+    bytes[4] = WAL_FORMAT_VERSION;
+    bytes[8..].copy_from_slice(&(<IrisCode>::IRIS_CODE_SIZE as u32).to_le_bytes());
+    bytes
+}
+
+/// Append-only log of inserts, each synced to disk before it is applied to the index, so that
+/// none is lost with the process.
+pub struct Wal {
+    file: Mutex<File>,
+}
+
+impl Wal {
+    /// Opens the log at `path`, creating it if missing, and returns the inserts in it in the order
+    /// they were logged. A record cut short or garbled by a crash while it was being written is
+    /// dropped along with everything after it.
+    pub fn open(path: &Path) -> anyhow::Result<(Self, Vec<(usize, IrisCode)>)> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)
+            .with_context(|| format!("opening {}", path.display()))?;
+        let len = file.metadata()?.len();
+        if len < HEADER_LEN as u64 {
+            file.set_len(0)?;
+            file.write_all(&header())?;
+            file.sync_all()?;
+            return Ok((Self::new(file), vec![]));
+        }
+
+        let mut reader = BufReader::new(&file);
+        let mut bytes = [0; HEADER_LEN];
+        reader.read_exact(&mut bytes)?;
+        ensure!(
+            bytes[..4] == WAL_MAGIC,
+            "{} is not a write-ahead log",
+            path.display()
+        );
+        ensure!(
+            bytes[4] == WAL_FORMAT_VERSION,
+            "unsupported write-ahead log version {}",
+            bytes[4]
+        );
+        ensure!(
+            bytes[8..] == header()[8..],
+            "{} logs {}-bit codes, expected {}",
+            path.display(),
+            u32::from_le_bytes(bytes[8..].try_into().unwrap()),
+            <IrisCode>::IRIS_CODE_SIZE
+        );
+
+        let code_bytes = <IrisCodeArray>::IRIS_CODE_SIZE_BYTES;
+        let mut entries = vec![];
+        let mut record = vec![0; record_len()];
+        loop {
+            match reader.read_exact(&mut record) {
+                Ok(()) => {}
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(err) => return Err(err.into()),
+            }
+            let (data, sum) = record.split_at(record_len() - 8);
+            if checksum(data) != u64::from_le_bytes(sum.try_into().unwrap()) {
+                break;
+            }
+            let id = u64::from_le_bytes(data[..8].try_into().unwrap()) as usize;
+            let mut code = IrisCode::default();
+            code.code
+                .as_raw_mut_slice()
+                .copy_from_slice(&data[8..8 + code_bytes]);
+            code.mask
+                .as_raw_mut_slice()
+                .copy_from_slice(&data[8 + code_bytes..]);
+            entries.push((id, code));
+        }
+
+        let valid = (HEADER_LEN + entries.len() * record_len()) as u64;
+        if valid < len {
+            warn!(
+                path = %path.display(),
+                dropped_bytes = len - valid,
+                "dropping the torn tail of the write-ahead log"
+            );
+            file.set_len(valid)?;
+            file.sync_all()?;
+        }
+        Ok((Self::new(file), entries))
+    }
+
+    fn new(file: File) -> Self {
+        Self {
+            file: Mutex::new(file),
+        }
+    }
+
+    /// Logs `entries` and waits until they are on disk.
+    pub fn append(&self, entries: &[(usize, &IrisCode)]) -> io::Result<()> {
+        let mut bytes = Vec::with_capacity(entries.len() * record_len());
+        for (id, code) in entries {
+            let start = bytes.len();
+            bytes.extend_from_slice(&(*id as u64).to_le_bytes());
+            bytes.extend_from_slice(code.code.as_raw_slice());
+            bytes.extend_from_slice(code.mask.as_raw_slice());
+            let sum = checksum(&bytes[start..]);
+            bytes.extend_from_slice(&sum.to_le_bytes());
+        }
+        let mut file = self.file.lock().unwrap();
+        file.write_all(&bytes)?;
+        file.sync_data()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn replay_drops_a_truncated_tail() {
+        let path = std::env::temp_dir().join(format!("wal-truncated-{}.log", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut rng = StdRng::seed_from_u64(0);
+        let codes: Vec<IrisCode> = (0..3).map(|_| IrisCode::random_rng(&mut rng)).collect();
+
+        let (wal, replayed) = Wal::open(&path).unwrap();
+        assert!(replayed.is_empty());
+        let entries: Vec<_> = codes.iter().enumerate().collect();
+        wal.append(&entries).unwrap();
+        drop(wal);
+        // a crash halfway through writing the last record
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len((HEADER_LEN + 2 * record_len() + record_len() / 2) as u64)
+            .unwrap();
+        drop(file);
+
+        let (wal, replayed) = Wal::open(&path).unwrap();
+        let expected: Vec<_> = codes[..2].iter().cloned().enumerate().collect();
+        assert_eq!(replayed, expected);
+        assert_eq!(
+            fs::metadata(&path).unwrap().len(),
+            (HEADER_LEN + 2 * record_len()) as u64
+        );
+        // records appended after the torn one was cut off are replayed
+        wal.append(&[(2, &codes[2])]).unwrap();
+        drop(wal);
+        let (_, replayed) = Wal::open(&path).unwrap();
+        let expected: Vec<_> = codes.iter().cloned().enumerate().collect();
+        assert_eq!(replayed, expected);
+        fs::remove_file(&path).unwrap();
+    }
+}